@@ -1,19 +0,0 @@
-//! Autopilot for sailboats
-
-use serde::{Deserialize, Serialize};
-
-pub struct Autopilot {
-	// TODO
-}
-
-/// Save state for simulation file
-#[derive(Serialize, Deserialize)]
-pub struct AutopilotSave {
-	// TODO
-}
-
-/// Autopilot input from client
-#[derive(Serialize, Deserialize)]
-pub struct AutopilotInputs {
-	// TODO
-}
\ No newline at end of file
@@ -0,0 +1,482 @@
+//! Autopilot for sailboats
+
+use crate::{prelude::*, simulation::boat::SailStatic};
+use serde::{Deserialize, Serialize};
+
+pub mod route;
+
+// CONSTS
+/// Limit on the integral term so that it can't wind up while the rudder is saturated, in radians*seconds
+const MAX_INTEGRAL: Float = 1.0;
+/// Distance from a waypoint where it is considered reached
+pub const ARRIVAL_RADIUS: Float = 5.0;
+/// Step between sheeting angles tried by the automatic trim, degrees
+const TRIM_RESOLUTION: Float = 1.0;
+/// Largest sheeting angle tried by the automatic trim, degrees
+const MAX_TRIM_SHEETING_ANGLE: Float = 90.0;
+
+/// What the autopilot is trying to hold
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum AutopilotMode {
+	/// Compass heading, degrees CCW from +X
+	Heading(Float),
+	/// Apparent wind angle, degrees from the bow to where the wind is coming from, + = port side
+	WindAngle(Float),
+	/// Sail to a point as fast as possible, using the boat type's optimal upwind and downwind angles and tacking/gybing on the laylines
+	Vmg(V2),
+	/// Follow a path (usually from `route::plan_route()`), sailing each leg like `Vmg`
+	Route {
+		path: Vec<V2>,
+		/// Index of the point currently being sailed to
+		next: usize
+	}
+}
+
+impl Default for AutopilotMode {
+	fn default() -> Self {
+		Self::Heading(0.0)
+	}
+}
+
+/// Tunable gains for the rudder PID controller, the error is in radians and the output is a rudder angle in radians
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PidGains {
+	pub p: Float,
+	pub i: Float,
+	pub d: Float
+}
+
+impl Default for PidGains {
+	fn default() -> Self {
+		Self {
+			p: 1.0,
+			i: 0.1,
+			d: 0.5
+		}
+	}
+}
+
+pub struct Autopilot {
+	state: AutopilotSave
+}
+
+impl Autopilot {
+	pub fn from_save(state: AutopilotSave) -> Self {
+		Self {
+			state
+		}
+	}
+	pub fn save(&self) -> AutopilotSave {
+		self.state.clone()
+	}
+	pub fn mode(&self) -> &AutopilotMode {
+		&self.state.mode
+	}
+	pub fn set_mode(&mut self, mode: AutopilotMode) {
+		if mode != self.state.mode {
+			self.state.mode = mode;
+			self.reset();
+		}
+	}
+	/// Applies input from the client, changing the mode resets the controller
+	/// `waypoints_opt` is ignored here because planning needs the map, see `route::plan_route()`
+	pub fn handle_input(&mut self, inputs: &AutopilotInputs) {
+		if let Some(mode) = &inputs.mode_opt {
+			self.set_mode(mode.clone());
+		}
+		if let Some(gains) = &inputs.gains_opt {
+			self.state.gains = gains.clone();
+		}
+		if let Some(auto_trim) = inputs.auto_trim_opt {
+			self.state.auto_trim = auto_trim;
+		}
+	}
+	pub fn auto_trim(&self) -> bool {
+		self.state.auto_trim
+	}
+	/// Clears the integral and derivative history, should be called when the autopilot is re-enabled
+	pub fn reset(&mut self) {
+		self.state.integral = 0.0;
+		self.state.prev_error_opt = None;
+	}
+	/// Heading change needed, radians, + = CCW
+	pub fn heading_error(&self, boat: &BoatSaveState, boat_type: &BoatType, true_wind: &Wind) -> Float {
+		match &self.state.mode {
+			AutopilotMode::Heading(target) => wrap_angle(target.to_radians() - boat.heading()),
+			// Turning CCW moves the bow towards wind coming from the port side, which decreases the angle
+			AutopilotMode::WindAngle(target) => wrap_angle(boat.apparent_wind_angle(true_wind) - target.to_radians()),
+			AutopilotMode::Vmg(target) => match (target - boat.pos.translation.vector).magnitude() < ARRIVAL_RADIUS {
+				true => 0.0,
+				false => wrap_angle(vmg_heading(boat, boat_type, true_wind, target) - boat.heading())
+			},
+			AutopilotMode::Route{path, next} => match path.get(*next) {
+				Some(target) => wrap_angle(vmg_heading(boat, boat_type, true_wind, target) - boat.heading()),
+				// Finished
+				None => 0.0
+			}
+		}
+	}
+	/// Moves on to the next route point once the current one has been reached
+	fn update_route(&mut self, boat: &BoatSaveState) {
+		if let AutopilotMode::Route{path, next} = &mut self.state.mode {
+			if let Some(target) = path.get(*next) {
+				if (target - boat.pos.translation.vector).magnitude() < ARRIVAL_RADIUS {
+					*next += 1;
+				}
+			}
+		}
+	}
+	/// Runs the controller for one step, `max_rudder_movement` is in degrees/second
+	pub fn update(&mut self, dt: Float, boat: &BoatSaveState, boat_type: &BoatType, true_wind: &Wind, max_rudder_movement: Float) -> BoatInputs {
+		self.update_route(boat);
+		let error = self.heading_error(boat, boat_type, true_wind);
+		self.state.integral = (self.state.integral + error * dt).clamp(-MAX_INTEGRAL, MAX_INTEGRAL);
+		let derivative = match (self.state.prev_error_opt, dt > 0.0) {
+			(Some(prev_error), true) => wrap_angle(error - prev_error) / dt,
+			_ => 0.0
+		};
+		self.state.prev_error_opt = Some(error);
+		let gains = &self.state.gains;
+		// Positive rudder angle turns the boat CW, so the output is negated
+		let max_rudder_angle = self.state.max_rudder_angle.to_radians();
+		let target_rudder = (-(gains.p * error + gains.i * self.state.integral + gains.d * derivative)).clamp(-max_rudder_angle, max_rudder_angle);
+		let max_change = max_rudder_movement.to_radians() * dt;
+		let rudder = boat.rudder_angle + (target_rudder - boat.rudder_angle).clamp(-max_change, max_change);
+		BoatInputs {
+			rudder_control: Some(rudder),
+			sheeting_angles: Vec::new()
+		}
+	}
+}
+
+/// Heading (radians CCW from +X) to steer to make the best progress towards `target`
+/// If the target is inside the upwind or downwind no-go cone the boat sails at the optimal angle on its current tack until the target is on the other tack's layline
+pub fn vmg_heading(boat: &BoatSaveState, boat_type: &BoatType, true_wind: &Wind, target: &V2) -> Float {
+	let to_target = target - boat.pos.translation.vector;
+	let bearing = to_target.y.atan2(to_target.x);
+	if true_wind.magnitude() < EPSILON {
+		return bearing;
+	}
+	let wind_from = (-true_wind.y).atan2(-true_wind.x);
+	// Where the target is relative to the wind, 0 = dead upwind, + = CCW
+	let target_angle = wrap_angle(bearing - wind_from);
+	let upwind_heading_angle = boat_type.upwind_max_wind_angle.to_radians();
+	// The boat slips sideways, so the course it actually makes over the water is further off the wind than its heading
+	let upwind_course_angle = boat_type.upwind_max_total_leeway.to_radians();
+	let downwind_angle = boat_type.downwind_max_wind_angle.to_radians();
+	// Wind coming from the port side, heading is CW from the wind
+	let on_port_tack = boat.apparent_wind_angle(true_wind) >= 0.0;
+	// Inside either cone the boat stays on its current tack, which brings the target towards the other tack's layline
+	// Once the target is past the layline it is outside the cone and is sailed to directly, which is what makes the boat tack/gybe
+	if target_angle.abs() < upwind_course_angle {
+		match on_port_tack {
+			true => wind_from - upwind_heading_angle,
+			false => wind_from + upwind_heading_angle
+		}
+	}
+	else if target_angle.abs() > downwind_angle {
+		match on_port_tack {
+			true => wind_from - downwind_angle,
+			false => wind_from + downwind_angle
+		}
+	}
+	else {
+		// Reaching, target can be sailed to directly
+		bearing
+	}
+}
+
+/// Inputs which set every sail to its `optimal_sheeting_angle()`, used for automatic trim whether or not the autopilot is steering
+/// Heel is not modeled yet, so this doesn't try to reduce it
+pub fn optimal_sheeting_angles(boat: &BoatSaveState, boat_type: &BoatType, true_wind: &Wind) -> BoatInputs {
+	let apparent_wind = boat.apparent_wind_local(true_wind);
+	BoatInputs {
+		rudder_control: None,
+		sheeting_angles: boat_type.sails.iter().map(|(ref_, sail)| (
+			GenericQuery::id(ref_.id),
+			optimal_sheeting_angle(sail, &apparent_wind)
+		)).collect()
+	}
+}
+
+/// Sheeting angle (degrees) which gives the most forward force from `sail`, `apparent_wind` is in the boat's frame
+pub fn optimal_sheeting_angle(sail: &SailStatic, apparent_wind: &Wind) -> Float {
+	let mut best_angle = MAX_TRIM_SHEETING_ANGLE;
+	let mut best_drive = Float::NEG_INFINITY;
+	let mut sheeting_angle = 0.0;
+	while sheeting_angle <= MAX_TRIM_SHEETING_ANGLE {
+		let drive = sail.force(sail.angle_for_sheeting(sheeting_angle, apparent_wind), apparent_wind).x;
+		// Prefer the loosest sheet when drive is equal, ex: when the wind is from straight ahead
+		if drive >= best_drive {
+			best_drive = drive;
+			best_angle = sheeting_angle;
+		}
+		sheeting_angle += TRIM_RESOLUTION;
+	}
+	best_angle
+}
+
+/// Save state for simulation file
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AutopilotSave {
+	pub mode: AutopilotMode,
+	pub gains: PidGains,
+	/// Maximum rudder angle the autopilot will use, degrees
+	#[serde(default = "default_max_rudder_angle")]
+	pub max_rudder_angle: Float,
+	/// Integral of the error, radians*seconds
+	#[serde(default)]
+	pub integral: Float,
+	/// Error from the previous step, for the derivative term
+	#[serde(default)]
+	pub prev_error_opt: Option<Float>,
+	/// Whether the autopilot trims the sails
+	#[serde(default)]
+	pub auto_trim: bool
+}
+
+impl Default for AutopilotSave {
+	fn default() -> Self {
+		Self {
+			mode: AutopilotMode::default(),
+			gains: PidGains::default(),
+			max_rudder_angle: default_max_rudder_angle(),
+			integral: 0.0,
+			prev_error_opt: None,
+			auto_trim: false
+		}
+	}
+}
+
+fn default_max_rudder_angle() -> Float {
+	35.0
+}
+
+/// Autopilot input from client
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct AutopilotInputs {
+	/// Turns the autopilot on or off
+	pub enabled_opt: Option<bool>,
+	pub mode_opt: Option<AutopilotMode>,
+	/// Waypoints to plan a route through, the planned route replaces the current mode
+	pub waypoints_opt: Option<Vec<V2>>,
+	pub gains_opt: Option<PidGains>,
+	/// Turns automatic sail trim on or off
+	pub auto_trim_opt: Option<bool>
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::rc::Rc;
+	use crate::{resource_interface::MapSave, simulation::user::UserInput, test_utils};
+
+	const DT: Float = 0.02;
+
+	/// Simulation with "alice" on the autopilot in `mode` with automatic trim, wind blowing towards `wind_direction` (degrees) without gusts or shifts
+	fn simulation(map: MapSave, wind_direction: Float, mode: AutopilotMode) -> Simulation {
+		let mut save = test_utils::save();
+		save.default_wind = WindGeneratorSaveState {
+			max_speed_variation: 0.0,
+			max_direction_variation: 0.0,
+			direction: wind_direction,
+			..Default::default()
+		};
+		let mut sim = Simulation::from_resources(save, test_utils::resources(map)).unwrap();
+		sim.add_client("alice", test_utils::BOAT_TYPE_NAME).unwrap();
+		sim.apply_input("alice", UserInput {
+			boat: None,
+			autopilot: Some(AutopilotInputs {
+				enabled_opt: Some(true),
+				mode_opt: Some(mode),
+				auto_trim_opt: Some(true),
+				..Default::default()
+			})
+		}).unwrap();
+		sim
+	}
+
+	fn boat(sim: &Simulation) -> &BoatSaveState {
+		sim.boat("alice").unwrap().state()
+	}
+
+	#[test]
+	fn heading_hold_settles() {
+		// Beam reach to the north with the wind blowing east
+		let mut sim = simulation(test_utils::open_water_map(1000), 0.0, AutopilotMode::Heading(90.0));
+		for _ in 0..(30.0 / DT) as usize {
+			sim.step(DT);
+		}
+		let heading = boat(&sim).heading();
+		assert!(wrap_angle(heading - PI / 2.0).abs() < 5.0_f32.to_radians(), "Heading {} degrees", heading.to_degrees());
+		// Settled, not swinging through the target
+		assert!(boat(&sim).vel.rotation.angle().abs() < 0.05);
+		assert!(boat(&sim).vel.translation.vector.magnitude() > 0.5);
+	}
+
+	#[test]
+	fn wind_angle_hold_settles() {
+		// Wind 70 degrees off the port bow, close reaching
+		let mut sim = simulation(test_utils::open_water_map(1000), 0.0, AutopilotMode::WindAngle(70.0));
+		for _ in 0..(30.0 / DT) as usize {
+			sim.step(DT);
+		}
+		let wind = sim.save_state().clients["alice"].wind.vector();
+		let angle = boat(&sim).apparent_wind_angle(&wind);
+		assert!((angle - 70.0_f32.to_radians()).abs() < 5.0_f32.to_radians(), "Apparent wind angle {} degrees", angle.to_degrees());
+	}
+
+	#[test]
+	fn rudder_moves_within_rate_limit() {
+		let boat_type = Rc::new(test_utils::boat_type());
+		let mut boat = Boat::new(boat_type.clone(), BoatSaveState::new(test_utils::BOAT_TYPE_NAME.to_owned(), &boat_type, Iso::identity()));
+		let mut autopilot = Autopilot::from_save(AutopilotSave::default());
+		// Turn all the way around, so the controller asks for full rudder
+		autopilot.set_mode(AutopilotMode::Heading(180.0));
+		let wind = V2::new(0.0, -5.0);
+		let max_rudder_movement: Float = 30.0;
+		let max_change = max_rudder_movement.to_radians() * DT;
+		let max_rudder_angle = AutopilotSave::default().max_rudder_angle.to_radians();
+		let mut reached_full_rudder = false;
+		for _ in 0..500 {
+			let prev_rudder = boat.state().rudder_angle;
+			let inputs = autopilot.update(DT, boat.state(), &boat_type, &wind, max_rudder_movement);
+			let rudder = inputs.rudder_control.unwrap();
+			assert!((rudder - prev_rudder).abs() <= max_change + EPSILON, "Rudder moved {} in one step", rudder - prev_rudder);
+			assert!(rudder.abs() <= max_rudder_angle + EPSILON);
+			reached_full_rudder |= (rudder.abs() - max_rudder_angle).abs() < EPSILON;
+			boat.update(DT, &wind, &inputs);
+		}
+		assert!(reached_full_rudder);
+	}
+
+	#[test]
+	fn integral_is_clamped() {
+		let boat_type = test_utils::boat_type();
+		let boat = BoatSaveState::new(test_utils::BOAT_TYPE_NAME.to_owned(), &boat_type, Iso::identity());
+		let mut autopilot = Autopilot::from_save(AutopilotSave::default());
+		autopilot.set_mode(AutopilotMode::Heading(90.0));
+		// The boat never turns, as if the rudder were jammed
+		for _ in 0..1000 {
+			autopilot.update(DT, &boat, &boat_type, &V2::zeros(), 90.0);
+		}
+		assert_eq!(autopilot.save().integral, MAX_INTEGRAL);
+		autopilot.set_mode(AutopilotMode::Heading(-90.0));
+		assert_eq!(autopilot.save().integral, 0.0);
+		for _ in 0..1000 {
+			autopilot.update(DT, &boat, &boat_type, &V2::zeros(), 90.0);
+		}
+		assert_eq!(autopilot.save().integral, -MAX_INTEGRAL);
+	}
+
+	#[test]
+	fn mode_survives_save_round_trip() {
+		let mode = AutopilotMode::WindAngle(-60.0);
+		let mut sim = simulation(test_utils::open_water_map(1000), 0.0, mode.clone());
+		for _ in 0..100 {
+			sim.step(DT);
+		}
+		let json = serde_json::to_string(sim.save_state()).unwrap();
+		let save: SimulationSave = serde_json::from_str(&json).unwrap();
+		assert_eq!(save.clients["alice"].autopilot_state.mode, mode);
+		assert!(save.clients["alice"].autopilot_state.auto_trim);
+		let mut loaded = Simulation::from_resources(save, test_utils::resources(test_utils::open_water_map(1000))).unwrap();
+		loaded.step(DT);
+		let client = &loaded.save_state().clients["alice"];
+		assert!(client.autopilot_enabled);
+		assert_eq!(client.autopilot_state.mode, mode);
+	}
+
+	/// Stationary boat at the origin pointing `heading` degrees CCW from +X
+	fn boat_heading(heading: Float) -> BoatSaveState {
+		BoatSaveState::new(test_utils::BOAT_TYPE_NAME.to_owned(), &test_utils::boat_type(), Iso::new(V2::zeros(), heading.to_radians()))
+	}
+
+	/// `vmg_heading()` in degrees for a target 100m away at `bearing` degrees, with the wind from the north
+	fn vmg_heading_deg(heading: Float, bearing: Float) -> Float {
+		let target = V2::new(bearing.to_radians().cos(), bearing.to_radians().sin()) * 100.0;
+		vmg_heading(&boat_heading(heading), &test_utils::boat_type(), &V2::new(0.0, -5.0), &target).to_degrees()
+	}
+
+	fn assert_angle_eq(actual: Float, expected: Float) {
+		assert!(wrap_angle((actual - expected).to_radians()).abs() < 0.01, "Heading {} degrees, expected {}", actual, expected);
+	}
+
+	#[test]
+	fn vmg_dead_upwind_stays_on_current_tack() {
+		// Boat type sails 45 degrees off the wind, north is 90 degrees
+		// Port tack, wind over the port (left) side
+		assert_angle_eq(vmg_heading_deg(45.0, 90.0), 45.0);
+		// Starboard tack
+		assert_angle_eq(vmg_heading_deg(135.0, 90.0), 135.0);
+		// Still inside the 50 degree upwind cone on the far side, so the boat doesn't tack yet
+		assert_angle_eq(vmg_heading_deg(45.0, 130.0), 45.0);
+		assert_angle_eq(vmg_heading_deg(135.0, 50.0), 135.0);
+	}
+
+	#[test]
+	fn vmg_tacks_past_layline() {
+		// Target has crossed the starboard layline while on port tack, so it is sailed to directly on starboard
+		assert_angle_eq(vmg_heading_deg(45.0, 145.0), 145.0);
+		assert_angle_eq(vmg_heading_deg(135.0, 35.0), 35.0);
+	}
+
+	#[test]
+	fn vmg_reaches_directly() {
+		assert_angle_eq(vmg_heading_deg(45.0, 0.0), 0.0);
+		assert_angle_eq(vmg_heading_deg(45.0, 200.0), 200.0);
+	}
+
+	#[test]
+	fn vmg_gybes_downwind() {
+		// Downwind cone is everything more than 150 degrees from the wind, target is dead downwind to the south
+		// Wind over the port side, heading is 150 degrees CW from the wind
+		assert_angle_eq(vmg_heading_deg(-45.0, -90.0), -60.0);
+		// Wind over the starboard side
+		assert_angle_eq(vmg_heading_deg(-135.0, -90.0), -120.0);
+		// Target just outside the cone is sailed to directly, gybing onto the other side
+		assert_angle_eq(vmg_heading_deg(-45.0, -125.0), -125.0);
+	}
+
+	#[test]
+	fn vmg_reaches_map_end_upwind() {
+		// End is to the north-east with the wind from the north, so the boat has to tack
+		let map = test_utils::open_water_map(250);
+		let end = map.end;
+		let mut sim = simulation(map, -90.0, AutopilotMode::Vmg(end));
+		// Whether the boat has headed east and west of north, ie. sailed on port and starboard tack
+		let mut tacks_sailed = (false, false);
+		for _ in 0..(600.0 / DT) as usize {
+			sim.step(DT);
+			let pos = boat(&sim).pos.translation.vector;
+			if (end - pos).magnitude() < ARRIVAL_RADIUS {
+				assert_eq!(tacks_sailed, (true, true));
+				return;
+			}
+			match wrap_angle(boat(&sim).heading() - PI / 2.0) < 0.0 {
+				true => tacks_sailed.0 = true,
+				false => tacks_sailed.1 = true
+			}
+		}
+		panic!("Didn't reach the end, stopped at {:?}", boat(&sim).pos.translation.vector);
+	}
+
+	#[test]
+	fn trim_eases_on_beam_reach() {
+		let boat_type = test_utils::boat_type();
+		let (_, main) = boat_type.sails.get_item_tuple(&GenericQuery::id(0)).unwrap();
+		// Boat frame, wind from the port side blowing towards starboard
+		let beam_reach = optimal_sheeting_angle(main, &V2::new(0.0, -5.0));
+		assert!(beam_reach > 20.0 && beam_reach < 70.0, "Sheeted to {} degrees on a beam reach", beam_reach);
+		// Closer to the wind the sheet comes in
+		let close_reach = optimal_sheeting_angle(main, &V2::new(-3.0, -4.0));
+		assert!(close_reach < beam_reach, "Sheeted to {} degrees on a close reach", close_reach);
+	}
+
+	#[test]
+	fn trim_is_loosest_head_to_wind() {
+		let boat_type = test_utils::boat_type();
+		let (_, main) = boat_type.sails.get_item_tuple(&GenericQuery::id(0)).unwrap();
+		assert_eq!(optimal_sheeting_angle(main, &V2::new(-5.0, 0.0)), MAX_TRIM_SHEETING_ANGLE);
+	}
+}
@@ -0,0 +1,206 @@
+//! Route planning around land for the autopilot
+//! Builds a visibility graph out of the start, the waypoints and every coastline vertex (pushed slightly out to sea) and searches it with A*.
+//! Legs which point into the upwind or downwind no-go zones cost more, because the boat has to tack or gybe to sail them.
+//! There is no depth information in maps yet, so shallow water is not avoided.
+
+use std::collections::{BinaryHeap, HashMap};
+use std::cmp::Ordering;
+use crate::{prelude::*, geometry, resource_interface::MapSave, spatial_index::LandIndex};
+
+// CONSTS
+/// How far route points are kept away from the coastline
+const COAST_CLEARANCE: Float = 10.0;
+
+/// Plans a path from `start` through every waypoint in order, the returned path does not include `start`
+/// `land` must be built from `map`
+pub fn plan_route(map: &MapSave, land: &LandIndex, boat_type: &BoatType, wind: &Wind, start: V2, waypoints: &[V2]) -> Result<Vec<V2>, String> {
+	let nodes = coast_nodes(map, land);
+	let mut out = Vec::new();
+	let mut leg_start = start;
+	for waypoint in waypoints {
+		if land.point_in_land(waypoint) {
+			return Err(format!("Waypoint ({}, {}) is on land", waypoint.x, waypoint.y));
+		}
+		out.extend(plan_leg(land, boat_type, wind, leg_start, *waypoint, &nodes)?);
+		leg_start = *waypoint;
+	}
+	Ok(out)
+}
+
+/// Coastline vertices moved out to sea, any which end up on land or outside the map are discarded
+fn coast_nodes(map: &MapSave, land: &LandIndex) -> Vec<V2> {
+	let mut out = Vec::new();
+	for (_, landmass) in &map.landmasses {
+		for point in geometry::offset_polygon_vertices(&landmass.coastline, COAST_CLEARANCE) {
+			if map.contains(&point) && !land.point_in_land(&point) {
+				out.push(point);
+			}
+		}
+	}
+	out
+}
+
+/// Distance the boat actually has to sail to get from `a` to `b`, including tacking or gybing
+pub fn sailing_cost(boat_type: &BoatType, wind: &Wind, a: &V2, b: &V2) -> Float {
+	let leg = b - a;
+	let distance = leg.magnitude();
+	if distance < EPSILON || wind.magnitude() < EPSILON {
+		return distance;
+	}
+	// Angle between the leg and straight upwind
+	let upwind_angle = leg.angle(&-wind);
+	let upwind_course_angle = boat_type.upwind_max_total_leeway.to_radians();
+	let downwind_course_angle = PI - boat_type.downwind_max_wind_angle.to_radians();
+	if upwind_angle < upwind_course_angle {
+		// Upwind component has to be made good at the close-hauled angle on alternating tacks
+		distance * upwind_angle.cos() / upwind_course_angle.cos()
+	}
+	else if PI - upwind_angle < downwind_course_angle {
+		distance * (PI - upwind_angle).cos() / downwind_course_angle.cos()
+	}
+	else {
+		distance
+	}
+}
+
+/// Entry in the A* open set, ordered so that BinaryHeap pops the lowest estimated total cost first
+struct OpenNode {
+	estimate: Float,
+	index: usize
+}
+
+impl PartialEq for OpenNode {
+	fn eq(&self, other: &Self) -> bool {
+		self.estimate == other.estimate
+	}
+}
+
+impl Eq for OpenNode {}
+
+impl PartialOrd for OpenNode {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for OpenNode {
+	fn cmp(&self, other: &Self) -> Ordering {
+		other.estimate.partial_cmp(&self.estimate).unwrap_or(Ordering::Equal)
+	}
+}
+
+/// A* over the visibility graph, returns the path excluding `start`
+fn plan_leg(land: &LandIndex, boat_type: &BoatType, wind: &Wind, start: V2, end: V2, coast_nodes: &[V2]) -> Result<Vec<V2>, String> {
+	if !land.segment_hits_land(&start, &end) {
+		return Ok(vec![end]);
+	}
+	// 0 = start, 1 = end, the rest are coast nodes
+	let mut points = vec![start, end];
+	points.extend_from_slice(coast_nodes);
+	let mut best_costs: HashMap<usize, Float> = HashMap::new();
+	let mut came_from: HashMap<usize, usize> = HashMap::new();
+	let mut open = BinaryHeap::new();
+	best_costs.insert(0, 0.0);
+	open.push(OpenNode{estimate: (end - start).magnitude(), index: 0});
+	while let Some(OpenNode{index: current, ..}) = open.pop() {
+		if current == 1 {
+			let mut path = vec![end];
+			let mut node = current;
+			while let Some(prev) = came_from.get(&node) {
+				if *prev != 0 {
+					path.push(points[*prev]);
+				}
+				node = *prev;
+			}
+			path.reverse();
+			return Ok(path);
+		}
+		let current_cost = best_costs[&current];
+		for next in 1..points.len() {
+			if next == current || land.segment_hits_land(&points[current], &points[next]) {
+				continue;
+			}
+			let cost = current_cost + sailing_cost(boat_type, wind, &points[current], &points[next]);
+			if best_costs.get(&next).is_none_or(|best| cost < *best) {
+				best_costs.insert(next, cost);
+				came_from.insert(next, current);
+				open.push(OpenNode{estimate: cost + (end - points[next]).magnitude(), index: next});
+			}
+		}
+	}
+	Err(format!("No route from ({}, {}) to ({}, {}) avoids land", start.x, start.y, end.x, end.y))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils;
+
+	/// Blowing towards -Y, so upwind is +Y
+	fn north_wind() -> Wind {
+		V2::new(0.0, -5.0)
+	}
+
+	fn plan(map: &MapSave, wind: &Wind, start: V2, goal: V2) -> Result<Vec<V2>, String> {
+		plan_route(map, &LandIndex::new(map), &test_utils::boat_type(), wind, start, &[goal])
+	}
+
+	/// Checks that the path ends at `goal` and no leg of it touches land, returns its length
+	fn check_path(map: &MapSave, start: V2, goal: V2, path: &[V2]) -> Float {
+		assert_eq!(path.last(), Some(&goal));
+		let mut length = 0.0;
+		let mut prev = start;
+		for point in path {
+			assert!(!map.segment_hits_land(&prev, point), "Leg from {:?} to {:?} crosses land", prev, point);
+			length += (point - prev).magnitude();
+			prev = *point;
+		}
+		length
+	}
+
+	#[test]
+	fn direct_route_in_clear_water() {
+		let map = test_utils::open_water_map(1000);
+		let goal = V2::new(500.0, 900.0);
+		assert_eq!(plan(&map, &north_wind(), V2::new(500.0, 100.0), goal).unwrap(), vec![goal]);
+	}
+
+	#[test]
+	fn route_goes_around_island() {
+		let map = test_utils::map_with_land(1000, vec![test_utils::landmass(test_utils::rectangle(V2::new(400.0, 400.0), V2::new(600.0, 600.0)), Vec::new())]);
+		let (start, goal) = (V2::new(200.0, 500.0), V2::new(800.0, 500.0));
+		let path = plan(&map, &north_wind(), start, goal).unwrap();
+		assert!(path.len() > 1);
+		let length = check_path(&map, start, goal, &path);
+		// Around one side of the island, not back and forth
+		assert!(length < 600.0 + 2.0 * 100.0 + 4.0 * COAST_CLEARANCE, "Path is {} long", length);
+	}
+
+	#[test]
+	fn upwind_penalty_picks_tacking_route() {
+		// Wall between start and goal, its right end is nearer but going around it leaves a leg straight upwind to the goal
+		let map = test_utils::map_with_land(1000, vec![test_utils::landmass(test_utils::rectangle(V2::new(390.0, 255.0), V2::new(710.0, 275.0)), Vec::new())]);
+		let (start, goal) = (V2::new(500.0, 200.0), V2::new(780.0, 640.0));
+		// Without wind only distance counts
+		let shortest = plan(&map, &V2::zeros(), start, goal).unwrap();
+		let shortest_length = check_path(&map, start, goal, &shortest);
+		assert!(shortest.iter().all(|point| point.x > 700.0));
+		// With wind it is quicker to go round the far end, tacking out to the left then across to the goal
+		let tacking = plan(&map, &north_wind(), start, goal).unwrap();
+		let tacking_length = check_path(&map, start, goal, &tacking);
+		assert!(tacking.iter().any(|point| point.x < 390.0));
+		assert!(tacking_length > shortest_length);
+		let route_cost = |path: &[V2]| std::iter::once(&start).chain(path).zip(path).map(|(a, b)| sailing_cost(&test_utils::boat_type(), &north_wind(), a, b)).sum::<Float>();
+		assert!(route_cost(&tacking) < route_cost(&shortest));
+	}
+
+	#[test]
+	fn enclosed_goal_is_an_error() {
+		// Lagoon with no way in
+		let atoll = test_utils::landmass(test_utils::rectangle(V2::new(300.0, 300.0), V2::new(700.0, 700.0)), vec![test_utils::rectangle(V2::new(400.0, 400.0), V2::new(600.0, 600.0))]);
+		let map = test_utils::map_with_land(1000, vec![atoll]);
+		assert!(plan(&map, &north_wind(), V2::new(100.0, 100.0), V2::new(500.0, 500.0)).is_err());
+		// Waypoints on land are rejected too
+		assert!(plan(&map, &north_wind(), V2::new(100.0, 100.0), V2::new(350.0, 350.0)).is_err());
+	}
+}
@@ -0,0 +1,264 @@
+//! Command line interface, lets the server run unattended (ex: in a container)
+
+use std::{net::SocketAddr, rc::Rc, thread, time::{Duration, Instant}};
+use crate::{prelude::*, resource_interface::ResourceType, polar::{self, PolarTable}, leaderboard::LeaderboardQuery, replay::ReplayPlayer, server::spectator::{self, SpectatorServer}, export::{self, ExportTrack}, mapgen::{self, MapGenParams}, raster::{self, LandMask}};
+
+const USAGE: &str = "Usage:
+	serve <sim> [port]                            Run the server for a simulation
+	new-sim [<map> <name> [password]]             Create a new simulation from a map, runs the setup wizard if no arguments are given
+	list maps|boats|sims|courses|replays          List available resources
+	validate maps|boats|sims|courses/<name>       Check a resource for problems, ex: `validate maps/harbor`
+	client <ip:port> [username] [boat type] [password]   Connect to a server, missing arguments are prompted for
+	polar <boat type> <output .json|.csv>         Generate a polar diagram for a boat type
+	leaderboard <map> [boat type] [n]             Show the fastest runs on a map
+	incidents <sim>                               Show logged racing-rule infringements
+	replay <name> [speed] [spectator port]        Play back a recorded session, speed 0 runs it as fast as possible
+	verify-replay <name>                          Check that a replay plays back identically every time
+	export <sim> <output .gpx|.kml> [username]    Export boat tracks for chart software, all boats if no username is given
+	import-geojson <file> <map name> [tolerance] [margin]   Create a map from GeoJSON coastlines, in meters (default 5 and 200)
+	generate-map <name> [seed] [islands] [size] [roughness] [channel width]   Create a random map with islands, roughness is 0 (round) to 1 (jagged)
+	bench-land [map] [queries]                    Time land queries with and without the spatial index, uses a 50000 point coastline if no map is given
+	render-map <map> <output.png> [units per pixel]   Draw a map's land and water as an image, ex: for a minimap";
+
+/// Runs the subcommand given by `args` (not including the program name)
+pub fn run(args: &[String]) -> Result<(), String> {
+	let arg = |i: usize| args.get(i).map(|s| s.as_str());
+	match arg(0) {
+		Some("serve") => {
+			let sim_name = arg(1).ok_or_else(usage_error)?;
+			let port: u16 = match arg(2) {
+				Some(port) => to_string_err_with_message(port.parse(), "Invalid port")?,
+				None => crate::server::DEFAULT_PORT
+			};
+			let sim = Simulation::load(resource_interface::load_simulation(sim_name)?)?;
+			WorldServer::new(sim_name.to_owned(), sim).run(port)
+		},
+		Some("new-sim") => {
+			let (map_name, sim_name) = match (arg(1), arg(2)) {
+				(Some(map_name), Some(sim_name)) => (map_name, sim_name),
+				(None, None) => return crate::wizard::new_sim_wizard(),
+				_ => return Err(usage_error())
+			};
+			new_sim(map_name, sim_name, arg(3).map(|s| s.to_owned()))
+		},
+		Some("list") => {
+			let resource_type = arg(1).and_then(ResourceType::from_plural_name).ok_or_else(usage_error)?;
+			for name in resource_type.list()? {
+				println!("{}", name);
+			}
+			Ok(())
+		},
+		Some("validate") => {
+			let (type_name, name) = arg(1).and_then(|s| s.split_once('/')).ok_or_else(usage_error)?;
+			let resource_type = ResourceType::from_plural_name(type_name).ok_or_else(usage_error)?;
+			resource_interface::validate(resource_type, name)?;
+			println!("{:?} \"{}\" is valid", resource_type, name);
+			Ok(())
+		},
+		Some("client") => {
+			let server_addr: SocketAddr = to_string_err_with_message(arg(1).ok_or_else(usage_error)?.parse(), "Invalid server address")?;
+			let username = arg(2).map(|s| s.to_owned()).unwrap_or_else(|| prompt("Username"));
+			let boat_type = arg(3).map(|s| s.to_owned()).unwrap_or_else(|| prompt("Boat type"));
+			crate::client::run_headless(server_addr, username, arg(4).map(|s| s.to_owned()), boat_type)
+		},
+		Some("polar") => {
+			let (boat_type_name, output_path) = match (arg(1), arg(2)) {
+				(Some(boat_type_name), Some(output_path)) => (boat_type_name, output_path),
+				_ => return Err(usage_error())
+			};
+			let boat_type = Rc::new(resource_interface::load_boat_type(boat_type_name)?);
+			let table = PolarTable::generate(boat_type_name, boat_type, polar::default_wind_angles(), polar::default_wind_speeds());
+			table.write(output_path)?;
+			for (i, speed) in table.wind_speeds.iter().enumerate() {
+				if let (Some((upwind_angle, upwind_course)), Some(downwind_angle)) = (table.best_upwind_angles(i), table.best_downwind_angle(i)) {
+					println!("{} m/s: best upwind angle {:.0} deg (course {:.0} deg), best downwind angle {:.0} deg", speed, upwind_angle, upwind_course, downwind_angle);
+				}
+			}
+			Ok(())
+		},
+		Some("leaderboard") => {
+			let map_name = arg(1).ok_or_else(usage_error)?;
+			let n: usize = match arg(3) {
+				Some(n) => to_string_err_with_message(n.parse(), "Invalid number of entries")?,
+				None => 10
+			};
+			let query = LeaderboardQuery {
+				boat_type_opt: arg(2).map(|s| s.to_owned()),
+				course_name_opt: None,
+				n,
+				corrected: false
+			};
+			for ranked in resource_interface::load_leaderboard(map_name)?.top(&query) {
+				let corrected = ranked.entry.corrected_time_opt.map(|time| format!(" (corrected {:.2} s)", time)).unwrap_or_default();
+				println!("{:>3}. {:<20} {:<16} {:>9.2} s{}", ranked.rank, ranked.entry.username, ranked.entry.boat_type, ranked.entry.time, corrected);
+			}
+			Ok(())
+		},
+		Some("incidents") => {
+			let sim = resource_interface::load_simulation(arg(1).ok_or_else(usage_error)?)?;
+			for incident in &sim.rule_incidents {
+				println!("{}", incident);
+			}
+			Ok(())
+		},
+		Some("replay") => {
+			let mut player = ReplayPlayer::new(resource_interface::load_replay(arg(1).ok_or_else(usage_error)?)?)?;
+			player.speed = match arg(2) {
+				Some(speed) => to_string_err_with_message(speed.parse(), "Invalid speed")?,
+				None => 1.0
+			};
+			let spectator_opt = match arg(3) {
+				Some(port) => Some(SpectatorServer::start(to_string_err_with_message(port.parse(), "Invalid port")?, player.sim().map(), spectator::land_image(player.sim().map())?)?),
+				None => None
+			};
+			println!("Replay is {:.1} s long", player.replay().duration());
+			if player.speed <= 0.0 {
+				player.run_to_end()?;
+			}
+			let mut last_update = Instant::now();
+			while !player.is_finished() {
+				player.update(last_update.elapsed().as_secs_f32())?;
+				last_update = Instant::now();
+				if let Some(spectator) = &spectator_opt {
+					spectator.update(player.sim())?;
+				}
+				thread::sleep(Duration::from_millis(16));
+			}
+			for result in &player.sim().save_state().race_results {
+				print!("{}", result);
+			}
+			Ok(())
+		},
+		Some("verify-replay") => {
+			let replay = resource_interface::load_replay(arg(1).ok_or_else(usage_error)?)?;
+			let steps = replay.verify_determinism(&replay.resolve()?)?;
+			println!("Replay is deterministic over {} steps", steps);
+			Ok(())
+		},
+		Some("export") => {
+			let (sim_name, output_path) = match (arg(1), arg(2)) {
+				(Some(sim_name), Some(output_path)) => (sim_name, output_path),
+				_ => return Err(usage_error())
+			};
+			let sim = resource_interface::load_simulation(sim_name)?;
+			let geo = match sim.map_name.load()?.geo_reference_opt {
+				Some(geo) => geo,
+				None => return Err(format!("Map \"{}\" has no geographic reference", sim.map_name))
+			};
+			let tracks: Vec<ExportTrack> = sim.clients.iter()
+				.filter(|(username, _)| arg(3).is_none_or(|name| name == username.as_str()))
+				.map(|(username, client)| ExportTrack {
+					username,
+					boat_type: client.boat.type_name.name(),
+					points: &client.track
+				})
+				.collect();
+			if tracks.is_empty() {
+				return Err("No matching boats".to_owned());
+			}
+			export::write(output_path, &geo, &tracks)?;
+			println!("Exported {} track(s) to {}", tracks.len(), output_path);
+			Ok(())
+		},
+		Some("import-geojson") => {
+			let (path, map_name) = match (arg(1), arg(2)) {
+				(Some(path), Some(map_name)) => (path, map_name),
+				_ => return Err(usage_error())
+			};
+			if ResourceType::Map.list()?.iter().any(|name| name == map_name) {
+				return Err(format!("Map \"{}\" already exists", map_name));
+			}
+			let tolerance: Float = match arg(3) {
+				Some(tolerance) => to_string_err_with_message(tolerance.parse(), "Invalid tolerance")?,
+				None => 5.0
+			};
+			let margin: Float = match arg(4) {
+				Some(margin) => to_string_err_with_message(margin.parse(), "Invalid margin")?,
+				None => 200.0
+			};
+			let map = crate::import::map_from_geojson_file(path, None, tolerance, margin)?;
+			for problem in map.problems() {
+				println!("Warning: {}", problem);
+			}
+			resource_interface::save_map(map_name, &map)?;
+			println!("Created map \"{}\" with {} landmasses, size {} x {}", map_name, map.landmasses.len(), map.size.0, map.size.1);
+			Ok(())
+		},
+		Some("generate-map") => {
+			let map_name = arg(1).ok_or_else(usage_error)?;
+			if ResourceType::Map.list()?.iter().any(|name| name == map_name) {
+				return Err(format!("Map \"{}\" already exists", map_name));
+			}
+			let mut params = MapGenParams::default();
+			if let Some(seed) = arg(2) {
+				params.seed = to_string_err_with_message(seed.parse(), "Invalid seed")?;
+			}
+			if let Some(islands) = arg(3) {
+				params.islands = to_string_err_with_message(islands.parse(), "Invalid number of islands")?;
+			}
+			if let Some(size) = arg(4) {
+				let size: Int = to_string_err_with_message(size.parse(), "Invalid size")?;
+				params.size = IntV2(size, size);
+			}
+			if let Some(roughness) = arg(5) {
+				params.roughness = to_string_err_with_message(roughness.parse(), "Invalid roughness")?;
+			}
+			if let Some(channel_width) = arg(6) {
+				params.channel_width = to_string_err_with_message(channel_width.parse(), "Invalid channel width")?;
+			}
+			let map = mapgen::generate(&params)?;
+			resource_interface::save_map(map_name, &map)?;
+			println!("Created map \"{}\" with {} islands", map_name, map.landmasses.len());
+			Ok(())
+		},
+		Some("bench-land") => {
+			let map_opt = match arg(1) {
+				Some(map_name) => Some(resource_interface::load_map(map_name)?),
+				None => None
+			};
+			let queries: usize = match arg(2) {
+				Some(queries) => to_string_err_with_message(queries.parse(), "Invalid number of queries")?,
+				None => 10_000
+			};
+			println!("{}", crate::spatial_index::benchmark(map_opt.as_ref(), 50_000, queries));
+			Ok(())
+		},
+		Some("render-map") => {
+			let (map_name, output_path) = match (arg(1), arg(2)) {
+				(Some(map_name), Some(output_path)) => (map_name, output_path),
+				_ => return Err(usage_error())
+			};
+			let map = resource_interface::load_map(map_name)?;
+			let resolution: Float = match arg(3) {
+				Some(resolution) => to_string_err_with_message(resolution.parse(), "Invalid resolution")?,
+				None => 1.0
+			};
+			// Use the GUI colors if there are global settings
+			let (water_color, land_color) = match resource_interface::load_settings() {
+				Ok(settings) => (settings.gui.colors.ocean, settings.gui.colors.land),
+				Err(_) => (raster::DEFAULT_WATER_COLOR, raster::DEFAULT_LAND_COLOR)
+			};
+			let mask = LandMask::new(&map, resolution)?;
+			mask.write_png(output_path, &map, water_color, land_color)?;
+			println!("Wrote {} x {} image to {}", mask.width, mask.height, output_path);
+			Ok(())
+		},
+		_ => Err(usage_error())
+	}
+}
+
+fn usage_error() -> String {
+	USAGE.to_owned()
+}
+
+/// Creates and saves a new simulation with no clients and default wind
+pub fn new_sim(map_name: &str, sim_name: &str, password: Option<String>) -> Result<(), String> {
+	// Make sure the map exists before creating anything
+	resource_interface::load_map(map_name)?;
+	if ResourceType::Simulation.list()?.iter().any(|name| name == sim_name) {
+		return Err(format!("Simulation \"{}\" already exists", sim_name));
+	}
+	resource_interface::save_simulation(sim_name, &SimulationSave::new(map_name.to_owned(), WindGeneratorSaveState::default(), password))?;
+	println!("Created simulation \"{}\" on map \"{}\"", sim_name, map_name);
+	Ok(())
+}
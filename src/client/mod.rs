@@ -1,9 +1,100 @@
 //! Client & UI
 
-#[derive(Debug)]
-struct SigninEntryState {
-	pub name: String,
-	pub psswd: String,
-	pub ip: String,
-	pub port: String
-}
\ No newline at end of file
+use std::{net::{SocketAddr, UdpSocket}, rc::Rc, thread, time::{Duration, Instant, SystemTime}};
+use crate::{prelude::*, server::{self, RenetRequest, RenetResponse, snapshot::SnapshotReceiver}, simulation::user::UserInput};
+use renet::{ConnectionConfig, DefaultChannel, RenetClient, transport::{ClientAuthentication, NetcodeClientTransport}};
+
+pub mod prediction;
+
+use prediction::ClientPrediction;
+
+// CONSTS
+const TICK_INTERVAL: Duration = Duration::from_millis(16);
+/// Time between status lines printed by the headless client
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Connects to a server and follows the user's boat without a GUI, printing its state periodically
+pub fn run_headless(server_addr: SocketAddr, username: String, password: Option<String>, boat_type_name: String) -> Result<(), String> {
+	let boat_type = Rc::new(resource_interface::load_boat_type(&boat_type_name)?);
+	let socket = to_string_err(UdpSocket::bind("0.0.0.0:0"))?;
+	let current_time = to_string_err(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH))?;
+	let authentication = ClientAuthentication::Unsecure {
+		server_addr,
+		client_id: current_time.as_millis() as u64,
+		user_data: None,
+		protocol_id: server::PROTOCOL_ID
+	};
+	let mut transport = to_string_err(NetcodeClientTransport::new(current_time, authentication, socket))?;
+	let mut client = RenetClient::new(ConnectionConfig::default());
+	let mut sent_join = false;
+	let mut receiver = SnapshotReceiver::new();
+	let mut prediction_opt: Option<ClientPrediction> = None;
+	// Server's fixed step, known once the join is accepted
+	let mut time_step_opt: Option<Float> = None;
+	let mut last_update = Instant::now();
+	let mut last_status = Instant::now();
+	loop {
+		let elapsed = last_update.elapsed();
+		last_update = Instant::now();
+		client.update(elapsed);
+		to_string_err(transport.update(elapsed, &mut client))?;
+		if client.is_disconnected() {
+			return Err("Disconnected from server".to_owned());
+		}
+		if client.is_connected() {
+			if !sent_join {
+				client.send_message(DefaultChannel::ReliableOrdered, server::encode_message(&RenetRequest::Join {
+					username: username.clone(),
+					password: password.clone(),
+					boat_type: boat_type_name.clone()
+				})?);
+				sent_join = true;
+			}
+			while let Some(message) = client.receive_message(DefaultChannel::ReliableOrdered) {
+				match server::decode_message::<RenetResponse>(&message)? {
+					RenetResponse::JoinAccepted{time_step} => {
+						println!("Joined as \"{}\"", username);
+						time_step_opt = Some(time_step);
+					},
+					RenetResponse::JoinRejected(e) => return Err(format!("Server rejected join: {}", e)),
+					RenetResponse::Error(e) => println!("Server error: {}", e),
+					RenetResponse::Snapshot(_) | RenetResponse::Leaderboard(_) => {}
+				}
+			}
+			while let Some(message) = client.receive_message(DefaultChannel::Unreliable) {
+				if let RenetResponse::Snapshot(delta) = server::decode_message::<RenetResponse>(&message)? {
+					let (seq, snapshot) = match receiver.receive(&delta) {
+						Ok(out) => out,
+						// Base was lost, the server will send a full snapshot once it notices nothing is being acknowledged
+						Err(_) => continue
+					};
+					client.send_message(DefaultChannel::ReliableOrdered, server::encode_message(&RenetRequest::AckSnapshot(seq))?);
+					if let (None, Some(time_step), Some(own)) = (&prediction_opt, time_step_opt, snapshot.boats.get(&username)) {
+						prediction_opt = Some(ClientPrediction::new(username.clone(), Boat::new(boat_type.clone(), own.boat.clone()), own.wind, time_step));
+					}
+					if let Some(prediction) = &mut prediction_opt {
+						prediction.handle_snapshot(snapshot);
+					}
+				}
+			}
+			if let Some(prediction) = &mut prediction_opt {
+				for seq in prediction.step(elapsed.as_secs_f32(), BoatInputs::default()) {
+					client.send_message(DefaultChannel::ReliableOrdered, server::encode_message(&RenetRequest::Input {
+						seq,
+						input: UserInput {
+							boat: Some(BoatInputs::default()),
+							autopilot: None
+						}
+					})?);
+				}
+				if last_status.elapsed() >= STATUS_INTERVAL {
+					last_status = Instant::now();
+					let pos = prediction.own_boat().state().pos;
+					println!("Position: ({:.1}, {:.1}), heading: {:.0} deg, {} other boat(s)", pos.translation.x, pos.translation.y, pos.rotation.angle().to_degrees(), prediction.other_boats().len());
+				}
+			}
+		}
+		to_string_err(transport.send_packets(&mut client))?;
+		thread::sleep(TICK_INTERVAL);
+	}
+}
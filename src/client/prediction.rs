@@ -0,0 +1,308 @@
+//! Client-side prediction and server reconciliation
+//! The client's own boat is simulated locally with the same integrator and fixed time step as the server, one input per server tick.
+//! Every input is kept with the state predicted for its tick until the server acknowledges it. If the server's state for that tick differs,
+//! the predicted boat is reset to the authoritative state and the unacknowledged inputs are replayed on top of it.
+//! Other boats are displayed slightly in the past by interpolating between the two snapshots surrounding the render time.
+
+use std::collections::{HashMap, VecDeque};
+use crate::{prelude::*, server::{StateSnapshot, snapshot::QuantizedBoat}};
+
+// CONSTS
+/// How far behind the latest snapshot other boats are displayed, in seconds
+const INTERPOLATION_DELAY: Float = 0.1;
+/// Snapshots older than this (relative to the newest) are discarded
+const INTERPOLATION_BUFFER_TIME: Float = 1.0;
+/// Prevents unbounded growth if the server stops acknowledging inputs
+const MAX_PENDING_INPUTS: usize = 1024;
+
+/// Input that has been applied locally but not yet acknowledged by the server
+struct PendingInput {
+	seq: u64,
+	inputs: BoatInputs,
+	/// State right after this input's tick, compared with the server's once it is acknowledged
+	predicted: QuantizedBoat
+}
+
+/// The client's own boat, simulated ahead of the server
+pub struct PredictedBoat {
+	boat: Boat,
+	/// Server's fixed step, every input is simulated for exactly one step
+	time_step: Float,
+	next_seq: u64,
+	pending_inputs: VecDeque<PendingInput>
+}
+
+impl PredictedBoat {
+	pub fn new(boat: Boat, time_step: Float) -> Self {
+		Self {
+			boat,
+			time_step,
+			next_seq: 1,
+			pending_inputs: VecDeque::new()
+		}
+	}
+	/// Steps the local boat by one server tick with `inputs` and returns the sequence number to send along with them to the server
+	pub fn predict(&mut self, wind: &Wind, inputs: BoatInputs) -> u64 {
+		let seq = self.next_seq;
+		self.next_seq += 1;
+		self.boat.update(self.time_step, wind, &inputs);
+		self.pending_inputs.push_back(PendingInput {
+			seq,
+			inputs,
+			predicted: QuantizedBoat::from_state(self.boat.state())
+		});
+		if self.pending_inputs.len() > MAX_PENDING_INPUTS {
+			self.pending_inputs.pop_front();
+		}
+		seq
+	}
+	/// Checks the server's state, which includes every input up to and including `last_processed_seq`, against the prediction for that tick
+	/// On a misprediction the boat is reset to the server's state and the rest of the inputs are replayed, returns how far the boat moved because of it
+	pub fn reconcile(&mut self, server_state: BoatSaveState, last_processed_seq: u64, wind: &Wind) -> Float {
+		let mut acked_opt = None;
+		while let Some(pending) = self.pending_inputs.front() {
+			if pending.seq > last_processed_seq {
+				break;
+			}
+			acked_opt = self.pending_inputs.pop_front();
+		}
+		if let Some(acked) = acked_opt {
+			if acked.seq == last_processed_seq && acked.predicted.is_close_to(&QuantizedBoat::from_state(&server_state)) {
+				return 0.0;
+			}
+		}
+		let predicted_pos = self.boat.state().pos.translation.vector;
+		self.boat.set_state(server_state);
+		for pending in &mut self.pending_inputs {
+			self.boat.update(self.time_step, wind, &pending.inputs);
+			pending.predicted = QuantizedBoat::from_state(self.boat.state());
+		}
+		(self.boat.state().pos.translation.vector - predicted_pos).norm()
+	}
+	pub fn boat(&self) -> &Boat {
+		&self.boat
+	}
+	pub fn time_step(&self) -> Float {
+		self.time_step
+	}
+	pub fn pending_input_count(&self) -> usize {
+		self.pending_inputs.len()
+	}
+}
+
+/// Buffer of timestamped snapshots for a boat controlled by someone else
+pub struct SnapshotInterpolator {
+	snapshots: VecDeque<(Float, BoatSaveState)>
+}
+
+impl SnapshotInterpolator {
+	pub fn new() -> Self {
+		Self {
+			snapshots: VecDeque::new()
+		}
+	}
+	/// Adds a snapshot, `time` is the server's simulation time, out-of-order snapshots are ignored
+	pub fn push(&mut self, time: Float, state: BoatSaveState) {
+		if let Some((latest_time, _)) = self.snapshots.back() {
+			if time <= *latest_time {
+				return;
+			}
+		}
+		self.snapshots.push_back((time, state));
+		while let Some((oldest_time, _)) = self.snapshots.front() {
+			if time - oldest_time > INTERPOLATION_BUFFER_TIME && self.snapshots.len() > 2 {
+				self.snapshots.pop_front();
+			}
+			else {
+				break;
+			}
+		}
+	}
+	/// State to display at `latest server time - INTERPOLATION_DELAY`
+	pub fn sample(&self) -> Option<BoatSaveState> {
+		let latest_time = self.snapshots.back()?.0;
+		self.sample_at(latest_time - INTERPOLATION_DELAY)
+	}
+	pub fn sample_at(&self, render_time: Float) -> Option<BoatSaveState> {
+		let (first_time, first) = self.snapshots.front()?;
+		if render_time <= *first_time {
+			return Some(first.clone());
+		}
+		for ((t0, s0), (t1, s1)) in self.snapshots.iter().zip(self.snapshots.iter().skip(1)) {
+			if render_time >= *t0 && render_time <= *t1 {
+				return Some(s0.interpolate(s1, (render_time - t0) / (t1 - t0)));
+			}
+		}
+		// Render time is newer than every snapshot, don't extrapolate
+		self.snapshots.back().map(|(_, state)| state.clone())
+	}
+}
+
+impl Default for SnapshotInterpolator {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Client's view of every boat in the simulation
+pub struct ClientPrediction {
+	username: String,
+	own_boat: PredictedBoat,
+	others: HashMap<String, SnapshotInterpolator>,
+	wind: Wind,
+	/// Real time which hasn't been predicted yet, always less than one time step after `step()`
+	unsimulated_time: Float,
+	/// Server tick of the newest snapshot handled, snapshots arriving out of order are ignored
+	latest_tick_opt: Option<u64>
+}
+
+impl ClientPrediction {
+	/// `time_step` has to be the server's, as sent when joining
+	pub fn new(username: String, own_boat: Boat, wind: Wind, time_step: Float) -> Self {
+		Self {
+			username,
+			own_boat: PredictedBoat::new(own_boat, time_step),
+			others: HashMap::new(),
+			wind,
+			unsimulated_time: 0.0,
+			latest_tick_opt: None
+		}
+	}
+	/// Local step of `dt` real time in whole server ticks, returns the sequence number of each tick, an input has to be sent to the server for every one
+	pub fn step(&mut self, dt: Float, inputs: BoatInputs) -> Vec<u64> {
+		self.unsimulated_time += dt;
+		let mut seqs = Vec::new();
+		while self.unsimulated_time >= self.own_boat.time_step() {
+			self.unsimulated_time -= self.own_boat.time_step();
+			seqs.push(self.own_boat.predict(&self.wind, inputs.clone()));
+		}
+		seqs
+	}
+	/// Returns how far the own boat had to be corrected
+	pub fn handle_snapshot(&mut self, snapshot: StateSnapshot) -> Float {
+		if self.latest_tick_opt.is_some_and(|tick| snapshot.tick <= tick) {
+			return 0.0;
+		}
+		self.latest_tick_opt = Some(snapshot.tick);
+		self.others.retain(|username, _| snapshot.boats.contains_key(username));
+		let mut correction = 0.0;
+		for (username, client) in snapshot.boats {
+			if username == self.username {
+				self.wind = client.wind;
+				correction = self.own_boat.reconcile(client.boat, snapshot.last_processed_input, &self.wind);
+			}
+			else {
+				self.others.entry(username).or_default().push(snapshot.time, client.boat);
+			}
+		}
+		correction
+	}
+	pub fn own_boat(&self) -> &Boat {
+		self.own_boat.boat()
+	}
+	pub fn pending_input_count(&self) -> usize {
+		self.own_boat.pending_input_count()
+	}
+	/// Interpolated states of all other boats
+	pub fn other_boats(&self) -> HashMap<String, BoatSaveState> {
+		self.others.iter().filter_map(|(username, interpolator)| interpolator.sample().map(|state| (username.clone(), state))).collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{collections::VecDeque, rc::Rc};
+	use super::*;
+	use crate::{server::{RenetRequest, WorldServer, snapshot::SnapshotReceiver}, simulation::user::UserInput, test_utils};
+
+	/// Ticks between the server taking a snapshot and the client receiving it
+	const LATENCY: usize = 3;
+
+	fn rudder(rudder: Float) -> BoatInputs {
+		BoatInputs {
+			rudder_control: Some(rudder),
+			sheeting_angles: Vec::new()
+		}
+	}
+
+	/// Server with one user "alice" in steady wind, so that the only difference between client and server could come from the prediction itself
+	fn server() -> WorldServer {
+		let mut save = test_utils::save();
+		save.default_wind = WindGeneratorSaveState {
+			max_speed_variation: 0.0,
+			max_direction_variation: 0.0,
+			direction: 0.0,
+			..Default::default()
+		};
+		let sim = Simulation::from_resources(save, test_utils::resources(test_utils::open_water_map(1000))).unwrap();
+		let mut server = WorldServer::new("test".to_owned(), sim);
+		server.join("alice", None, test_utils::BOAT_TYPE_NAME).unwrap();
+		server
+	}
+
+	#[test]
+	fn reconcile_without_misprediction_makes_no_correction() {
+		let mut server = server();
+		let mut receiver = SnapshotReceiver::new();
+		let usernames = ["alice".to_owned()];
+		let (_, delta) = server.make_snapshot_deltas(&usernames).remove(0);
+		let (_, first) = receiver.receive(&delta).unwrap();
+		let own = &first.boats["alice"];
+		let mut client = ClientPrediction::new("alice".to_owned(), Boat::new(Rc::new(test_utils::boat_type()), own.boat.clone()), own.wind, server.time_step());
+		let mut snapshots_in_flight = VecDeque::new();
+		for tick in 0..300 {
+			let inputs = rudder(if tick < 150 {0.2} else {-0.3});
+			// Inputs arrive in time for the server tick they were predicted for, so the server never has to tick without one
+			for seq in client.step(server.time_step(), inputs.clone()) {
+				server.handle_request("alice", RenetRequest::Input {
+					seq,
+					input: UserInput {
+						boat: Some(inputs.clone()),
+						autopilot: None
+					}
+				}).unwrap();
+			}
+			server.step().unwrap();
+			snapshots_in_flight.push_back(server.make_snapshot_deltas(&usernames).remove(0).1);
+			if snapshots_in_flight.len() > LATENCY {
+				let (seq, snapshot) = receiver.receive(&snapshots_in_flight.pop_front().unwrap()).unwrap();
+				server.handle_request("alice", RenetRequest::AckSnapshot(seq)).unwrap();
+				assert_eq!(client.handle_snapshot(snapshot), 0.0, "Corrected at tick {}", tick);
+				assert_eq!(client.pending_input_count(), LATENCY);
+			}
+		}
+		// The boat has to have actually moved for this to mean anything
+		assert!((client.own_boat().state().pos.translation.vector - V2::new(50.0, 50.0)).norm() > 5.0);
+	}
+
+	#[test]
+	fn misprediction_is_replayed_from_server_state() {
+		let boat_type = Rc::new(test_utils::boat_type());
+		let start = BoatSaveState::new(test_utils::BOAT_TYPE_NAME.to_owned(), &boat_type, Iso::translation(50.0, 50.0));
+		let wind = V2::new(5.0, 0.0);
+		let time_step = 0.02;
+		let mut predicted = PredictedBoat::new(Boat::new(boat_type.clone(), start.clone()), time_step);
+		let mut server_boat = Boat::new(boat_type.clone(), start);
+		for seq in 1..=10 {
+			assert_eq!(predicted.predict(&wind, rudder(0.2)), seq);
+			if seq <= 5 {
+				server_boat.update(time_step, &wind, &rudder(0.2));
+			}
+		}
+		// Server agrees about tick 5
+		assert_eq!(predicted.reconcile(server_boat.state().clone(), 5, &wind), 0.0);
+		assert_eq!(predicted.pending_input_count(), 5);
+		// Something the client didn't know about pushed the boat 1m sideways on tick 6
+		server_boat.update(time_step, &wind, &rudder(0.2));
+		let mut pushed = server_boat.state().clone();
+		pushed.pos.translation.vector.y += 1.0;
+		let correction = predicted.reconcile(pushed.clone(), 6, &wind);
+		assert!((correction - 1.0).abs() < 0.05, "Correction of {}", correction);
+		assert_eq!(predicted.pending_input_count(), 4);
+		let mut expected = Boat::new(boat_type, pushed);
+		for _ in 0..4 {
+			expected.update(time_step, &wind, &rudder(0.2));
+		}
+		assert_eq!(QuantizedBoat::from_state(predicted.boat().state()), QuantizedBoat::from_state(expected.state()));
+	}
+}
@@ -0,0 +1,224 @@
+//! Race courses: a start line, an ordered list of marks and gates, and a finish line
+//! Courses are stored separately from maps so that one map can host many courses
+
+use crate::{prelude::*, geometry, resource_interface::{MapSave, ResourceRef}};
+use serde::{Deserialize, Serialize};
+
+/// Which side of the boat a mark must be on while rounding it
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum RoundingSide {
+	Port,
+	Starboard
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Line {
+	pub a: V2,
+	pub b: V2
+}
+
+impl Line {
+	pub fn new(a: V2, b: V2) -> Self {
+		Self {
+			a,
+			b
+		}
+	}
+	/// Line of `length` centered on `center`, perpendicular to `direction` (radians CCW from +X)
+	pub fn across(center: V2, direction: Float, length: Float) -> Self {
+		let half = V2::new(-direction.sin(), direction.cos()) * (length / 2.0);
+		Self::new(center - half, center + half)
+	}
+	pub fn center(&self) -> V2 {
+		(self.a + self.b) / 2.0
+	}
+	/// Whether moving from `from` to `to` crosses this line
+	pub fn is_crossed(&self, from: &V2, to: &V2) -> bool {
+		geometry::segments_intersect(from, to, &self.a, &self.b)
+	}
+	/// Which side of the line a point is on, + = left of a->b, - = right
+	pub fn side(&self, point: &V2) -> Float {
+		geometry::cross(&(self.b - self.a), &(point - self.a))
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum CourseElement {
+	/// Single mark, to be left on the `rounding` side
+	Mark {
+		pos: V2,
+		rounding: RoundingSide
+	},
+	/// Pair of marks to pass between, in either direction
+	Gate(Line)
+}
+
+impl CourseElement {
+	/// Point to aim for when sailing to this element
+	pub fn target(&self) -> V2 {
+		match self {
+			Self::Mark{pos, ..} => *pos,
+			Self::Gate(line) => line.center()
+		}
+	}
+	fn points(&self) -> Vec<V2> {
+		match self {
+			Self::Mark{pos, ..} => vec![*pos],
+			Self::Gate(line) => vec![line.a, line.b]
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CourseSave {
+	/// Map this course is laid out on
+	pub map_name: ResourceRef<MapSave>,
+	/// Boats start on the side away from the first mark and must cross towards it
+	pub start_line: Line,
+	/// Marks and gates in the order they must be sailed
+	pub marks: Vec<CourseElement>,
+	pub finish_line: Line,
+	/// Distance from a mark within which a boat is considered to be rounding it
+	#[serde(default = "default_mark_zone_radius")]
+	pub mark_zone_radius: Float
+}
+
+fn default_mark_zone_radius() -> Float {
+	20.0
+}
+
+impl CourseSave {
+	/// Sign of `Line::side()` for points on the pre-start side of the start line
+	pub fn pre_start_side(&self) -> Float {
+		let first_target = match self.marks.first() {
+			Some(element) => element.target(),
+			None => self.finish_line.center()
+		};
+		match self.start_line.side(&first_target) > 0.0 {
+			true => -1.0,
+			false => 1.0
+		}
+	}
+	/// Shortest distance to sail the course, straight from the start line through each mark or gate to the finish line, used for time-on-distance handicaps
+	pub fn length(&self) -> Float {
+		let mut points = vec![self.start_line.center()];
+		points.extend(self.marks.iter().map(CourseElement::target));
+		points.push(self.finish_line.center());
+		points.windows(2).map(|pair| (pair[1] - pair[0]).magnitude()).sum()
+	}
+	/// Windward-leeward course: a start line, then `laps` rounds of a windward mark and a leeward gate downwind of the start, finishing just downwind of the start line
+	/// `wind_from` is the direction the wind is coming from, radians CCW from +X
+	pub fn windward_leeward(map_name: String, start_center: V2, wind_from: Float, leg_length: Float, laps: usize) -> Self {
+		let upwind = V2::new(wind_from.cos(), wind_from.sin());
+		let line_length = leg_length / 5.0;
+		let mut marks = Vec::new();
+		for _ in 0..laps {
+			marks.push(CourseElement::Mark {
+				pos: start_center + upwind * leg_length,
+				rounding: RoundingSide::Port
+			});
+			marks.push(CourseElement::Gate(Line::across(start_center - upwind * line_length, wind_from, line_length / 2.0)));
+		}
+		Self {
+			map_name: ResourceRef::new(map_name),
+			start_line: Line::across(start_center, wind_from, line_length),
+			marks,
+			finish_line: Line::across(start_center - upwind * (line_length / 2.0), wind_from, line_length),
+			mark_zone_radius: default_mark_zone_radius()
+		}
+	}
+	/// Triangle course: windward mark, wing mark, leeward mark, all rounded to port, finishing at the start line
+	pub fn triangle(map_name: String, start_center: V2, wind_from: Float, leg_length: Float) -> Self {
+		let upwind = V2::new(wind_from.cos(), wind_from.sin());
+		// Wing mark is off to the right when looking upwind
+		let right = V2::new(upwind.y, -upwind.x);
+		let windward = start_center + upwind * leg_length;
+		let wing = start_center + upwind * (leg_length / 2.0) + right * (leg_length * (3.0 as Float).sqrt() / 2.0);
+		let start_line = Line::across(start_center, wind_from, leg_length / 5.0);
+		Self {
+			map_name: ResourceRef::new(map_name),
+			finish_line: start_line.clone(),
+			start_line,
+			marks: vec![
+				CourseElement::Mark{pos: windward, rounding: RoundingSide::Port},
+				CourseElement::Mark{pos: wing, rounding: RoundingSide::Port},
+				CourseElement::Mark{pos: start_center - upwind * (leg_length / 10.0), rounding: RoundingSide::Port}
+			],
+			mark_zone_radius: default_mark_zone_radius()
+		}
+	}
+	/// Human-readable list of everything wrong with this course, including marks on land or off the map
+	pub fn problems(&self) -> Vec<String> {
+		let map = match self.map_name.load() {
+			Ok(map) => map,
+			Err(e) => return vec![format!("Map: {}", e)]
+		};
+		let mut out = Vec::new();
+		let mut check_point = |description: String, point: &V2| {
+			if !map.contains(point) {
+				out.push(format!("{} is outside of the map", description));
+			}
+			else if map.is_land(point) {
+				out.push(format!("{} is on land", description));
+			}
+		};
+		for (name, line) in [("Start line", &self.start_line), ("Finish line", &self.finish_line)] {
+			check_point(format!("{} end A", name), &line.a);
+			check_point(format!("{} end B", name), &line.b);
+		}
+		for (i, element) in self.marks.iter().enumerate() {
+			for point in element.points() {
+				check_point(format!("Mark {}", i + 1), &point);
+			}
+		}
+		out
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn windward_leeward_marks_are_in_order_along_the_wind() {
+		let wind_from: Float = 1.0;
+		let upwind = V2::new(wind_from.cos(), wind_from.sin());
+		let start_center = V2::new(500.0, 500.0);
+		let course = CourseSave::windward_leeward("test_map".to_owned(), start_center, wind_from, 300.0, 2);
+		// Distance upwind of the start
+		let upwind_of_start = |point: V2| (point - start_center).dot(&upwind);
+		assert_eq!(course.marks.len(), 4);
+		for lap in course.marks.chunks(2) {
+			assert!(matches!(lap[0], CourseElement::Mark{..}));
+			assert!((upwind_of_start(lap[0].target()) - 300.0).abs() < 0.01);
+			assert!(matches!(lap[1], CourseElement::Gate(_)));
+			assert!(upwind_of_start(lap[1].target()) < 0.0, "Leeward gate is upwind of the start");
+		}
+		let finish = upwind_of_start(course.finish_line.center());
+		assert!(finish < 0.0 && finish > upwind_of_start(course.marks[1].target()), "Finish is between the leeward gate and the start");
+		// Boats start on the downwind side of the start line
+		assert!(course.start_line.side(&(start_center - upwind)) * course.pre_start_side() > 0.0);
+	}
+
+	#[test]
+	fn triangle_legs_are_equilateral() {
+		let wind_from: Float = 2.0;
+		let upwind = V2::new(wind_from.cos(), wind_from.sin());
+		let start_center = V2::new(500.0, 500.0);
+		let leg_length = 400.0;
+		let course = CourseSave::triangle("test_map".to_owned(), start_center, wind_from, leg_length);
+		assert_eq!(course.marks.len(), 3);
+		assert!(course.marks.iter().all(|element| matches!(element, CourseElement::Mark{rounding: RoundingSide::Port, ..})));
+		let (windward, wing, leeward) = (course.marks[0].target(), course.marks[1].target(), course.marks[2].target());
+		assert!((windward - (start_center + upwind * leg_length)).magnitude() < 0.01);
+		// Looking upwind, the wing mark is on the right (clockwise)
+		assert!(geometry::cross(&upwind, &(wing - start_center)) < 0.0);
+		for (a, b) in [(start_center, windward), (windward, wing), (wing, start_center)] {
+			assert!(((b - a).magnitude() - leg_length).abs() < 0.01, "Leg from {:?} to {:?} is {} long", a, b, (b - a).magnitude());
+		}
+		// Leeward mark is just downwind of the start, and boats finish where they started
+		assert!((leeward - (start_center - upwind * (leg_length / 10.0))).magnitude() < 0.01);
+		assert_eq!(course.finish_line, course.start_line);
+		assert!(course.start_line.side(&(start_center - upwind)) * course.pre_start_side() > 0.0);
+	}
+}
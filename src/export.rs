@@ -0,0 +1,190 @@
+//! Exports boat tracks as GPX and KML, so sessions can be inspected in standard chart software
+
+use std::fs;
+use crate::{prelude::*, resource_interface::GeoReference, simulation::TrackPoint};
+
+/// One boat's track
+pub struct ExportTrack<'a> {
+	pub username: &'a str,
+	pub boat_type: &'a str,
+	pub points: &'a [TrackPoint]
+}
+
+/// GPX 1.1, speed and course are written with Garmin's TrackPointExtension since plain GPX 1.1 has no fields for them
+pub fn gpx(geo: &GeoReference, tracks: &[ExportTrack]) -> String {
+	let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<gpx version=\"1.1\" creator=\"sailboat_simulator\" xmlns=\"http://www.topografix.com/GPX/1/1\" xmlns:gpxtpx=\"http://www.garmin.com/xmlschemas/TrackPointExtension/v2\">\n");
+	for track in tracks {
+		out.push_str(&format!("\t<trk>\n\t\t<name>{}</name>\n\t\t<type>{}</type>\n\t\t<trkseg>\n", xml_escape(track.username), xml_escape(track.boat_type)));
+		for point in track.points {
+			let (lat, lon) = geo.to_lat_lon(&point.pos);
+			out.push_str(&format!(
+				"\t\t\t<trkpt lat=\"{:.7}\" lon=\"{:.7}\"><time>{}</time><extensions><gpxtpx:TrackPointExtension><gpxtpx:speed>{:.2}</gpxtpx:speed><gpxtpx:course>{:.1}</gpxtpx:course></gpxtpx:TrackPointExtension></extensions></trkpt>\n",
+				lat, lon, iso_8601(point.date), point.speed as f64 * geo.scale, compass_bearing(point.heading)
+			));
+		}
+		out.push_str("\t\t</trkseg>\n\t</trk>\n");
+	}
+	out.push_str("</gpx>\n");
+	out
+}
+
+/// KML with one `gx:Track` per boat, heading is stored as the track's angles and speed as extended data
+pub fn kml(geo: &GeoReference, tracks: &[ExportTrack]) -> String {
+	let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<kml xmlns=\"http://www.opengis.net/kml/2.2\" xmlns:gx=\"http://www.google.com/kml/ext/2.2\">\n<Document>\n");
+	out.push_str("\t<Schema id=\"boat\"><gx:SimpleArrayField name=\"speed\" type=\"float\"><displayName>Speed (m/s)</displayName></gx:SimpleArrayField></Schema>\n");
+	for track in tracks {
+		out.push_str(&format!("\t<Placemark>\n\t\t<name>{}</name>\n\t\t<description>{}</description>\n\t\t<gx:Track>\n", xml_escape(track.username), xml_escape(track.boat_type)));
+		for point in track.points {
+			out.push_str(&format!("\t\t\t<when>{}</when>\n", iso_8601(point.date)));
+		}
+		for point in track.points {
+			let (lat, lon) = geo.to_lat_lon(&point.pos);
+			out.push_str(&format!("\t\t\t<gx:coord>{:.7} {:.7} 0</gx:coord>\n", lon, lat));
+		}
+		for point in track.points {
+			out.push_str(&format!("\t\t\t<gx:angles>{:.1} 0 0</gx:angles>\n", compass_bearing(point.heading)));
+		}
+		out.push_str("\t\t\t<ExtendedData><SchemaData schemaUrl=\"#boat\"><gx:SimpleArrayData name=\"speed\">\n");
+		for point in track.points {
+			out.push_str(&format!("\t\t\t\t<gx:value>{:.2}</gx:value>\n", point.speed as f64 * geo.scale));
+		}
+		out.push_str("\t\t\t</gx:SimpleArrayData></SchemaData></ExtendedData>\n\t\t</gx:Track>\n\t</Placemark>\n");
+	}
+	out.push_str("</Document>\n</kml>\n");
+	out
+}
+
+/// Writes GPX or KML depending on the extension of `path`
+pub fn write(path: &str, geo: &GeoReference, tracks: &[ExportTrack]) -> Result<(), String> {
+	let contents = if path.ends_with(".gpx") {
+		gpx(geo, tracks)
+	}
+	else if path.ends_with(".kml") {
+		kml(geo, tracks)
+	}
+	else {
+		return Err(format!("Unknown track file type \"{}\", must be .gpx or .kml", path));
+	};
+	to_string_err_with_message(fs::write(path, contents), "Could not write track file")
+}
+
+/// Heading in radians CCW from +X (east) to degrees clockwise from north
+fn compass_bearing(heading: Float) -> f64 {
+	(90.0 - heading.to_degrees() as f64).rem_euclid(360.0)
+}
+
+/// UTC date and time, ex: "2024-03-01T12:30:05Z"
+fn iso_8601(date: f64) -> String {
+	let seconds = date.floor() as i64;
+	let (days, time_of_day) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+	// Civil date from days since 1970-01-01, Howard Hinnant's algorithm
+	let z = days + 719_468;
+	let era = z.div_euclid(146_097);
+	let day_of_era = z.rem_euclid(146_097);
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let month_index = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+	let month = if month_index < 10 {month_index + 3} else {month_index - 9};
+	let year = year_of_era + era * 400 + if month <= 2 {1} else {0};
+	format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time_of_day / 3600, (time_of_day % 3600) / 60, time_of_day % 60)
+}
+
+fn xml_escape(s: &str) -> String {
+	s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn geo() -> GeoReference {
+		GeoReference {
+			origin_lat: 45.0,
+			origin_lon: -63.0,
+			scale: 2.0
+		}
+	}
+
+	fn points() -> Vec<TrackPoint> {
+		vec![
+			TrackPoint {
+				date: 1_704_067_199.9,
+				pos: V2::new(0.0, 0.0),
+				speed: 1.0,
+				heading: 0.0
+			},
+			TrackPoint {
+				date: 1_704_067_200.0,
+				pos: V2::new(1000.0, 500.0),
+				speed: 2.5,
+				heading: PI / 2.0
+			}
+		]
+	}
+
+	#[test]
+	fn iso_8601_dates() {
+		assert_eq!(iso_8601(0.0), "1970-01-01T00:00:00Z");
+		// Leap days
+		assert_eq!(iso_8601(951_782_400.0), "2000-02-29T00:00:00Z");
+		assert_eq!(iso_8601(1_709_164_805.5), "2024-02-29T00:00:05Z");
+		// 2100 isn't a leap year
+		assert_eq!(iso_8601(4_107_542_400.0 - 1.0), "2100-02-28T23:59:59Z");
+		assert_eq!(iso_8601(4_107_542_400.0), "2100-03-01T00:00:00Z");
+		// Year boundary, fractions of a second are dropped
+		assert_eq!(iso_8601(1_704_067_199.9), "2023-12-31T23:59:59Z");
+		assert_eq!(iso_8601(1_704_067_200.0), "2024-01-01T00:00:00Z");
+	}
+
+	#[test]
+	fn compass_bearings() {
+		assert!((compass_bearing(0.0) - 90.0).abs() < 1e-4);
+		assert!(compass_bearing(PI / 2.0).abs() < 1e-4);
+		assert!((compass_bearing(PI) - 270.0).abs() < 1e-4);
+		assert!((compass_bearing(-PI / 2.0) - 180.0).abs() < 1e-4);
+	}
+
+	#[test]
+	fn names_are_escaped() {
+		assert_eq!(xml_escape("<b>&\"bob\"'s"), "&lt;b&gt;&amp;&quot;bob&quot;&apos;s");
+		let points = points();
+		let tracks = [ExportTrack {
+			username: "<&\"",
+			boat_type: "a&b",
+			points: &points
+		}];
+		for out in [gpx(&geo(), &tracks), kml(&geo(), &tracks)] {
+			assert!(out.contains("<name>&lt;&amp;&quot;</name>"));
+			assert!(!out.contains("<&\""));
+		}
+	}
+
+	#[test]
+	fn two_point_track() {
+		let geo = geo();
+		let points = points();
+		let tracks = [ExportTrack {
+			username: "alice",
+			boat_type: "dinghy",
+			points: &points
+		}];
+		let gpx = gpx(&geo, &tracks);
+		let kml = kml(&geo, &tracks);
+		assert_eq!(gpx.matches("<trkpt ").count(), 2);
+		assert_eq!(kml.matches("<gx:coord>").count(), 2);
+		for point in &points {
+			let (lat, lon) = geo.to_lat_lon(&point.pos);
+			assert!(gpx.contains(&format!("<trkpt lat=\"{:.7}\" lon=\"{:.7}\"><time>{}</time>", lat, lon, iso_8601(point.date))));
+			// KML coordinates are longitude first
+			assert!(kml.contains(&format!("<gx:coord>{:.7} {:.7} 0</gx:coord>", lon, lat)));
+			assert!(kml.contains(&format!("<when>{}</when>", iso_8601(point.date))));
+		}
+		// North-east of the origin
+		let (lat, lon) = geo.to_lat_lon(&points[1].pos);
+		assert!(lat > geo.origin_lat && lon > geo.origin_lon);
+		// Speed is scaled to meters per second, heading is a compass bearing
+		assert!(gpx.contains("<gpxtpx:speed>5.00</gpxtpx:speed><gpxtpx:course>0.0</gpxtpx:course>"));
+		assert!(kml.contains("<gx:angles>90.0 0 0</gx:angles>"));
+	}
+}
@@ -1,11 +1,11 @@
 //! Generic references to any type of game "object"
 //! Copied from virtual_bike
 
-use std::marker::PhantomData;
-use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, hash::{Hash, Hasher}, marker::PhantomData};
+use serde::{Deserialize, Deserializer, Serialize, de::{self, MapAccess, Visitor}};
 
 /// Generic reference to things in the game
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, Hash)]
+#[derive(Serialize, Deserialize)]
 pub struct GenericRef<T> {
 	pub id: u64,
 	pub unique_name_opt: Option<String>,
@@ -13,6 +13,50 @@ pub struct GenericRef<T> {
 	_phantom: PhantomData<T>
 }
 
+// Implemented manually so that `T` doesn't need to implement them, it is only a marker
+impl<T> Clone for GenericRef<T> {
+	fn clone(&self) -> Self {
+		Self {
+			id: self.id,
+			unique_name_opt: self.unique_name_opt.clone(),
+			_phantom: PhantomData{}
+		}
+	}
+}
+
+impl<T> PartialEq for GenericRef<T> {
+	fn eq(&self, other: &Self) -> bool {
+		self.id == other.id && self.unique_name_opt == other.unique_name_opt
+	}
+}
+
+impl<T> Eq for GenericRef<T> {}
+
+impl<T> Hash for GenericRef<T> {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.id.hash(state);
+		self.unique_name_opt.hash(state);
+	}
+}
+
+impl<T> fmt::Display for GenericRef<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.unique_name_opt {
+			Some(name) => write!(f, "{} ({})", self.id, name),
+			None => write!(f, "{}", self.id)
+		}
+	}
+}
+
+impl<T> fmt::Debug for GenericRef<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("GenericRef").field("id", &self.id).field("unique_name_opt", &self.unique_name_opt).finish()
+	}
+}
+
+/// Marker for types which are kept in datasets parallel to datasets of `T`, with the same ids, ex: the state of each sail of a boat type
+pub trait SharesIds<T> {}
+
 impl<T> GenericRef<T> {
 	pub fn id(id: u64) -> Self {
 		Self {
@@ -41,11 +85,9 @@ impl<T> GenericRef<T> {
 			_phantom: PhantomData{}
 		}
 	}
-	pub fn to_string(&self) -> String {
-		match &self.unique_name_opt {
-			Some(name) => format!("{} ({})", self.id, name),
-			None => self.id.to_string()
-		}
+	/// Safe version of `into_another_type()`, only allowed between types whose datasets are declared to share ids with `SharesIds`
+	pub fn to_shared<T2: SharesIds<T>>(&self) -> GenericRef<T2> {
+		self.into_another_type()
 	}
 }
 
@@ -114,36 +156,251 @@ impl<T> Default for GenericQuery<T> {
 	}
 }
 
+/// List of items with unique ids (and unique names, for the ones that have them), with indices for fast lookup
+/// Only the items are saved, the indices are rebuilt when loading
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "GenericDatasetSave<T>")]
 pub struct GenericDataset<T> {
-	pub items: Vec<(GenericRef<T>, T)>
+	items: Vec<(GenericRef<T>, T)>,
+	#[serde(skip)]
+	id_index: HashMap<u64, usize>,
+	#[serde(skip)]
+	name_index: HashMap<String, usize>,
+	/// Id given to the next item inserted with `insert()`
+	#[serde(skip)]
+	next_id: u64
+}
+
+/// Saved form of `GenericDataset`, either the list of (reference, item) tuples it is serialized as, ex: `{"items": [[{"id": 0, "unique_name_opt": "main"}, {...}]]}`
+/// or, for hand-written files, a map from unique names to items which get ids in file order, ex: `{"main": {...}, "jib": {...}}`
+/// Non human-readable formats (bincode) only accept the list, since telling the two apart needs a self-describing format
+pub enum GenericDatasetSave<T> {
+	Items(Vec<(GenericRef<T>, T)>),
+	Named(Vec<(String, T)>)
+}
+
+#[derive(Deserialize)]
+struct ItemsSave<T> {
+	items: Vec<(GenericRef<T>, T)>
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HumanReadableSave<T> {
+	Items(ItemsSave<T>),
+	Named(NamedItems<T>)
+}
+
+/// Map from unique names to items, keeping the order of the file
+struct NamedItems<T>(Vec<(String, T)>);
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for NamedItems<T> {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		struct NamedItemsVisitor<T>(PhantomData<T>);
+		impl<'de, T: Deserialize<'de>> Visitor<'de> for NamedItemsVisitor<T> {
+			type Value = NamedItems<T>;
+			fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+				formatter.write_str("a map from unique names to items")
+			}
+			fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+				let mut out = Vec::new();
+				while let Some(entry) = map.next_entry::<String, T>()? {
+					out.push(entry);
+				}
+				Ok(NamedItems(out))
+			}
+		}
+		deserializer.deserialize_map(NamedItemsVisitor(PhantomData))
+	}
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for GenericDatasetSave<T> {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		if deserializer.is_human_readable() {
+			match HumanReadableSave::deserialize(deserializer).map_err(|_| de::Error::custom("dataset must be either {\"items\": [[reference, item], ...]} or {\"<unique name>\": item, ...}"))? {
+				HumanReadableSave::Items(save) => Ok(Self::Items(save.items)),
+				HumanReadableSave::Named(NamedItems(items)) => Ok(Self::Named(items))
+			}
+		}
+		else {
+			Ok(Self::Items(ItemsSave::deserialize(deserializer)?.items))
+		}
+	}
+}
+
+impl<T> TryFrom<GenericDatasetSave<T>> for GenericDataset<T> {
+	type Error = String;
+	fn try_from(save: GenericDatasetSave<T>) -> Result<Self, String> {
+		let mut out = Self::new();
+		match save {
+			GenericDatasetSave::Items(items) => for (ref_, item) in items {
+				out.insert_with_ref(ref_, item)?;
+			},
+			GenericDatasetSave::Named(items) => for (name, item) in items {
+				out.insert(Some(name), item)?;
+			}
+		}
+		Ok(out)
+	}
 }
 
 impl<T> GenericDataset<T> {
 	pub fn new() -> Self {
 		Self {
-			items: Vec::new()
+			items: Vec::new(),
+			id_index: HashMap::new(),
+			name_index: HashMap::new(),
+			next_id: 0
 		}
 	}
-	pub fn get_item_index_with_query(&self, query: &GenericQuery<T>) -> Option<usize> {
-		for (i, (ref_, _)) in self.items.iter().enumerate() {
-			if ref_.query_matches(query) {
-				return Some(i)
+	/// Adds an item with a new id, fails if the name is already used
+	pub fn insert(&mut self, unique_name_opt: Option<String>, item: T) -> Result<GenericRef<T>, String> {
+		let mut ref_ = GenericRef::id(self.next_id);
+		ref_.unique_name_opt = unique_name_opt;
+		self.insert_with_ref(ref_.clone(), item)?;
+		Ok(ref_)
+	}
+	/// Adds an item with a specific reference, fails if its id or name is already used
+	pub fn insert_with_ref(&mut self, ref_: GenericRef<T>, item: T) -> Result<(), String> {
+		if self.id_index.contains_key(&ref_.id) {
+			return Err(format!("Id {} is already used", ref_.id));
+		}
+		if let Some(name) = &ref_.unique_name_opt {
+			if self.name_index.contains_key(name) {
+				return Err(format!("Name \"{}\" is already used", name));
 			}
+			self.name_index.insert(name.clone(), self.items.len());
 		}
-		// Default
-		None
+		self.id_index.insert(ref_.id, self.items.len());
+		self.next_id = self.next_id.max(ref_.id.saturating_add(1));
+		self.items.push((ref_, item));
+		Ok(())
 	}
-	pub fn get_item_tuple(&self, query: &GenericQuery<T>) -> Option<(&GenericRef<T>, &T)> {
-		match self.get_item_index_with_query(query) {
-			Some(i) => Some((&self.items[i].0, &self.items[i].1)),
-			None => None
+	pub fn remove(&mut self, query: &GenericQuery<T>) -> Option<(GenericRef<T>, T)> {
+		let i = self.get_item_index_with_query(query)?;
+		let out = self.items.remove(i);
+		self.rebuild_indices();
+		Some(out)
+	}
+	/// Removes the most recently inserted item
+	pub fn pop(&mut self) -> Option<(GenericRef<T>, T)> {
+		let out = self.items.pop()?;
+		self.id_index.remove(&out.0.id);
+		if let Some(name) = &out.0.unique_name_opt {
+			self.name_index.remove(name);
 		}
+		Some(out)
 	}
-	pub fn get_item_id(&self, query: &GenericQuery<T>) -> Option<u64> {
-		match self.get_item_tuple(query) {
-			Some((ref_, _)) => Some(ref_.id),
-			None => None
+	fn rebuild_indices(&mut self) {
+		self.id_index = self.items.iter().enumerate().map(|(i, (ref_, _))| (ref_.id, i)).collect();
+		self.name_index = self.items.iter().enumerate().filter_map(|(i, (ref_, _))| ref_.unique_name_opt.clone().map(|name| (name, i))).collect();
+	}
+	pub fn get_item_index_with_query(&self, query: &GenericQuery<T>) -> Option<usize> {
+		match query {
+			GenericQuery::Id(id, _) => self.id_index.get(id).copied(),
+			GenericQuery::UniqueName(name, _) => self.name_index.get(name).copied()
 		}
 	}
-}
\ No newline at end of file
+	pub fn get_item_tuple(&self, query: &GenericQuery<T>) -> Option<(&GenericRef<T>, &T)> {
+		self.get_item_index_with_query(query).map(|i| (&self.items[i].0, &self.items[i].1))
+	}
+	pub fn get_item_id(&self, query: &GenericQuery<T>) -> Option<u64> {
+		self.get_item_tuple(query).map(|(ref_, _)| ref_.id)
+	}
+	pub fn get(&self, query: &GenericQuery<T>) -> Option<&T> {
+		self.get_item_tuple(query).map(|(_, item)| item)
+	}
+	pub fn get_mut(&mut self, query: &GenericQuery<T>) -> Option<&mut T> {
+		let i = self.get_item_index_with_query(query)?;
+		Some(&mut self.items[i].1)
+	}
+	/// In insertion order
+	pub fn iter(&self) -> std::slice::Iter<'_, (GenericRef<T>, T)> {
+		self.items.iter()
+	}
+	/// References can't be changed, so that the indices stay valid
+	pub fn iter_mut(&mut self) -> impl Iterator<Item = (&GenericRef<T>, &mut T)> {
+		self.items.iter_mut().map(|(ref_, item)| (&*ref_, item))
+	}
+	pub fn len(&self) -> usize {
+		self.items.len()
+	}
+	pub fn is_empty(&self) -> bool {
+		self.items.is_empty()
+	}
+}
+
+/// Indices are derived from the items, so only the items are compared
+impl<T: PartialEq> PartialEq for GenericDataset<T> {
+	fn eq(&self, other: &Self) -> bool {
+		self.items == other.items
+	}
+}
+
+impl<T> Default for GenericDataset<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<'a, T> IntoIterator for &'a GenericDataset<T> {
+	type Item = &'a (GenericRef<T>, T);
+	type IntoIter = std::slice::Iter<'a, (GenericRef<T>, T)>;
+	fn into_iter(self) -> Self::IntoIter {
+		self.items.iter()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{prelude::*, test_utils};
+
+	#[derive(Serialize, Deserialize, PartialEq, Debug)]
+	struct Item {
+		value: i32
+	}
+
+	const LIST_JSON: &str = r#"{"items": [[{"id": 0, "unique_name_opt": "main"}, {"value": 1}], [{"id": 1, "unique_name_opt": "jib"}, {"value": 2}]]}"#;
+	const NAMED_JSON: &str = r#"{"main": {"value": 1}, "jib": {"value": 2}}"#;
+
+	#[test]
+	fn list_and_named_formats_are_equal() {
+		let list: GenericDataset<Item> = serde_json::from_str(LIST_JSON).unwrap();
+		let named: GenericDataset<Item> = serde_json::from_str(NAMED_JSON).unwrap();
+		assert_eq!(list, named);
+		assert_eq!(named.get(&GenericQuery::unique_name("jib".to_owned())), Some(&Item{value: 2}));
+		assert_eq!(named.get_item_id(&GenericQuery::unique_name("main".to_owned())), Some(0));
+	}
+
+	#[test]
+	fn named_format_round_trips_as_list() {
+		let named: GenericDataset<Item> = serde_json::from_str(NAMED_JSON).unwrap();
+		let raw_string = serde_json::to_string(&named).unwrap();
+		assert!(raw_string.starts_with("{\"items\":"));
+		let reloaded: GenericDataset<Item> = serde_json::from_str(&raw_string).unwrap();
+		assert_eq!(named, reloaded);
+	}
+
+	#[test]
+	fn list_format_round_trips_through_bincode() {
+		let list: GenericDataset<Item> = serde_json::from_str(LIST_JSON).unwrap();
+		let reloaded: GenericDataset<Item> = bincode::deserialize(&bincode::serialize(&list).unwrap()).unwrap();
+		assert_eq!(list, reloaded);
+	}
+
+	#[test]
+	fn duplicate_names_are_rejected() {
+		assert!(serde_json::from_str::<GenericDataset<Item>>(r#"{"main": {"value": 1}, "main": {"value": 2}}"#).is_err());
+		assert!(serde_json::from_str::<GenericDataset<Item>>(r#"{"items": [[{"id": 0, "unique_name_opt": "main"}, {"value": 1}], [{"id": 1, "unique_name_opt": "main"}, {"value": 2}]]}"#).is_err());
+		assert!(serde_json::from_str::<GenericDataset<Item>>(r#"{"items": [[{"id": 0, "unique_name_opt": null}, {"value": 1}], [{"id": 0, "unique_name_opt": null}, {"value": 2}]]}"#).is_err());
+	}
+
+	#[test]
+	fn list_format_boat_type_still_loads() {
+		let boat_type: BoatType = serde_json::from_str(test_utils::BOAT_TYPE_JSON).unwrap();
+		assert_eq!(boat_type.sails.len(), 2);
+		assert_eq!(boat_type.sails.get_item_id(&GenericQuery::unique_name("jib".to_owned())), Some(1));
+		assert_eq!(boat_type.sails.get(&GenericQuery::id(0)).map(|sail| sail.area), Some(8.0));
+	}
+}
@@ -0,0 +1,182 @@
+//! 2D geometry helpers for coastlines and courses
+//! Polygons are lists of points which do NOT have to repeat the first point at the end
+
+use crate::prelude::*;
+
+/// Z component of the cross product of 2D vectors
+pub fn cross(a: &V2, b: &V2) -> Float {
+	a.x * b.y - a.y * b.x
+}
+
+/// Whether segments a1-a2 and b1-b2 cross each other, touching at an end point counts
+pub fn segments_intersect(a1: &V2, a2: &V2, b1: &V2, b2: &V2) -> bool {
+	segment_intersection(a1, a2, b1, b2).is_some()
+}
+
+/// Fraction along a1-a2 where it crosses b1-b2, if it does
+pub fn segment_intersection(a1: &V2, a2: &V2, b1: &V2, b2: &V2) -> Option<Float> {
+	let a = a2 - a1;
+	let b = b2 - b1;
+	let denominator = cross(&a, &b);
+	if denominator.abs() < EPSILON {
+		// Parallel, collinear overlaps are ignored
+		return None;
+	}
+	let diff = b1 - a1;
+	let t = cross(&diff, &b) / denominator;
+	let u = cross(&diff, &a) / denominator;
+	match (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u) {
+		true => Some(t),
+		false => None
+	}
+}
+
+/// Even-odd rule
+pub fn point_in_polygon(point: &V2, polygon: &[V2]) -> bool {
+	let mut inside = false;
+	for (a, b) in polygon_edges(polygon) {
+		if (a.y > point.y) != (b.y > point.y) {
+			let x_crossing = a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x);
+			if point.x < x_crossing {
+				inside = !inside;
+			}
+		}
+	}
+	inside
+}
+
+/// Iterator over every edge, including the one from the last point back to the first
+pub fn polygon_edges(polygon: &[V2]) -> impl Iterator<Item = (&V2, &V2)> {
+	polygon.iter().zip(polygon.iter().cycle().skip(1)).take(polygon.len())
+}
+
+/// Whether the segment a-b crosses the polygon's boundary or is entirely inside it
+pub fn segment_intersects_polygon(a: &V2, b: &V2, polygon: &[V2]) -> bool {
+	if point_in_polygon(a, polygon) || point_in_polygon(b, polygon) {
+		return true;
+	}
+	polygon_edges(polygon).any(|(p1, p2)| segments_intersect(a, b, p1, p2))
+}
+
+/// Positive if the points go CCW
+pub fn polygon_signed_area(polygon: &[V2]) -> Float {
+	polygon_edges(polygon).map(|(a, b)| cross(a, b)).sum::<Float>() / 2.0
+}
+
+/// Center of area, falls back to the average of the points for degenerate polygons
+pub fn polygon_centroid(polygon: &[V2]) -> V2 {
+	let area = polygon_signed_area(polygon);
+	if area.abs() < EPSILON {
+		return polygon.iter().sum::<V2>() / (polygon.len().max(1) as Float);
+	}
+	polygon_edges(polygon).map(|(a, b)| (a + b) * cross(a, b)).sum::<V2>() / (6.0 * area)
+}
+
+pub fn closest_point_on_segment(point: &V2, a: &V2, b: &V2) -> V2 {
+	let ab = b - a;
+	let len_squared = ab.magnitude_squared();
+	if len_squared < EPSILON {
+		return *a;
+	}
+	let t = ((point - a).dot(&ab) / len_squared).clamp(0.0, 1.0);
+	a + ab * t
+}
+
+/// Each vertex moved `distance` away from the inside of the polygon, along the average of its neighboring edges' normals
+pub fn offset_polygon_vertices(polygon: &[V2], distance: Float) -> Vec<V2> {
+	let n = polygon.len();
+	if n < 3 {
+		return polygon.to_vec();
+	}
+	// Outward normal of edge (a, b) is to the right for CCW polygons
+	let orientation: Float = match polygon_signed_area(polygon) >= 0.0 {
+		true => 1.0,
+		false => -1.0
+	};
+	let outward_normal = |a: &V2, b: &V2| -> V2 {
+		let edge = b - a;
+		match edge.magnitude() < EPSILON {
+			true => V2::zeros(),
+			false => V2::new(edge.y, -edge.x).normalize() * orientation
+		}
+	};
+	(0..n).map(|i| {
+		let prev = &polygon[(i + n - 1) % n];
+		let current = &polygon[i];
+		let next = &polygon[(i + 1) % n];
+		let normal_sum = outward_normal(prev, current) + outward_normal(current, next);
+		match normal_sum.magnitude() < EPSILON {
+			true => *current,
+			false => current + normal_sum.normalize() * distance
+		}
+	}).collect()
+}
+
+/// Douglas-Peucker simplification of an open line, every removed point is within `tolerance` of the result
+pub fn simplify_polyline(points: &[V2], tolerance: Float) -> Vec<V2> {
+	if points.len() < 3 {
+		return points.to_vec();
+	}
+	let (first, last) = (&points[0], &points[points.len() - 1]);
+	let (mut max_distance, mut max_i) = (0.0, 0);
+	for (i, point) in points.iter().enumerate().take(points.len() - 1).skip(1) {
+		let distance = (point - closest_point_on_segment(point, first, last)).magnitude();
+		if distance > max_distance {
+			max_distance = distance;
+			max_i = i;
+		}
+	}
+	if max_distance <= tolerance {
+		return vec![*first, *last];
+	}
+	let mut out = simplify_polyline(&points[..=max_i], tolerance);
+	out.pop();
+	out.extend(simplify_polyline(&points[max_i..], tolerance));
+	out
+}
+
+/// Douglas-Peucker simplification of a closed polygon, split at the first point and the point furthest from it
+/// May return less than 3 points if the whole polygon is smaller than `tolerance`
+pub fn simplify_polygon(polygon: &[V2], tolerance: Float) -> Vec<V2> {
+	if polygon.len() < 4 {
+		return polygon.to_vec();
+	}
+	let furthest = (1..polygon.len()).max_by(|a, b| (polygon[*a] - polygon[0]).magnitude().total_cmp(&(polygon[*b] - polygon[0]).magnitude())).unwrap_or(1);
+	let mut out = simplify_polyline(&polygon[..=furthest], tolerance);
+	out.pop();
+	let mut second_half = polygon[furthest..].to_vec();
+	second_half.push(polygon[0]);
+	out.extend(simplify_polyline(&second_half, tolerance));
+	out.pop();
+	out
+}
+
+/// A point inside `polygon` but outside all of `holes`, for placing labels
+/// Uses the centroid if it works, otherwise the middle of the widest inside span along a few horizontal lines
+pub fn interior_point(polygon: &[V2], holes: &[Vec<V2>]) -> V2 {
+	let is_inside = |point: &V2| point_in_polygon(point, polygon) && !holes.iter().any(|hole| point_in_polygon(point, hole));
+	let centroid = polygon_centroid(polygon);
+	if is_inside(&centroid) {
+		return centroid;
+	}
+	let min_y = polygon.iter().map(|point| point.y).fold(Float::INFINITY, Float::min);
+	let max_y = polygon.iter().map(|point| point.y).fold(Float::NEG_INFINITY, Float::max);
+	for fraction in [0.5, 0.25, 0.75, 0.125, 0.375, 0.625, 0.875] {
+		let y = min_y + (max_y - min_y) * fraction;
+		let mut crossings: Vec<Float> = Vec::new();
+		for ring in std::iter::once(polygon).chain(holes.iter().map(|hole| hole.as_slice())) {
+			for (a, b) in polygon_edges(ring) {
+				if (a.y > y) != (b.y > y) {
+					crossings.push(a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x));
+				}
+			}
+		}
+		crossings.sort_by(|a, b| a.total_cmp(b));
+		// Even-odd: the spans between crossing pairs are inside
+		let widest_opt = crossings.chunks_exact(2).max_by(|a, b| (a[1] - a[0]).total_cmp(&(b[1] - b[0])));
+		if let Some(span) = widest_opt {
+			return V2::new((span[0] + span[1]) / 2.0, y);
+		}
+	}
+	polygon.first().copied().unwrap_or(centroid)
+}
@@ -0,0 +1,258 @@
+//! Imports real coastlines from GeoJSON into maps
+//! Polygon and MultiPolygon features become landmasses, the first ring of each polygon is the coastline and the rest are holes
+
+use std::fs;
+use crate::{prelude::*, geometry, resource_interface::{MapSave, LandmassSave, GeoReference}, spatial_index::LandIndex};
+use serde_json::Value;
+
+// CONSTS
+const DEFAULT_LAND_COLOR: [u8; 4] = [110, 150, 80, 255];
+/// Spacing of the grid searched for water when the start or end is on land, as a fraction of the map's larger side
+const WATER_SEARCH_STEP: Float = 0.01;
+
+/// Polygon read from GeoJSON, rings of (latitude, longitude) without the repeated closing point
+struct GeoPolygon {
+	name_opt: Option<String>,
+	rings: Vec<Vec<(f64, f64)>>
+}
+
+/// Reads a GeoJSON file and builds a map around it
+/// If `geo_opt` is None, the map's origin is placed `margin` map units south-west of the land with 1 meter per map unit
+/// `tolerance` is the maximum distance (map units) coastlines are allowed to move when simplified
+pub fn map_from_geojson_file(path: &str, geo_opt: Option<GeoReference>, tolerance: Float, margin: Float) -> Result<MapSave, String> {
+	let raw_string = to_string_err_with_message(fs::read_to_string(path), "Could not read GeoJSON file")?;
+	map_from_geojson(&raw_string, geo_opt, tolerance, margin)
+}
+
+pub fn map_from_geojson(raw_string: &str, geo_opt: Option<GeoReference>, tolerance: Float, margin: Float) -> Result<MapSave, String> {
+	let polygons = parse_polygons(raw_string)?;
+	if polygons.is_empty() {
+		return Err("GeoJSON has no polygons".to_owned());
+	}
+	let geo = match geo_opt {
+		Some(geo) => geo,
+		None => {
+			let min_lat = polygons.iter().flat_map(|polygon| polygon.rings.iter().flatten()).map(|(lat, _)| *lat).fold(f64::INFINITY, f64::min);
+			let min_lon = polygons.iter().flat_map(|polygon| polygon.rings.iter().flatten()).map(|(_, lon)| *lon).fold(f64::INFINITY, f64::min);
+			let corner = GeoReference {
+				origin_lat: min_lat,
+				origin_lon: min_lon,
+				scale: 1.0
+			};
+			let (origin_lat, origin_lon) = corner.to_lat_lon(&V2::new(-margin, -margin));
+			GeoReference {
+				origin_lat,
+				origin_lon,
+				scale: 1.0
+			}
+		}
+	};
+	let landmasses = project_polygons(&polygons, &geo, tolerance);
+	let max_x = landmasses.iter().flat_map(|landmass| landmass.coastline.iter()).map(|point| point.x).fold(0.0, Float::max);
+	let max_y = landmasses.iter().flat_map(|landmass| landmass.coastline.iter()).map(|point| point.y).fold(0.0, Float::max);
+	let size = IntV2((max_x + margin).ceil() as Int, (max_y + margin).ceil() as Int);
+	let mut dataset = GenericDataset::new();
+	for landmass in landmasses {
+		// MultiPolygon parts share their feature's name, later ones get a number so names stay unique
+		let name_opt = landmass.name_and_representative_point_opt.as_ref().map(|(_, name)| {
+			let mut unique_name = name.clone();
+			let mut n = 2;
+			while dataset.get(&GenericQuery::unique_name(unique_name.clone())).is_some() {
+				unique_name = format!("{} {}", name, n);
+				n += 1;
+			}
+			unique_name
+		});
+		dataset.insert(name_opt, landmass)?;
+	}
+	let mut map = MapSave {
+		size,
+		global_default_start: V2::new(margin / 2.0, margin / 2.0),
+		end: V2::new(size.0 as Float - margin / 2.0, size.1 as Float - margin / 2.0),
+		landmasses: dataset,
+		geo_reference_opt: Some(geo)
+	};
+	// Corners are in the margin, but land can still reach them if the margin is 0 or `geo_opt` puts the origin on land
+	let land = LandIndex::new(&map);
+	map.global_default_start = nearest_water(&map, &land, map.global_default_start).ok_or("Map has no water for the start")?;
+	map.end = nearest_water(&map, &land, map.end).ok_or("Map has no water for the end")?;
+	Ok(map)
+}
+
+/// `preferred` if it is water, otherwise the nearest water on a grid over the map
+fn nearest_water(map: &MapSave, land: &LandIndex, preferred: V2) -> Option<V2> {
+	if map.contains(&preferred) && !land.point_in_land(&preferred) {
+		return Some(preferred);
+	}
+	let step = (map.size.0.max(map.size.1) as Float * WATER_SEARCH_STEP).max(1.0);
+	let columns = (map.size.0 as Float / step) as usize;
+	let rows = (map.size.1 as Float / step) as usize;
+	(0..=columns).flat_map(|x| (0..=rows).map(move |y| V2::new(x as Float * step, y as Float * step)))
+		.filter(|point| !land.point_in_land(point))
+		.min_by(|a, b| (a - preferred).magnitude().total_cmp(&(b - preferred).magnitude()))
+}
+
+/// Projects polygons into map coordinates and simplifies them, rings which become too small are dropped
+fn project_polygons(polygons: &[GeoPolygon], geo: &GeoReference, tolerance: Float) -> Vec<LandmassSave> {
+	let mut out = Vec::new();
+	for polygon in polygons {
+		let mut rings = polygon.rings.iter().map(|ring| {
+			let projected: Vec<V2> = ring.iter().map(|(lat, lon)| geo.from_lat_lon(*lat, *lon)).collect();
+			geometry::simplify_polygon(&projected, tolerance)
+		});
+		let coastline = match rings.next() {
+			Some(coastline) if coastline.len() >= 3 => coastline,
+			_ => continue
+		};
+		let holes: Vec<Vec<V2>> = rings.filter(|hole| hole.len() >= 3).collect();
+		out.push(LandmassSave {
+			name_and_representative_point_opt: polygon.name_opt.as_ref().map(|name| (geometry::interior_point(&coastline, &holes), name.clone())),
+			coastline,
+			color: DEFAULT_LAND_COLOR,
+			holes
+		});
+	}
+	out
+}
+
+/// Every Polygon and MultiPolygon in a FeatureCollection, Feature or bare geometry, other geometry types are ignored
+fn parse_polygons(raw_string: &str) -> Result<Vec<GeoPolygon>, String> {
+	let root: Value = to_string_err_with_message(serde_json::from_str(raw_string), "Could not parse GeoJSON")?;
+	let mut out = Vec::new();
+	match root["type"].as_str() {
+		Some("FeatureCollection") => {
+			let features = root["features"].as_array().ok_or("FeatureCollection has no features array")?;
+			for feature in features {
+				parse_feature(feature, &mut out)?;
+			}
+		},
+		Some("Feature") => parse_feature(&root, &mut out)?,
+		Some(_) => parse_geometry(&root, None, &mut out)?,
+		None => return Err("GeoJSON has no \"type\"".to_owned())
+	}
+	Ok(out)
+}
+
+fn parse_feature(feature: &Value, out: &mut Vec<GeoPolygon>) -> Result<(), String> {
+	let properties = &feature["properties"];
+	let name_opt = properties["name"].as_str().or(properties["NAME"].as_str()).map(|name| name.to_owned());
+	parse_geometry(&feature["geometry"], name_opt, out)
+}
+
+fn parse_geometry(geometry: &Value, name_opt: Option<String>, out: &mut Vec<GeoPolygon>) -> Result<(), String> {
+	match geometry["type"].as_str() {
+		Some("Polygon") => out.push(GeoPolygon {
+			name_opt,
+			rings: parse_rings(&geometry["coordinates"])?
+		}),
+		Some("MultiPolygon") => for polygon in geometry["coordinates"].as_array().ok_or("MultiPolygon has no coordinates")? {
+			out.push(GeoPolygon {
+				name_opt: name_opt.clone(),
+				rings: parse_rings(polygon)?
+			});
+		},
+		Some("GeometryCollection") => for child in geometry["geometries"].as_array().ok_or("GeometryCollection has no geometries")? {
+			parse_geometry(child, name_opt.clone(), out)?;
+		},
+		_ => {}
+	}
+	Ok(())
+}
+
+/// GeoJSON positions are [longitude, latitude], rings repeat their first position at the end
+fn parse_rings(coordinates: &Value) -> Result<Vec<Vec<(f64, f64)>>, String> {
+	let mut rings = Vec::new();
+	for ring in coordinates.as_array().ok_or("Polygon coordinates must be an array of rings")? {
+		let mut points = Vec::new();
+		for position in ring.as_array().ok_or("Polygon ring must be an array of positions")? {
+			match (position[0].as_f64(), position[1].as_f64()) {
+				(Some(lon), Some(lat)) => points.push((lat, lon)),
+				_ => return Err(format!("Invalid position {}", position))
+			}
+		}
+		if points.len() > 1 && points.first() == points.last() {
+			points.pop();
+		}
+		rings.push(points);
+	}
+	Ok(rings)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// FeatureCollection of one named Polygon, `rings` are (latitude, longitude) without the closing point
+	fn geojson(name: &str, rings: &[Vec<(f64, f64)>]) -> String {
+		let rings: Vec<Vec<[f64; 2]>> = rings.iter().map(|ring| ring.iter().chain(ring.first()).map(|(lat, lon)| [*lon, *lat]).collect()).collect();
+		serde_json::json!({
+			"type": "FeatureCollection",
+			"features": [{
+				"type": "Feature",
+				"properties": {"name": name},
+				"geometry": {"type": "Polygon", "coordinates": rings}
+			}]
+		}).to_string()
+	}
+
+	/// Square ring from `min` to `max` degrees, with `per_side` points along each side
+	fn square_ring(min: f64, max: f64, per_side: usize) -> Vec<(f64, f64)> {
+		let corners = [(min, min), (min, max), (max, max), (max, min)];
+		let mut out = Vec::new();
+		for i in 0..4 {
+			let (a, b) = (corners[i], corners[(i + 1) % 4]);
+			for j in 0..per_side {
+				let t = j as f64 / per_side as f64;
+				out.push((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t));
+			}
+		}
+		out
+	}
+
+	/// About 1.1km square with 50 points per side, every other one moved north by `wiggle` degrees
+	fn wiggly_square(wiggle: f64) -> Vec<(f64, f64)> {
+		square_ring(0.0, 0.01, 50).into_iter().enumerate().map(|(i, (lat, lon))| (lat + (i % 2) as f64 * wiggle, lon)).collect()
+	}
+
+	#[test]
+	fn straight_coastlines_are_simplified_to_their_corners() {
+		// Wiggle of about 0.5m
+		let map = map_from_geojson(&geojson("Square", &[wiggly_square(0.000_005)]), None, 5.0, 100.0).unwrap();
+		let (_, landmass) = map.landmasses.iter().next().unwrap();
+		assert_eq!(landmass.coastline.len(), 4);
+		assert!(map.landmasses.get(&GenericQuery::unique_name("Square".to_owned())).is_some());
+		// Wiggle of about 5m is more than the tolerance, so it is kept
+		let detailed = map_from_geojson(&geojson("Square", &[wiggly_square(0.000_05)]), None, 1.0, 100.0).unwrap();
+		assert!(detailed.landmasses.iter().next().unwrap().1.coastline.len() > 100);
+	}
+
+	#[test]
+	fn inner_rings_become_holes() {
+		let map = map_from_geojson(&geojson("Atoll", &[square_ring(0.0, 0.01, 1), square_ring(0.004, 0.006, 1)]), None, 1.0, 100.0).unwrap();
+		let (_, landmass) = map.landmasses.iter().next().unwrap();
+		assert_eq!(landmass.holes.len(), 1);
+		let geo = map.geo_reference_opt.clone().unwrap();
+		assert!(map.is_land(&geo.from_lat_lon(0.002, 0.002)));
+		assert!(!map.is_land(&geo.from_lat_lon(0.005, 0.005)), "Lagoon is water");
+		assert!(!map.is_land(&geo.from_lat_lon(0.012, 0.012)));
+		// The label isn't put in the lagoon
+		let (label_point, _) = landmass.name_and_representative_point_opt.clone().unwrap();
+		assert!(landmass.contains(&label_point));
+	}
+
+	#[test]
+	fn start_and_end_are_moved_off_land() {
+		let geo = GeoReference {
+			origin_lat: 0.005,
+			origin_lon: 0.005,
+			scale: 1.0
+		};
+		// Origin is in the middle of the island so the start corner is on land
+		let map = map_from_geojson(&geojson("Island", &[square_ring(0.0, 0.01, 1)]), Some(geo), 1.0, 0.0).unwrap();
+		assert!(map.landmasses.iter().next().unwrap().1.contains(&V2::new(1.0, 1.0)));
+		for point in [map.global_default_start, map.end] {
+			assert!(map.contains(&point));
+			assert!(!map.is_land(&point), "{:?} is on land", point);
+		}
+	}
+}
@@ -0,0 +1,162 @@
+//! Persistent leaderboards, one per map, recording every finished run
+
+use std::time::SystemTime;
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// CONSTS
+/// Times closer than this (seconds) are tied
+const TIE_TOLERANCE: Float = 0.01;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LeaderboardEntry {
+	pub username: String,
+	pub boat_type: String,
+	pub map_name: String,
+	pub course_name_opt: Option<String>,
+	/// Mean wind during the run, m/s and degrees CCW from +X (the direction it is blowing towards)
+	pub wind_speed_average: Float,
+	pub wind_direction: Float,
+	/// Elapsed time, seconds
+	pub time: Float,
+	/// Time after applying the boat type's handicap, if it has one
+	pub corrected_time_opt: Option<Float>,
+	/// Unix timestamp, seconds
+	pub date: u64
+}
+
+impl LeaderboardEntry {
+	/// Time used for ranking, falls back to elapsed time if `corrected` is requested but there is no corrected time
+	pub fn ranking_time(&self, corrected: bool) -> Float {
+		match (corrected, self.corrected_time_opt) {
+			(true, Some(corrected_time)) => corrected_time,
+			_ => self.time
+		}
+	}
+}
+
+/// Which entries to return from `LeaderboardSave::top()`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LeaderboardQuery {
+	/// Only entries for this boat type, all boat types if None
+	pub boat_type_opt: Option<String>,
+	/// Only entries for this course, all courses if None
+	pub course_name_opt: Option<String>,
+	/// Maximum number of entries
+	pub n: usize,
+	/// Rank by handicap-corrected time instead of elapsed time
+	pub corrected: bool
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RankedEntry {
+	/// 1 = first, tied entries share a rank and the next rank is skipped ("1224" ranking)
+	pub rank: usize,
+	pub entry: LeaderboardEntry
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LeaderboardSave {
+	pub entries: Vec<LeaderboardEntry>
+}
+
+impl LeaderboardSave {
+	pub fn record(&mut self, entry: LeaderboardEntry) {
+		self.entries.push(entry);
+	}
+	pub fn top(&self, query: &LeaderboardQuery) -> Vec<RankedEntry> {
+		let mut matching: Vec<&LeaderboardEntry> = self.entries.iter().filter(|entry| {
+			query.boat_type_opt.as_ref().is_none_or(|boat_type| &entry.boat_type == boat_type) &&
+			query.course_name_opt.as_ref().is_none_or(|course_name| entry.course_name_opt.as_ref() == Some(course_name))
+		}).collect();
+		// Earlier runs come first among ties
+		matching.sort_by(|a, b| a.ranking_time(query.corrected).total_cmp(&b.ranking_time(query.corrected)).then(a.date.cmp(&b.date)));
+		let mut out: Vec<RankedEntry> = Vec::new();
+		for (i, entry) in matching.into_iter().take(query.n).enumerate() {
+			let rank = match out.last() {
+				Some(prev) if (prev.entry.ranking_time(query.corrected) - entry.ranking_time(query.corrected)).abs() < TIE_TOLERANCE => prev.rank,
+				_ => i + 1
+			};
+			out.push(RankedEntry {
+				rank,
+				entry: entry.clone()
+			});
+		}
+		out
+	}
+}
+
+pub fn unix_time_now() -> u64 {
+	SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn entry(username: &str, boat_type: &str, course_name_opt: Option<&str>, time: Float, date: u64) -> LeaderboardEntry {
+		LeaderboardEntry {
+			username: username.to_owned(),
+			boat_type: boat_type.to_owned(),
+			map_name: "test_map".to_owned(),
+			course_name_opt: course_name_opt.map(str::to_owned),
+			wind_speed_average: 5.0,
+			wind_direction: 90.0,
+			time,
+			corrected_time_opt: None,
+			date
+		}
+	}
+
+	fn query(boat_type_opt: Option<&str>, course_name_opt: Option<&str>, n: usize) -> LeaderboardQuery {
+		LeaderboardQuery {
+			boat_type_opt: boat_type_opt.map(str::to_owned),
+			course_name_opt: course_name_opt.map(str::to_owned),
+			n,
+			corrected: false
+		}
+	}
+
+	fn names_and_ranks(ranked: &[RankedEntry]) -> Vec<(&str, usize)> {
+		ranked.iter().map(|ranked| (ranked.entry.username.as_str(), ranked.rank)).collect()
+	}
+
+	#[test]
+	fn ties_share_a_rank_and_skip_the_next() {
+		let leaderboard = LeaderboardSave {
+			entries: vec![
+				entry("carol", "dinghy", None, 12.0, 1),
+				entry("bob", "dinghy", None, 10.005, 2),
+				entry("alice", "dinghy", None, 10.0, 3),
+				entry("dave", "dinghy", None, 10.001, 1)
+			]
+		};
+		// Earlier runs come first among ties
+		assert_eq!(names_and_ranks(&leaderboard.top(&query(None, None, 10))), vec![("alice", 1), ("dave", 1), ("bob", 1), ("carol", 4)]);
+	}
+
+	#[test]
+	fn top_is_truncated_to_n() {
+		let leaderboard = LeaderboardSave {
+			entries: (0..10).map(|i| entry(&format!("user{}", i), "dinghy", None, 100.0 - i as Float, 0)).collect()
+		};
+		assert_eq!(names_and_ranks(&leaderboard.top(&query(None, None, 3))), vec![("user9", 1), ("user8", 2), ("user7", 3)]);
+		assert!(leaderboard.top(&query(None, None, 0)).is_empty());
+	}
+
+	#[test]
+	fn filters_by_boat_type_and_course() {
+		let leaderboard = LeaderboardSave {
+			entries: vec![
+				entry("alice", "dinghy", Some("triangle"), 10.0, 0),
+				entry("bob", "keelboat", Some("triangle"), 11.0, 0),
+				entry("carol", "dinghy", Some("windward_leeward"), 12.0, 0),
+				entry("dave", "dinghy", None, 13.0, 0)
+			]
+		};
+		assert_eq!(names_and_ranks(&leaderboard.top(&query(Some("dinghy"), None, 10))), vec![("alice", 1), ("carol", 2), ("dave", 3)]);
+		assert_eq!(names_and_ranks(&leaderboard.top(&query(None, Some("triangle"), 10))), vec![("alice", 1), ("bob", 2)]);
+		assert_eq!(names_and_ranks(&leaderboard.top(&query(Some("dinghy"), Some("windward_leeward"), 10))), vec![("carol", 1)]);
+		assert!(leaderboard.top(&query(Some("catamaran"), None, 10)).is_empty());
+	}
+}
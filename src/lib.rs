@@ -1,7 +1,6 @@
 //! Multiplayer sailboat simulation game, based off the old python version found at https://github.com/HDrizzle/sailboat_simulator_python
 
-use nalgebra::{Point3, Point2, Vector3, Vector2, point, Matrix, Const, ArrayStorage, OPoint, Translation, Isometry2, UnitQuaternion};
-use dialoguer;
+use nalgebra::{Point3, Point2, Vector3, Vector2, Translation, Isometry2};
 
 pub mod client;
 pub mod server;
@@ -10,6 +9,21 @@ pub mod generic_ref;
 pub mod intv2;
 pub mod autopilot;
 pub mod simulation;
+pub mod cli;
+pub mod wizard;
+pub mod geometry;
+pub mod polar;
+pub mod course;
+pub mod leaderboard;
+pub mod rng;
+pub mod replay;
+pub mod export;
+pub mod import;
+pub mod mapgen;
+pub mod spatial_index;
+pub mod raster;
+#[cfg(test)]
+mod test_utils;
 
 #[allow(unused)]
 pub mod prelude {
@@ -34,7 +48,7 @@ use super::*;
 	pub use crate::{
 		generic_ref::{GenericRef, GenericQuery, GenericDataset},
 		intv2::IntV2,
-		autopilot::{Autopilot, AutopilotInputs},
+		autopilot::{Autopilot, AutopilotSave, AutopilotInputs, AutopilotMode},
 		resource_interface,
 		server::WorldServer,
 		simulation::{Simulation, SimulationSave, SimulationClientSave, SimulationSettings, boat::{Boat, BoatSaveState, BoatType, BoatInputs}, wind::{WindGeneratorSaveState, Wind}}
@@ -50,6 +64,23 @@ use super::*;
 			UnitComplex::from_complex(iso1.rotation.complex() + iso2.rotation.complex())// TODO: test
 		)
 	}
+	/// Wraps an angle in radians to (-PI, PI]
+	pub fn wrap_angle(angle: Float) -> Float {
+		let wrapped = (angle + PI).rem_euclid(2.0 * PI) - PI;
+		match wrapped <= -PI {
+			true => wrapped + 2.0 * PI,
+			false => wrapped
+		}
+	}
+	/// Interpolate between 2 isometries, `t` = 0 -> iso1, `t` = 1 -> iso2
+	pub fn interpolate_iso(iso1: &Iso, iso2: &Iso, t: Float) -> Iso {
+		Iso::from_parts(
+			Translation{
+				vector: iso1.translation.vector.lerp(&iso2.translation.vector, t)
+			},
+			iso1.rotation.slerp(&iso2.rotation, t)
+		)
+	}
 	pub fn to_string_err<T, E: ToString>(result: Result<T, E>) -> Result<T, String> {
 		match result {
 			Ok(t) => Ok(t),
@@ -72,5 +103,9 @@ use super::*;
 
 // Ui main
 pub fn ui_main() {
-
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	if let Err(e) = cli::run(&args) {
+		eprintln!("{}", e);
+		std::process::exit(1);
+	}
 }
\ No newline at end of file
@@ -0,0 +1,241 @@
+//! Procedural map generator, makes maps with random islands from a seed
+//! Islands are kept at least a channel width away from each other, the map edges, the start and the end, so all the water is connected
+
+use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
+use crate::{prelude::*, geometry, rng::Rng, resource_interface::{MapSave, LandmassSave}, spatial_index::LandIndex};
+
+// CONSTS
+const LAND_COLOR: [u8; 4] = [110, 150, 80, 255];
+/// Points around each island's coastline
+const COASTLINE_POINTS: usize = 40;
+/// Number of sine waves added together to make coastlines rough
+const COASTLINE_HARMONICS: usize = 6;
+/// Random island placements to try for each island before giving up
+const ATTEMPTS_PER_ISLAND: usize = 50;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MapGenParams {
+	pub seed: u64,
+	pub size: IntV2,
+	/// Number of islands to try to place, fewer may fit
+	pub islands: usize,
+	/// 0 = round islands, 1 = very jagged
+	pub roughness: Float,
+	/// Minimum width of water between islands and around the edges of the map
+	pub channel_width: Float
+}
+
+impl Default for MapGenParams {
+	fn default() -> Self {
+		Self {
+			seed: 0,
+			size: IntV2(2000, 2000),
+			islands: 8,
+			roughness: 0.4,
+			channel_width: 60.0
+		}
+	}
+}
+
+impl MapGenParams {
+	/// Human-readable list of parameters which can't generate a map
+	pub fn problems(&self) -> Vec<String> {
+		let mut out = Vec::new();
+		if !(self.channel_width > 0.0 && self.channel_width.is_finite()) {
+			out.push(format!("Channel width must be positive, not {}", self.channel_width));
+		}
+		else if (self.size.0 as Float) < self.channel_width * 4.0 || (self.size.1 as Float) < self.channel_width * 4.0 {
+			out.push(format!("Map size {:?} is too small for channel width {}", self.size, self.channel_width));
+		}
+		if !(0.0..=1.0).contains(&self.roughness) {
+			out.push(format!("Roughness must be from 0 to 1, not {}", self.roughness));
+		}
+		out
+	}
+}
+
+/// Generates a map, the same parameters always give the same map
+pub fn generate(params: &MapGenParams) -> Result<MapSave, String> {
+	let problems = params.problems();
+	if !problems.is_empty() {
+		return Err(format!("Invalid map generator parameters:\n{}", problems.join("\n")));
+	}
+	let size = params.size.to_v2();
+	let mut rng = Rng::new(params.seed);
+	let start = V2::new(params.channel_width, params.channel_width);
+	let end = size - start;
+	let min_radius = params.channel_width;
+	let max_radius = (size.x.min(size.y) / 6.0).max(min_radius);
+	let mut islands: Vec<Vec<V2>> = Vec::new();
+	for _ in 0..params.islands {
+		for _ in 0..ATTEMPTS_PER_ISLAND {
+			let radius = rng.range(min_radius, max_radius);
+			let center = V2::new(rng.range(0.0, size.x), rng.range(0.0, size.y));
+			let coastline = island_coastline(&mut rng, center, radius, params.roughness);
+			if island_fits(&coastline, &islands, size, &[start, end], params.channel_width) {
+				islands.push(coastline);
+				break;
+			}
+		}
+	}
+	let mut map = MapSave {
+		size: params.size,
+		global_default_start: start,
+		end,
+		landmasses: GenericDataset::new(),
+		geo_reference_opt: None
+	};
+	for (i, coastline) in islands.into_iter().enumerate() {
+		let name = format!("Island {}", i + 1);
+		map.landmasses.insert(Some(name.clone()), LandmassSave {
+			name_and_representative_point_opt: Some((geometry::interior_point(&coastline, &[]), name)),
+			coastline,
+			color: LAND_COLOR,
+			holes: Vec::new()
+		})?;
+	}
+	// The spacing rules should make this impossible, but islands are removed until it holds just in case
+	while !water_connected(&map, &start, &end, params.channel_width / 2.0) {
+		if map.landmasses.pop().is_none() {
+			return Err("Start and end are not connected even without islands".to_owned());
+		}
+	}
+	Ok(map)
+}
+
+/// Star-shaped island, the radius varies around it by a sum of random sine waves
+fn island_coastline(rng: &mut Rng, center: V2, radius: Float, roughness: Float) -> Vec<V2> {
+	let harmonics: Vec<(Float, Float)> = (0..COASTLINE_HARMONICS).map(|k| {
+		// Higher frequencies get smaller amplitudes so the coastline stays mostly smooth
+		let amplitude = rng.range(0.0, 1.0) / (k + 1) as Float;
+		(amplitude, rng.range(0.0, 2.0 * PI))
+	}).collect();
+	let amplitude_sum: Float = harmonics.iter().map(|(amplitude, _)| amplitude).sum::<Float>().max(EPSILON);
+	(0..COASTLINE_POINTS).map(|i| {
+		let angle = 2.0 * PI * i as Float / COASTLINE_POINTS as Float;
+		let noise: Float = harmonics.iter().enumerate().map(|(k, (amplitude, phase))| amplitude * ((k + 2) as Float * angle + phase).sin()).sum::<Float>() / amplitude_sum;
+		let point_radius = radius * (1.0 + roughness * 0.6 * noise);
+		center + V2::new(angle.cos(), angle.sin()) * point_radius
+	}).collect()
+}
+
+/// Whether a new island is at least `channel_width` from the map edges, `keep_clear` points and every other island
+fn island_fits(coastline: &[V2], islands: &[Vec<V2>], size: V2, keep_clear: &[V2], channel_width: Float) -> bool {
+	let inside_edges = coastline.iter().all(|point| point.x >= channel_width && point.y >= channel_width && point.x <= size.x - channel_width && point.y <= size.y - channel_width);
+	let clear_of_points = keep_clear.iter().all(|point| !geometry::point_in_polygon(point, coastline) && polygon_distance(&[*point], coastline) >= channel_width);
+	inside_edges && clear_of_points && islands.iter().all(|other| {
+		!geometry::point_in_polygon(&coastline[0], other) && !geometry::point_in_polygon(&other[0], coastline) && polygon_distance(coastline, other) >= channel_width && polygon_distance(other, coastline) >= channel_width
+	})
+}
+
+/// Smallest distance from any of `points` to the edges of `polygon`
+fn polygon_distance(points: &[V2], polygon: &[V2]) -> Float {
+	points.iter().flat_map(|point| geometry::polygon_edges(polygon).map(move |(a, b)| (point - geometry::closest_point_on_segment(point, a, b)).magnitude())).fold(Float::INFINITY, Float::min)
+}
+
+/// Whether there is a path through water from `a` to `b`, searched on a grid with `cell_size` spacing
+/// Always false if `cell_size` isn't positive
+pub fn water_connected(map: &MapSave, a: &V2, b: &V2, cell_size: Float) -> bool {
+	if !(cell_size > 0.0 && cell_size.is_finite()) {
+		return false;
+	}
+	let columns = (map.size.0 as Float / cell_size).ceil() as usize + 1;
+	let rows = (map.size.1 as Float / cell_size).ceil() as usize + 1;
+	let cell_of = |point: &V2| -> (usize, usize) {
+		((point.x / cell_size).round().clamp(0.0, (columns - 1) as Float) as usize, (point.y / cell_size).round().clamp(0.0, (rows - 1) as Float) as usize)
+	};
+	let land = LandIndex::new(map);
+	let is_water = |(x, y): (usize, usize)| !land.point_in_land(&V2::new(x as Float * cell_size, y as Float * cell_size));
+	let (start, goal) = (cell_of(a), cell_of(b));
+	if !is_water(start) || !is_water(goal) {
+		return false;
+	}
+	let mut visited = vec![false; columns * rows];
+	let mut queue = VecDeque::from([start]);
+	visited[start.1 * columns + start.0] = true;
+	while let Some((x, y)) = queue.pop_front() {
+		if (x, y) == goal {
+			return true;
+		}
+		let neighbors = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+		for (nx, ny) in neighbors {
+			if nx >= columns || ny >= rows || visited[ny * columns + nx] {
+				continue;
+			}
+			visited[ny * columns + nx] = true;
+			if is_water((nx, ny)) {
+				queue.push_back((nx, ny));
+			}
+		}
+	}
+	false
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils;
+
+	fn params(seed: u64, islands: usize) -> MapGenParams {
+		MapGenParams {
+			seed,
+			size: IntV2(800, 800),
+			islands,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn same_seed_gives_same_map() {
+		let map = serde_json::to_string(&generate(&params(7, 6)).unwrap()).unwrap();
+		assert_eq!(serde_json::to_string(&generate(&params(7, 6)).unwrap()).unwrap(), map);
+		assert_ne!(serde_json::to_string(&generate(&params(8, 6)).unwrap()).unwrap(), map);
+	}
+
+	#[test]
+	fn start_and_end_are_connected_water() {
+		for seed in 0..3 {
+			for islands in [2, 10] {
+				let params = params(seed, islands);
+				let map = generate(&params).unwrap();
+				assert!(!map.landmasses.is_empty(), "No islands placed for seed {}", seed);
+				assert!(!map.is_land(&map.global_default_start) && !map.is_land(&map.end), "Start or end on land for seed {} with {} islands", seed, islands);
+				assert!(water_connected(&map, &map.global_default_start, &map.end, params.channel_width / 4.0), "Start and end not connected for seed {} with {} islands", seed, islands);
+				// Islands are spaced by the channel width
+				let coastlines: Vec<&Vec<V2>> = map.landmasses.iter().map(|(_, landmass)| &landmass.coastline).collect();
+				for (i, a) in coastlines.iter().enumerate() {
+					for b in &coastlines[i + 1..] {
+						assert!(polygon_distance(a, b) >= params.channel_width);
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn islands_closer_than_channel_width_dont_fit() {
+		let size = V2::new(1000.0, 1000.0);
+		let existing = vec![test_utils::rectangle(V2::new(100.0, 100.0), V2::new(200.0, 200.0))];
+		let island = |min_x: Float| test_utils::rectangle(V2::new(min_x, 100.0), V2::new(min_x + 100.0, 200.0));
+		assert!(island_fits(&island(260.0), &existing, size, &[], 50.0));
+		assert!(!island_fits(&island(240.0), &existing, size, &[], 50.0));
+		// Overlapping and inside
+		assert!(!island_fits(&island(150.0), &existing, size, &[], 50.0));
+		assert!(!island_fits(&test_utils::rectangle(V2::new(120.0, 120.0), V2::new(180.0, 180.0)), &existing, size, &[], 10.0));
+		// Too close to a point which has to stay clear or to the edge of the map
+		assert!(!island_fits(&island(260.0), &[], size, &[V2::new(400.0, 150.0)], 50.0));
+		assert!(!island_fits(&island(20.0), &[], size, &[], 50.0));
+	}
+
+	#[test]
+	fn invalid_params_are_rejected() {
+		for channel_width in [0.0, -10.0, Float::NAN, 300.0] {
+			assert!(generate(&MapGenParams {channel_width, ..params(0, 4)}).is_err(), "Accepted channel width {}", channel_width);
+		}
+		for roughness in [-0.1, 1.5, Float::NAN] {
+			assert!(generate(&MapGenParams {roughness, ..params(0, 4)}).is_err(), "Accepted roughness {}", roughness);
+		}
+		assert!(!water_connected(&test_utils::open_water_map(100), &V2::new(10.0, 10.0), &V2::new(90.0, 90.0), 0.0));
+	}
+}
@@ -0,0 +1,188 @@
+//! Polar diagram generation
+//! Runs the boat physics to steady state with the heading held fixed and the sails trimmed for maximum drive, over a grid of true wind angles and speeds.
+
+use std::{fs, rc::Rc};
+use crate::{prelude::*, autopilot};
+use serde::{Deserialize, Serialize};
+
+// CONSTS
+const TIME_STEP: Float = 0.05;
+/// Simulation gives up on reaching steady state after this long, in seconds
+const MAX_SETTLE_TIME: Float = 300.0;
+/// Speed change over one second below which the boat is considered to be at steady state
+const STEADY_STATE_TOLERANCE: Float = 0.001;
+
+/// Default true wind angles, degrees off the bow
+pub fn default_wind_angles() -> Vec<Float> {
+	(0..=36).map(|i| i as Float * 5.0).collect()
+}
+
+/// Default true wind speeds, m/s
+pub fn default_wind_speeds() -> Vec<Float> {
+	vec![2.0, 4.0, 6.0, 8.0, 10.0, 12.0]
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PolarPoint {
+	/// Speed through the water, m/s
+	pub boat_speed: Float,
+	/// Angle between the heading and the direction the boat actually moves, degrees
+	pub leeway: Float
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PolarTable {
+	pub boat_type: String,
+	/// Degrees off the bow
+	pub wind_angles: Vec<Float>,
+	/// m/s
+	pub wind_speeds: Vec<Float>,
+	/// Indexed by [wind angle index][wind speed index]
+	pub points: Vec<Vec<PolarPoint>>
+}
+
+impl PolarTable {
+	pub fn generate(boat_type_name: &str, boat_type: Rc<BoatType>, wind_angles: Vec<Float>, wind_speeds: Vec<Float>) -> Self {
+		let points = wind_angles.iter().map(|angle| wind_speeds.iter().map(|speed| steady_state(boat_type_name, &boat_type, *angle, *speed)).collect()).collect();
+		Self {
+			boat_type: boat_type_name.to_owned(),
+			wind_angles,
+			wind_speeds,
+			points
+		}
+	}
+	/// (True wind angle of the heading, angle of the course made good) with the best velocity made good upwind, degrees
+	/// These can be used for `BoatType::upwind_max_wind_angle` and `BoatType::upwind_max_total_leeway` instead of hand-entered values
+	pub fn best_upwind_angles(&self, wind_speed_index: usize) -> Option<(Float, Float)> {
+		self.best_vmg(wind_speed_index, |angle| angle.to_radians().cos())
+	}
+	/// True wind angle of the heading with the best velocity made good downwind, degrees
+	pub fn best_downwind_angle(&self, wind_speed_index: usize) -> Option<Float> {
+		self.best_vmg(wind_speed_index, |angle| -angle.to_radians().cos()).map(|(heading_angle, _)| heading_angle)
+	}
+	fn best_vmg<F: Fn(Float) -> Float>(&self, wind_speed_index: usize, vmg_factor: F) -> Option<(Float, Float)> {
+		let mut best: Option<(Float, Float, Float)> = None;
+		for (angle, row) in self.wind_angles.iter().zip(self.points.iter()) {
+			let point = row.get(wind_speed_index)?;
+			// The boat slips to leeward, away from the wind
+			let course_angle = angle + point.leeway;
+			let vmg = point.boat_speed * vmg_factor(course_angle);
+			if best.is_none_or(|(best_vmg, _, _)| vmg > best_vmg) {
+				best = Some((vmg, *angle, course_angle));
+			}
+		}
+		best.map(|(_, angle, course_angle)| (angle, course_angle))
+	}
+	pub fn to_csv(&self) -> String {
+		let mut out = String::from("twa");
+		for speed in &self.wind_speeds {
+			out += &format!(",{}", speed);
+		}
+		out.push('\n');
+		for (angle, row) in self.wind_angles.iter().zip(self.points.iter()) {
+			out += &angle.to_string();
+			for point in row {
+				out += &format!(",{:.3}", point.boat_speed);
+			}
+			out.push('\n');
+		}
+		out
+	}
+	/// Format is chosen by the extension, ".csv" or ".json"
+	pub fn write(&self, path: &str) -> Result<(), String> {
+		let contents = match path.ends_with(".csv") {
+			true => self.to_csv(),
+			false => to_string_err(serde_json::to_string_pretty(self))?
+		};
+		to_string_err_with_message(fs::write(path, contents), "Could not write polar table")
+	}
+}
+
+/// Runs the boat with its heading locked at `wind_angle` degrees off the wind until its speed stops changing
+fn steady_state(boat_type_name: &str, boat_type: &Rc<BoatType>, wind_angle: Float, wind_speed: Float) -> PolarPoint {
+	// Boat heads along +X, wind comes from `wind_angle` CCW of that, so the boat is on port tack
+	let wind_from = wind_angle.to_radians();
+	let wind: Wind = -V2::new(wind_from.cos(), wind_from.sin()) * wind_speed;
+	let mut boat = Boat::new(boat_type.clone(), BoatSaveState::new(boat_type_name.to_owned(), boat_type, Iso::identity()));
+	let mut time: Float = 0.0;
+	let mut prev_speed: Float = 0.0;
+	let mut time_since_check: Float = 0.0;
+	while time < MAX_SETTLE_TIME {
+		let trim = autopilot::optimal_sheeting_angles(boat.state(), boat_type, &wind);
+		boat.update(TIME_STEP, &wind, &trim);
+		// Hold the heading
+		let mut state = boat.state().clone();
+		state.pos.rotation = Iso::identity().rotation;
+		state.vel = Iso::translation(state.vel.translation.x, state.vel.translation.y);
+		boat.set_state(state);
+		time += TIME_STEP;
+		time_since_check += TIME_STEP;
+		if time_since_check >= 1.0 {
+			let speed = boat.state().vel.translation.vector.magnitude();
+			if (speed - prev_speed).abs() < STEADY_STATE_TOLERANCE {
+				break;
+			}
+			prev_speed = speed;
+			time_since_check = 0.0;
+		}
+	}
+	let vel = boat.state().vel.translation.vector;
+	PolarPoint {
+		boat_speed: vel.magnitude(),
+		// Positive when slipping CW (to starboard), which is to leeward on port tack
+		leeway: match vel.magnitude() < EPSILON {
+			true => 0.0,
+			false => (-vel.y.atan2(vel.x)).to_degrees()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils;
+
+	fn point(boat_speed: Float, leeway: Float) -> PolarPoint {
+		PolarPoint {
+			boat_speed,
+			leeway
+		}
+	}
+
+	/// One wind speed, made up speeds with a clear best angle upwind (45) and downwind (150)
+	fn table() -> PolarTable {
+		PolarTable {
+			boat_type: "test".to_owned(),
+			wind_angles: vec![0.0, 30.0, 45.0, 60.0, 90.0, 135.0, 150.0, 180.0],
+			wind_speeds: vec![5.0],
+			points: [0.0, 1.0, 1.5, 1.8, 2.0, 2.2, 2.0, 1.6].iter().map(|speed| vec![point(*speed, 0.0)]).collect()
+		}
+	}
+
+	#[test]
+	fn best_angles() {
+		let table = table();
+		assert_eq!(table.best_upwind_angles(0), Some((45.0, 45.0)));
+		assert_eq!(table.best_downwind_angle(0), Some(150.0));
+		assert_eq!(table.best_upwind_angles(1), None);
+	}
+
+	#[test]
+	fn leeway_is_included_in_upwind_course() {
+		let mut table = table();
+		// Fast but slipping a lot at 45, so 60 makes better progress
+		table.points[2][0] = point(1.5, 25.0);
+		assert_eq!(table.best_upwind_angles(0), Some((60.0, 60.0)));
+	}
+
+	#[test]
+	fn generated_table_is_faster_reaching_than_in_irons() {
+		let table = PolarTable::generate(test_utils::BOAT_TYPE_NAME, Rc::new(test_utils::boat_type()), vec![0.0, 90.0, 150.0], vec![5.0]);
+		let speeds: Vec<Float> = table.points.iter().map(|row| row[0].boat_speed).collect();
+		assert!(speeds.iter().all(|speed| speed.is_finite()));
+		assert!(speeds[1] > 0.5, "Beam reach speed {} is too slow", speeds[1]);
+		assert!(speeds[0] < speeds[1] / 2.0, "In irons speed {} is not much slower than beam reach speed {}", speeds[0], speeds[1]);
+		assert!(speeds[2] > speeds[0]);
+		assert_eq!(table.to_csv().lines().count(), 4);
+	}
+}
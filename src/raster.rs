@@ -0,0 +1,140 @@
+//! Rasterizes maps into land/water masks, for filled land rendering and minimap images
+
+use std::fs;
+use crate::{prelude::*, geometry, resource_interface::MapSave};
+
+// CONSTS
+pub const DEFAULT_WATER_COLOR: [u8; 4] = [16, 96, 160, 255];
+pub const DEFAULT_LAND_COLOR: [u8; 4] = [110, 150, 80, 255];
+
+/// Which landmass (if any) covers each pixel, row 0 is the top (+Y edge) of the map
+pub struct LandMask {
+	pub width: usize,
+	pub height: usize,
+	/// Map units per pixel
+	pub resolution: Float,
+	/// Index into the map's landmasses + 1 for each pixel, 0 = water, row-major
+	pixels: Vec<u32>
+}
+
+impl LandMask {
+	/// Scanline-fills every landmass (minus its holes), sampling at pixel centers
+	pub fn new(map: &MapSave, resolution: Float) -> Result<Self, String> {
+		if resolution <= 0.0 {
+			return Err(format!("Resolution must be positive, not {}", resolution));
+		}
+		let width = (map.size.0 as Float / resolution).ceil().max(1.0) as usize;
+		let height = (map.size.1 as Float / resolution).ceil().max(1.0) as usize;
+		let mut pixels = vec![0u32; width * height];
+		for (i, (_, landmass)) in map.landmasses.iter().enumerate() {
+			let rings: Vec<&Vec<V2>> = std::iter::once(&landmass.coastline).chain(landmass.holes.iter()).collect();
+			for row in 0..height {
+				let y = (height - row) as Float * resolution - resolution / 2.0;
+				let mut crossings: Vec<Float> = Vec::new();
+				for ring in &rings {
+					for (a, b) in geometry::polygon_edges(ring) {
+						if (a.y > y) != (b.y > y) {
+							crossings.push(a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x));
+						}
+					}
+				}
+				crossings.sort_by(|a, b| a.total_cmp(b));
+				// Even-odd: pixels with centers between each pair of crossings are inside
+				for span in crossings.chunks_exact(2) {
+					let first = ((span[0] / resolution - 0.5).ceil().max(0.0) as usize).min(width);
+					let last = (span[1] / resolution - 0.5).ceil().clamp(0.0, width as Float) as usize;
+					for pixel in &mut pixels[row * width + first..row * width + last.max(first)] {
+						*pixel = i as u32 + 1;
+					}
+				}
+			}
+		}
+		Ok(Self {
+			width,
+			height,
+			resolution,
+			pixels
+		})
+	}
+	/// Index of the landmass covering pixel (`x`, `y`), None for water or pixels off the image
+	pub fn landmass_at(&self, x: usize, y: usize) -> Option<usize> {
+		if x >= self.width {
+			return None;
+		}
+		match self.pixels.get(y * self.width + x) {
+			Some(0) | None => None,
+			Some(i) => Some(*i as usize - 1)
+		}
+	}
+	pub fn is_land(&self, x: usize, y: usize) -> bool {
+		self.landmass_at(x, y).is_some()
+	}
+	/// RGBA bytes, row-major, each landmass uses its own color unless its alpha is 0
+	pub fn to_rgba(&self, map: &MapSave, water_color: [u8; 4], land_color: [u8; 4]) -> Vec<u8> {
+		let landmass_colors: Vec<[u8; 4]> = map.landmasses.iter().map(|(_, landmass)| match landmass.color[3] {
+			0 => land_color,
+			_ => landmass.color
+		}).collect();
+		let mut out = Vec::with_capacity(self.pixels.len() * 4);
+		for pixel in &self.pixels {
+			out.extend_from_slice(&match pixel {
+				0 => water_color,
+				i => landmass_colors[*i as usize - 1]
+			});
+		}
+		out
+	}
+	/// PNG file contents, colors as in `to_rgba()`
+	pub fn to_png(&self, map: &MapSave, water_color: [u8; 4], land_color: [u8; 4]) -> Result<Vec<u8>, String> {
+		let mut out = Vec::new();
+		{
+			let mut encoder = png::Encoder::new(&mut out, self.width as u32, self.height as u32);
+			encoder.set_color(png::ColorType::Rgba);
+			encoder.set_depth(png::BitDepth::Eight);
+			let mut writer = to_string_err(encoder.write_header())?;
+			to_string_err(writer.write_image_data(&self.to_rgba(map, water_color, land_color)))?;
+		}
+		Ok(out)
+	}
+	pub fn write_png(&self, path: &str, map: &MapSave, water_color: [u8; 4], land_color: [u8; 4]) -> Result<(), String> {
+		to_string_err_with_message(fs::write(path, self.to_png(map, water_color, land_color)?), "Could not write PNG file")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils;
+
+	fn holed_island() -> MapSave {
+		test_utils::map_with_land(100, vec![test_utils::landmass(
+			test_utils::rectangle(V2::new(20.0, 20.0), V2::new(80.0, 80.0)),
+			vec![test_utils::rectangle(V2::new(40.0, 40.0), V2::new(60.0, 60.0))]
+		)])
+	}
+
+	#[test]
+	fn fills_island_around_hole() {
+		let mask = LandMask::new(&holed_island(), 1.0).unwrap();
+		assert_eq!((mask.width, mask.height), (100, 100));
+		// Row 50 samples y = 49.5, through the hole
+		assert!(!mask.is_land(10, 50));
+		assert_eq!(mask.landmass_at(30, 50), Some(0));
+		assert!(!mask.is_land(50, 50));
+		assert!(mask.is_land(70, 50));
+		assert!(!mask.is_land(90, 50));
+		// Row 25 samples y = 74.5, above the hole
+		assert!(mask.is_land(50, 25));
+		assert!(!mask.is_land(50, 90));
+		// Off the right edge doesn't wrap onto the next row
+		assert_eq!(mask.landmass_at(mask.width, 30), None);
+		assert_eq!(mask.landmass_at(0, mask.height), None);
+	}
+
+	#[test]
+	fn encodes_png() {
+		let map = holed_island();
+		let png = LandMask::new(&map, 2.0).unwrap().to_png(&map, DEFAULT_WATER_COLOR, DEFAULT_LAND_COLOR).unwrap();
+		assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+	}
+}
@@ -0,0 +1,269 @@
+//! Recording and playback of whole simulation sessions
+//! A replay is the simulation's save at the start of recording plus every step and input in order, so playing it back re-runs the exact same simulation
+
+use crate::{prelude::*, rng::Rng, simulation::{ResolvedResources, user::UserInput}};
+use serde::{Deserialize, Serialize};
+
+/// Anything that changes a simulation from outside
+#[derive(Serialize, Deserialize, Clone)]
+pub enum ReplayAction {
+	Step {
+		dt: Float,
+		/// `Simulation::state_hash()` after the step, only recorded in deterministic mode
+		state_hash_opt: Option<u64>
+	},
+	Join {
+		username: String,
+		boat_type: String
+	},
+	Input {
+		username: String,
+		input: UserInput
+	},
+	StartRace,
+	EndRace
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReplayEvent {
+	/// Simulation time when the action took effect, after the step for `ReplayAction::Step`
+	pub time: Float,
+	pub action: ReplayAction
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Replay {
+	/// Simulation save at the start of the recording
+	pub initial: SimulationSave,
+	/// Settings in use during the recording, the global settings may have changed since
+	pub settings: SimulationSettings,
+	/// Seed of the simulation's wind RNG at the start of the recording
+	pub wind_seed: u64,
+	/// Simulation time at the start of the recording
+	pub start_time: Float,
+	/// Unix timestamp (seconds) when the simulation time was 0, so that tracks get the original timestamps
+	#[serde(default)]
+	pub start_date: f64,
+	pub events: Vec<ReplayEvent>
+}
+
+impl Replay {
+	pub fn new(initial: SimulationSave, settings: SimulationSettings, start_time: Float, start_date: f64) -> Self {
+		Self {
+			wind_seed: initial.wind_rng.state(),
+			initial,
+			settings,
+			start_time,
+			start_date,
+			events: Vec::new()
+		}
+	}
+	pub fn record(&mut self, time: Float, action: ReplayAction) {
+		self.events.push(ReplayEvent {
+			time,
+			action
+		});
+	}
+	/// Time from the start to the end of the recording
+	pub fn duration(&self) -> Float {
+		match self.events.last() {
+			Some(event) => event.time - self.start_time,
+			None => 0.0
+		}
+	}
+	/// Loads every resource the initial save refers to
+	pub fn resolve(&self) -> Result<ResolvedResources, String> {
+		self.initial.resolve().map_err(|dangling| format!("{} dangling reference(s) in replay:\n{}", dangling.len(), dangling.join("\n")))
+	}
+	/// Runs the whole replay and returns the state hash after every step
+	pub fn state_hashes(&self, resources: &ResolvedResources) -> Result<Vec<u64>, String> {
+		let mut sim = self.load_initial(resources)?;
+		let mut out = Vec::new();
+		for event in &self.events {
+			let is_step = matches!(event.action, ReplayAction::Step{..});
+			apply_action(&mut sim, event.action.clone())?;
+			if is_step {
+				out.push(sim.state_hash());
+			}
+		}
+		Ok(out)
+	}
+	/// Plays the replay twice and checks that both runs, and the recording if it has hashes, agree after every step
+	/// Returns the number of steps checked
+	pub fn verify_determinism(&self, resources: &ResolvedResources) -> Result<usize, String> {
+		let first = self.state_hashes(resources)?;
+		let second = self.state_hashes(resources)?;
+		let recorded = self.events.iter().filter_map(|event| match event.action {
+			ReplayAction::Step{state_hash_opt, ..} => Some(state_hash_opt),
+			_ => None
+		});
+		for (tick, ((first_hash, second_hash), recorded_hash_opt)) in first.iter().zip(&second).zip(recorded).enumerate() {
+			if first_hash != second_hash {
+				return Err(format!("Playbacks diverge at step {}", tick));
+			}
+			if let Some(recorded_hash) = recorded_hash_opt {
+				if recorded_hash != *first_hash {
+					return Err(format!("Playback diverges from the recording at step {}", tick));
+				}
+			}
+		}
+		Ok(first.len())
+	}
+	/// New simulation in the state it was in when recording started
+	fn load_initial(&self, resources: &ResolvedResources) -> Result<Simulation, String> {
+		let mut save = self.initial.clone();
+		save.local_settings_opt = Some(self.settings.clone());
+		save.wind_rng = Rng::new(self.wind_seed);
+		let mut sim = Simulation::from_resources(save, resources.clone())?;
+		sim.set_time(self.start_time, self.start_date);
+		Ok(sim)
+	}
+}
+
+/// Plays back a replay, with seeking, pausing and speed control
+pub struct ReplayPlayer {
+	replay: Replay,
+	/// Kept so that seeking backwards doesn't load them again
+	resources: ResolvedResources,
+	sim: Simulation,
+	/// Index of the next event to apply
+	next_event: usize,
+	/// Time since the start of the replay which playback has reached
+	time: Float,
+	pub paused: bool,
+	/// Playback speed, 1 = real time
+	pub speed: Float
+}
+
+impl ReplayPlayer {
+	pub fn new(replay: Replay) -> Result<Self, String> {
+		let resources = replay.resolve()?;
+		let sim = replay.load_initial(&resources)?;
+		Ok(Self {
+			replay,
+			resources,
+			sim,
+			next_event: 0,
+			time: 0.0,
+			paused: false,
+			speed: 1.0
+		})
+	}
+	/// Advances playback by `real_dt` seconds of real time, scaled by the playback speed
+	pub fn update(&mut self, real_dt: Float) -> Result<(), String> {
+		if self.paused {
+			return Ok(());
+		}
+		self.seek(self.time + real_dt * self.speed)
+	}
+	/// Moves playback to `time` seconds since the start of the replay, seeking backwards re-runs the simulation from the start
+	pub fn seek(&mut self, time: Float) -> Result<(), String> {
+		let time = time.clamp(0.0, self.replay.duration());
+		if time < self.time {
+			self.sim = self.replay.load_initial(&self.resources)?;
+			self.next_event = 0;
+		}
+		let target_time = self.replay.start_time + time;
+		// Rounding could otherwise leave the last events un-applied
+		let at_end = time >= self.replay.duration();
+		while let Some(event) = self.replay.events.get(self.next_event) {
+			if event.time > target_time && !at_end {
+				break;
+			}
+			apply_action(&mut self.sim, event.action.clone())?;
+			self.next_event += 1;
+		}
+		self.time = time;
+		Ok(())
+	}
+	/// Runs the rest of the replay as fast as possible
+	pub fn run_to_end(&mut self) -> Result<(), String> {
+		self.seek(self.replay.duration())
+	}
+	pub fn is_finished(&self) -> bool {
+		self.next_event >= self.replay.events.len()
+	}
+	pub fn time(&self) -> Float {
+		self.time
+	}
+	pub fn sim(&self) -> &Simulation {
+		&self.sim
+	}
+	pub fn replay(&self) -> &Replay {
+		&self.replay
+	}
+}
+
+fn apply_action(sim: &mut Simulation, action: ReplayAction) -> Result<(), String> {
+	match action {
+		ReplayAction::Step{dt, ..} => sim.step(dt),
+		ReplayAction::Join{username, boat_type} => sim.add_client(&username, &boat_type)?,
+		// Inputs which failed while recording fail the same way now, the server only reported them to the user
+		ReplayAction::Input{username, input} => {
+			let _ = sim.apply_input(&username, input);
+		},
+		ReplayAction::StartRace => sim.start_race()?,
+		ReplayAction::EndRace => sim.end_race()
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils;
+
+	const STEPS: usize = 100;
+	const DT: Float = 0.02;
+
+	fn rudder_input(rudder: Float) -> UserInput {
+		UserInput {
+			boat: Some(BoatInputs {
+				rudder_control: Some(rudder),
+				sheeting_angles: vec![(GenericQuery::unique_name("main".to_owned()), 30.0)]
+			}),
+			autopilot: None
+		}
+	}
+
+	/// Two boats, one of which changes its rudder every 20 steps
+	fn record_session() -> (Replay, ResolvedResources) {
+		let resources = test_utils::resources(test_utils::open_water_map(1000));
+		let mut sim = Simulation::from_resources(test_utils::save(), resources.clone()).unwrap();
+		sim.start_recording();
+		sim.add_client("alice", test_utils::BOAT_TYPE_NAME).unwrap();
+		sim.add_client("bob", test_utils::BOAT_TYPE_NAME).unwrap();
+		for i in 0..STEPS {
+			if i % 20 == 0 {
+				sim.apply_input("alice", rudder_input(0.05 * (i / 20) as Float)).unwrap();
+			}
+			sim.step(DT);
+		}
+		(sim.stop_recording().unwrap(), resources)
+	}
+
+	#[test]
+	fn playbacks_match_each_other_and_the_recording() {
+		let (replay, resources) = record_session();
+		let first = replay.state_hashes(&resources).unwrap();
+		let second = replay.state_hashes(&resources).unwrap();
+		assert_eq!(first.len(), STEPS);
+		assert_eq!(first, second);
+		assert_eq!(replay.verify_determinism(&resources), Ok(STEPS));
+	}
+
+	#[test]
+	fn perturbed_input_changes_the_hashes() {
+		let (replay, resources) = record_session();
+		let original = replay.state_hashes(&resources).unwrap();
+		let mut perturbed = replay.clone();
+		// Third rudder change, applied before step 40
+		let event = perturbed.events.iter_mut().filter(|event| matches!(event.action, ReplayAction::Input{..})).nth(2).unwrap();
+		event.action = ReplayAction::Input{username: "alice".to_owned(), input: rudder_input(-0.3)};
+		let changed = perturbed.state_hashes(&resources).unwrap();
+		assert_eq!(original[..40], changed[..40]);
+		assert_ne!(original[40], changed[40]);
+		assert_ne!(original.last(), changed.last());
+		assert!(perturbed.verify_determinism(&resources).is_err());
+	}
+}
@@ -1,7 +1,7 @@
 //! Module which contains all the JSON-compatible types loaded from the disk
 
-use std::collections::HashMap;
-use crate::prelude::*;
+use std::{fmt, fs, io, marker::PhantomData};
+use crate::{prelude::*, geometry, course::CourseSave, leaderboard::LeaderboardSave, replay::Replay};
 use serde::{Deserialize, Serialize};
 use serde_json;
 
@@ -10,9 +10,13 @@ const RESOURCES_DIR: &str = "resources/";
 const MAPS_DIR: &str = "maps/";
 const BOAT_TYPES_DIR: &str = "boat_types/";
 const SIMULATIONS_DIR: &str = "simulations/";
+const COURSES_DIR: &str = "courses/";
+const LEADERBOARDS_DIR: &str = "leaderboards/";
+const REPLAYS_DIR: &str = "replays/";
 const GLOBAL_SETTINGS_FILE: &str = "global_settings";// No ".json" because it will be added automatically
 
 /// Meant for when a resource can't be loaded
+#[derive(Debug)]
 pub struct ResourceLoadError {
 	resource_type: ResourceType,
 	full_path: String,
@@ -21,6 +25,17 @@ pub struct ResourceLoadError {
 	error_type: ResourceLoadErrorType
 }
 
+impl fmt::Display for ResourceLoadError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Could not load {:?} \"{}\" from \"{}\": {:?}", self.resource_type, self.name, self.full_path, self.error_type)?;
+		if let Some(message) = &self.message {
+			write!(f, ", {}", message)?;
+		}
+		Ok(())
+	}
+}
+
+#[derive(Debug)]
 pub enum ResourceLoadErrorType {
 	CannotFindFile,
 	NoPermission,
@@ -28,10 +43,15 @@ pub enum ResourceLoadErrorType {
 }
 
 // Resource types
+#[derive(Debug, Clone, Copy)]
 pub enum ResourceType {
 	Map,
 	BoatType,
 	Simulation,
+	Course,
+	/// Named after the map it is for
+	Leaderboard,
+	Replay,
 	GlobalSettings
 }
 
@@ -39,19 +59,79 @@ pub enum Resource {
 	Map(MapSave),
 	BoatType(BoatType),
 	Simulation(SimulationSave),
+	Course(CourseSave),
+	Leaderboard(LeaderboardSave),
+	Replay(Replay),
 	GlobalSettings(Settings)
 }
 
 impl ResourceType {
+	/// Parses the plural names used on the command line, ex: "maps"
+	pub fn from_plural_name(name: &str) -> Option<Self> {
+		match name {
+			"maps" => Some(Self::Map),
+			"boats" => Some(Self::BoatType),
+			"sims" => Some(Self::Simulation),
+			"courses" => Some(Self::Course),
+			"leaderboards" => Some(Self::Leaderboard),
+			"replays" => Some(Self::Replay),
+			_ => None
+		}
+	}
 	pub fn encoding(&self) -> ResourceEncoding {
 		self.type_info().0
 	}
-	pub fn load(&self, name: &str) -> Result<Resource> {
+	/// Names of all resources of this type, sorted
+	pub fn list(&self) -> Result<Vec<String>, String> {
+		let type_info = self.type_info();
+		if !type_info.2 {
+			return Err(format!("{:?} is a standalone file, not a folder", self));
+		}
+		let extension = type_info.0.extension();
+		let mut out = Vec::new();
+		for entry in to_string_err_with_message(fs::read_dir(format!("{}{}", RESOURCES_DIR, type_info.1)), "Could not read resource folder")? {
+			let file_name = to_string_err(entry)?.file_name().to_string_lossy().to_string();
+			if let Some(name) = file_name.strip_suffix(&extension) {
+				out.push(name.to_owned());
+			}
+		}
+		out.sort();
+		Ok(out)
+	}
+	pub fn load(&self, name: &str) -> Result<Resource, ResourceLoadError> {
+		let full_path = self.full_path(name);
+		let make_error = |error_type: ResourceLoadErrorType, message: Option<String>| ResourceLoadError {
+			resource_type: *self,
+			full_path: full_path.clone(),
+			name: name.to_owned(),
+			message,
+			error_type
+		};
+		let raw_string: String = match fs::read_to_string(&full_path) {
+			Ok(s) => s,
+			Err(e) => return Err(match e.kind() {
+				io::ErrorKind::PermissionDenied => make_error(ResourceLoadErrorType::NoPermission, None),
+				_ => make_error(ResourceLoadErrorType::CannotFindFile, Some(e.to_string()))
+			})
+		};
+		let decoded: Result<Resource, serde_json::Error> = match self {
+			Self::Map => serde_json::from_str(&raw_string).map(Resource::Map),
+			Self::BoatType => serde_json::from_str(&raw_string).map(Resource::BoatType),
+			Self::Simulation => serde_json::from_str(&raw_string).map(Resource::Simulation),
+			Self::Course => serde_json::from_str(&raw_string).map(Resource::Course),
+			Self::Leaderboard => serde_json::from_str(&raw_string).map(Resource::Leaderboard),
+			Self::Replay => serde_json::from_str(&raw_string).map(Resource::Replay),
+			Self::GlobalSettings => serde_json::from_str(&raw_string).map(Resource::GlobalSettings)
+		};
+		decoded.map_err(|e| make_error(ResourceLoadErrorType::CannotDecode, Some(e.to_string())))
+	}
+	/// Path of the file for resource `name`, `name` is ignored for standalone files
+	pub fn full_path(&self, name: &str) -> String {
 		let type_info = self.type_info();
-		let full_path: String = match type_info.2 {
+		match type_info.2 {
 			true => format!("{}{}{}{}", RESOURCES_DIR, &type_info.1, name, type_info.0.extension()),// Within a folder
 			false => format!("{}{}{}", RESOURCES_DIR, &type_info.1, type_info.0.extension())// Standalone file (ex: settings), don't need `name`
-		};
+		}
 	}
 	/// (Resource encoding, path, whether the path is a folder (true) or file (false))
 	fn type_info(&self) -> (ResourceEncoding, &'static str, bool) {
@@ -59,6 +139,9 @@ impl ResourceType {
 			Self::Map => (ResourceEncoding::JSON, MAPS_DIR, true),
 			Self::BoatType => (ResourceEncoding::JSON, BOAT_TYPES_DIR, true),
 			Self::Simulation => (ResourceEncoding::JSON, SIMULATIONS_DIR, true),
+			Self::Course => (ResourceEncoding::JSON, COURSES_DIR, true),
+			Self::Leaderboard => (ResourceEncoding::JSON, LEADERBOARDS_DIR, true),
+			Self::Replay => (ResourceEncoding::JSON, REPLAYS_DIR, true),
 			Self::GlobalSettings => (ResourceEncoding::JSON, GLOBAL_SETTINGS_FILE, false),
 		}
 	}
@@ -78,16 +161,178 @@ impl ResourceEncoding {
 			Self::PNG => String::from(".png")
 		}
 	}
-	pub fn load<T>(&self) -> Result<T, String> {
-		// TODO
+}
+
+/// Name of another resource of type `T`, ex: the map of a simulation
+/// Saved as just the name, so files look the same as with a plain string
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ResourceRef<T> {
+	name: String,
+	#[serde(skip)]
+	_phantom: PhantomData<T>
+}
+
+impl<T> ResourceRef<T> {
+	pub fn new(name: String) -> Self {
+		Self {
+			name,
+			_phantom: PhantomData{}
+		}
+	}
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+}
+
+impl<T: LoadableResource> ResourceRef<T> {
+	pub fn load(&self) -> Result<T, String> {
+		T::load(&self.name)
+	}
+}
+
+// Implemented manually so that `T` doesn't need to implement them
+impl<T> Clone for ResourceRef<T> {
+	fn clone(&self) -> Self {
+		Self::new(self.name.clone())
+	}
+}
+
+impl<T> PartialEq for ResourceRef<T> {
+	fn eq(&self, other: &Self) -> bool {
+		self.name == other.name
+	}
+}
+
+impl<T> fmt::Debug for ResourceRef<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "ResourceRef({:?})", self.name)
+	}
+}
+
+impl<T> fmt::Display for ResourceRef<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.name)
+	}
+}
+
+/// Resources which can be referred to with `ResourceRef`
+pub trait LoadableResource: Sized {
+	fn load(name: &str) -> Result<Self, String>;
+}
+
+impl LoadableResource for MapSave {
+	fn load(name: &str) -> Result<Self, String> {
+		load_map(name)
+	}
+}
+
+impl LoadableResource for BoatType {
+	fn load(name: &str) -> Result<Self, String> {
+		load_boat_type(name)
+	}
+}
+
+impl LoadableResource for CourseSave {
+	fn load(name: &str) -> Result<Self, String> {
+		load_course(name)
 	}
 }
 
 // Loading
+pub fn load_map(name: &str) -> Result<MapSave, String> {
+	match to_string_err(ResourceType::Map.load(name))? {
+		Resource::Map(map) => Ok(map),
+		_ => unreachable!()
+	}
+}
+
 pub fn load_boat_type(name: &str) -> Result<BoatType, String> {
-	let raw_string: String = load_file_with_better_error(&(VEHICLES_DIR.to_owned() + name + "/" + VEHICLE_STATIC_JSON_FILENAME))?;
-	let vehicle: VehicleStatic = serde_json::from_str(&raw_string)?;
-	Ok(vehicle)
+	match to_string_err(ResourceType::BoatType.load(name))? {
+		Resource::BoatType(boat_type) => Ok(boat_type),
+		_ => unreachable!()
+	}
+}
+
+pub fn load_simulation(name: &str) -> Result<SimulationSave, String> {
+	match to_string_err(ResourceType::Simulation.load(name))? {
+		Resource::Simulation(sim) => Ok(sim),
+		_ => unreachable!()
+	}
+}
+
+pub fn load_course(name: &str) -> Result<CourseSave, String> {
+	match to_string_err(ResourceType::Course.load(name))? {
+		Resource::Course(course) => Ok(course),
+		_ => unreachable!()
+	}
+}
+
+/// A map with no leaderboard file yet has an empty leaderboard
+pub fn load_leaderboard(map_name: &str) -> Result<LeaderboardSave, String> {
+	match ResourceType::Leaderboard.load(map_name) {
+		Ok(Resource::Leaderboard(leaderboard)) => Ok(leaderboard),
+		Ok(_) => unreachable!(),
+		Err(ResourceLoadError{error_type: ResourceLoadErrorType::CannotFindFile, ..}) => Ok(LeaderboardSave::default()),
+		Err(e) => Err(e.to_string())
+	}
+}
+
+pub fn load_replay(name: &str) -> Result<Replay, String> {
+	match to_string_err(ResourceType::Replay.load(name))? {
+		Resource::Replay(replay) => Ok(replay),
+		_ => unreachable!()
+	}
+}
+
+pub fn load_settings() -> Result<Settings, String> {
+	match to_string_err(ResourceType::GlobalSettings.load(""))? {
+		Resource::GlobalSettings(settings) => Ok(settings),
+		_ => unreachable!()
+	}
+}
+
+// Saving
+pub fn save_simulation(name: &str, sim: &SimulationSave) -> Result<(), String> {
+	let raw_string = to_string_err(serde_json::to_string_pretty(sim))?;
+	to_string_err_with_message(fs::write(ResourceType::Simulation.full_path(name), raw_string), "Could not write simulation file")
+}
+
+pub fn save_leaderboard(map_name: &str, leaderboard: &LeaderboardSave) -> Result<(), String> {
+	let raw_string = to_string_err(serde_json::to_string_pretty(leaderboard))?;
+	to_string_err_with_message(fs::write(ResourceType::Leaderboard.full_path(map_name), raw_string), "Could not write leaderboard file")
+}
+
+pub fn save_replay(name: &str, replay: &Replay) -> Result<(), String> {
+	let raw_string = to_string_err(serde_json::to_string(replay))?;
+	to_string_err_with_message(fs::write(ResourceType::Replay.full_path(name), raw_string), "Could not write replay file")
+}
+
+pub fn save_map(name: &str, map: &MapSave) -> Result<(), String> {
+	let raw_string = to_string_err(serde_json::to_string_pretty(map))?;
+	to_string_err_with_message(fs::write(ResourceType::Map.full_path(name), raw_string), "Could not write map file")
+}
+
+pub fn save_course(name: &str, course: &CourseSave) -> Result<(), String> {
+	let raw_string = to_string_err(serde_json::to_string_pretty(course))?;
+	to_string_err_with_message(fs::write(ResourceType::Course.full_path(name), raw_string), "Could not write course file")
+}
+
+/// Loads a resource and checks that it makes sense, every problem found is listed in the error
+pub fn validate(resource_type: ResourceType, name: &str) -> Result<(), String> {
+	let problems: Vec<String> = match to_string_err(resource_type.load(name))? {
+		Resource::Map(map) => map.problems(),
+		Resource::BoatType(boat_type) => boat_type.problems(),
+		Resource::Simulation(sim) => sim.problems(),
+		Resource::Course(course) => course.problems(),
+		Resource::Leaderboard(_) => Vec::new(),
+		Resource::Replay(replay) => replay.initial.problems(),
+		Resource::GlobalSettings(settings) => settings.simulator.problems()
+	};
+	match problems.is_empty() {
+		true => Ok(()),
+		false => Err(format!("{} problem(s) found with {:?} \"{}\":\n{}", problems.len(), resource_type, name, problems.join("\n")))
+	}
 }
 
 #[derive(Serialize, Deserialize)]
@@ -151,20 +396,114 @@ pub struct UserContact {
 
 /// A map is sort of a template to base simulations off of, NOTE: A simulation is stored seperate from any map it may use
 /// Maps are read-only and are not written to when a simulation is saved
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct MapSave {
 	pub size: IntV2,
 	pub global_default_start: V2,
 	pub end: V2,
-	pub landmasses: GenericDataset<LandmassSave>
+	pub landmasses: GenericDataset<LandmassSave>,
+	/// Where the map is on Earth, needed to export tracks to chart software
+	#[serde(default)]
+	pub geo_reference_opt: Option<GeoReference>
 }
 
-#[derive(Serialize, Deserialize)]
+/// Places map coordinates on Earth, +X is east and +Y is north
+/// Uses an equirectangular projection around the origin, which is accurate enough for maps a few tens of kilometers across
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GeoReference {
+	/// Latitude of the map's (0, 0), degrees
+	pub origin_lat: f64,
+	/// Longitude of the map's (0, 0), degrees
+	pub origin_lon: f64,
+	/// Meters per map unit
+	pub scale: f64
+}
+
+impl GeoReference {
+	/// Mean Earth radius, meters
+	const EARTH_RADIUS: f64 = 6_371_000.0;
+	/// (latitude, longitude) in degrees
+	pub fn to_lat_lon(&self, point: &V2) -> (f64, f64) {
+		let north = point.y as f64 * self.scale;
+		let east = point.x as f64 * self.scale;
+		(
+			self.origin_lat + (north / Self::EARTH_RADIUS).to_degrees(),
+			self.origin_lon + (east / (Self::EARTH_RADIUS * self.origin_lat.to_radians().cos())).to_degrees()
+		)
+	}
+	/// Inverse of `to_lat_lon()`
+	pub fn from_lat_lon(&self, lat: f64, lon: f64) -> V2 {
+		let north = (lat - self.origin_lat).to_radians() * Self::EARTH_RADIUS;
+		let east = (lon - self.origin_lon).to_radians() * Self::EARTH_RADIUS * self.origin_lat.to_radians().cos();
+		V2::new((east / self.scale) as Float, (north / self.scale) as Float)
+	}
+}
+
+impl MapSave {
+	pub fn contains(&self, point: &V2) -> bool {
+		point.x >= 0.0 && point.y >= 0.0 && point.x <= self.size.0 as Float && point.y <= self.size.1 as Float
+	}
+	pub fn is_land(&self, point: &V2) -> bool {
+		self.landmasses.iter().any(|(_, landmass)| landmass.contains(point))
+	}
+	/// Whether a straight line from `a` to `b` touches any land
+	pub fn segment_hits_land(&self, a: &V2, b: &V2) -> bool {
+		self.landmasses.iter().any(|(_, landmass)| landmass.segment_hits(a, b))
+	}
+	/// Human-readable list of everything wrong with this map
+	pub fn problems(&self) -> Vec<String> {
+		let mut out = Vec::new();
+		if self.size.0 <= 0 || self.size.1 <= 0 {
+			out.push(format!("Size must be positive, not {:?}", self.size));
+		}
+		if !self.contains(&self.global_default_start) {
+			out.push("Default start is outside of the map".to_owned());
+		}
+		if !self.contains(&self.end) {
+			out.push("End is outside of the map".to_owned());
+		}
+		for (ref_, landmass) in &self.landmasses {
+			if landmass.coastline.len() < 3 {
+				out.push(format!("Landmass {} has less than 3 coastline points", ref_));
+			}
+			if landmass.holes.iter().any(|hole| hole.len() < 3) {
+				out.push(format!("Landmass {} has a hole with less than 3 points", ref_));
+			}
+		}
+		if let Some(geo_reference) = &self.geo_reference_opt {
+			if geo_reference.scale <= 0.0 {
+				out.push(format!("Geographic reference scale must be positive, not {}", geo_reference.scale));
+			}
+			if geo_reference.origin_lat.abs() >= 90.0 {
+				out.push(format!("Geographic reference latitude must be between -90 and 90, not {}", geo_reference.origin_lat));
+			}
+		}
+		out
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct LandmassSave {
 	/// List of coordinates to make up coastline, it does NOT have to have the same coordinate at the start and end points
 	pub coastline: Vec<V2>,
 	/// If it has a name, and where to display it on the map
 	pub name_and_representative_point_opt: Option<(V2, String)>,
-	/// Color of specific landass, overrides default color
-	pub color: [u8; 4]
+	/// Color of specific landass, overrides default color unless the alpha is 0
+	pub color: [u8; 4],
+	/// Lakes and other water inside the coastline
+	#[serde(default)]
+	pub holes: Vec<Vec<V2>>
+}
+
+impl LandmassSave {
+	pub fn contains(&self, point: &V2) -> bool {
+		geometry::point_in_polygon(point, &self.coastline) && !self.holes.iter().any(|hole| geometry::point_in_polygon(point, hole))
+	}
+	/// Whether a straight line from `a` to `b` touches this landmass, a line entirely inside a hole doesn't count
+	pub fn segment_hits(&self, a: &V2, b: &V2) -> bool {
+		if self.contains(a) || self.contains(b) {
+			return true;
+		}
+		std::iter::once(&self.coastline).chain(self.holes.iter()).any(|ring| geometry::polygon_edges(ring).any(|(p1, p2)| geometry::segments_intersect(a, b, p1, p2)))
+	}
 }
\ No newline at end of file
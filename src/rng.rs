@@ -0,0 +1,37 @@
+//! Small seeded pseudo-random number generator (SplitMix64), so that wind and generated content can be reproduced exactly from a seed
+
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct Rng {
+	state: u64
+}
+
+impl Rng {
+	pub fn new(seed: u64) -> Self {
+		Self {
+			state: seed
+		}
+	}
+	/// Current state, a new `Rng` created with this as its seed continues the same sequence
+	pub fn state(&self) -> u64 {
+		self.state
+	}
+	pub fn next_u64(&mut self) -> u64 {
+		self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		let mut z = self.state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		z ^ (z >> 31)
+	}
+	/// Uniform in [0, 1)
+	pub fn next_float(&mut self) -> Float {
+		// Top 24 bits fit exactly in an f32 mantissa
+		(self.next_u64() >> 40) as Float / (1u64 << 24) as Float
+	}
+	/// Uniform in [min, max)
+	pub fn range(&mut self, min: Float, max: Float) -> Float {
+		min + (max - min) * self.next_float()
+	}
+}
@@ -1,23 +1,384 @@
 //! Server module
 
-use crate::prelude::*;
+use std::{collections::{HashMap, VecDeque}, net::{SocketAddr, UdpSocket}, thread, time::{Duration, Instant, SystemTime}};
+use crate::{prelude::*, simulation::user::UserInput, leaderboard::{self, LeaderboardEntry, LeaderboardQuery, RankedEntry}};
 use serde::{Deserialize, Serialize};
-use rouille;// Simple HTTP Server
+use renet::{ClientId, ConnectionConfig, DefaultChannel, RenetServer, ServerEvent, transport::{NetcodeServerTransport, ServerAuthentication, ServerConfig}};
+
+pub mod spectator;
+pub mod snapshot;
+
+use spectator::SpectatorServer;
+use snapshot::{Snapshot, SnapshotDelta, SnapshotHistory};
+
+// CONSTS
+/// Has to match between client and server
+pub const PROTOCOL_ID: u64 = 0x5A11_B0A7;
+pub const DEFAULT_PORT: u16 = 5000;
+const MAX_CLIENTS: usize = 64;
+/// Time between server loop iterations
+const TICK_INTERVAL: Duration = Duration::from_millis(16);
+/// Most fixed time steps simulated in one server loop iteration, so that a stall doesn't turn into ever longer iterations spent catching up
+const MAX_STEPS_PER_TICK: usize = 8;
+/// Simulation step when the settings don't set a fixed one, the server always steps by a fixed amount so that clients can predict exactly
+const DEFAULT_TIME_STEP: Float = 1.0 / 60.0;
+/// Inputs queued for a user beyond this are applied straight away, so that a client running fast doesn't build up latency
+const MAX_QUEUED_INPUTS: usize = 32;
 
 /// All possible requests to the Renet server
 #[derive(Serialize, Deserialize)]
 pub enum RenetRequest {
-	
+	/// Must be the first request from a new connection, creates a boat of type `boat_type` if the user doesn't have one yet
+	Join {
+		username: String,
+		password: Option<String>,
+		boat_type: String
+	},
+	/// Input for one server tick, tagged with a sequence number so that the client can tell which of its predicted inputs are included in a snapshot
+	/// Boat inputs are queued and applied one per tick, autopilot inputs are applied immediately
+	Input {
+		seq: u64,
+		input: UserInput
+	},
+	/// Client has received the snapshot with this sequence number, so it can be used as a base for deltas
+	AckSnapshot(u64),
+	/// Starts the countdown for a new race on the simulation's course
+	StartRace,
+	/// Ends the current race, boats which haven't finished are recorded as DNF
+	EndRace,
+	/// Top runs on this simulation's map
+	QueryLeaderboard(LeaderboardQuery)
 }
 
 /// All possible responses from the Renet server
 #[derive(Serialize, Deserialize)]
 pub enum RenetResponse {
-	
+	/// `time_step` is the server's fixed step, clients have to predict with the same one
+	JoinAccepted {
+		time_step: Float
+	},
+	JoinRejected(String),
+	/// Authoritative state of every boat, relative to a previous snapshot
+	Snapshot(SnapshotDelta),
+	Leaderboard(Vec<RankedEntry>),
+	/// A request could not be handled
+	Error(String)
+}
+
+/// Binary encoding used for all Renet messages
+pub fn encode_message<T: Serialize>(message: &T) -> Result<Vec<u8>, String> {
+	to_string_err(bincode::serialize(message))
+}
+
+pub fn decode_message<T: for<'a> Deserialize<'a>>(bytes: &[u8]) -> Result<T, String> {
+	to_string_err(bincode::deserialize(bytes))
+}
+
+/// Dequantized state of every boat at one point in time, as seen by one specific client
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StateSnapshot {
+	/// Number of fixed steps the server had simulated
+	pub tick: u64,
+	/// Server simulation time
+	pub time: Float,
+	/// Latest input sequence number from the recipient which has been applied
+	pub last_processed_input: u64,
+	pub boats: HashMap<String, SnapshotBoat>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SnapshotBoat {
+	pub boat: BoatSaveState,
+	pub wind: Wind
 }
 
 /// Main server
 pub struct WorldServer {
-    sim_name: String,
-    sim: Simulation
-}
\ No newline at end of file
+	sim_name: String,
+	sim: Simulation,
+	spectator_opt: Option<SpectatorServer>,
+	/// Number of fixed steps simulated
+	tick: u64,
+	/// Boat inputs waiting for a tick, with their sequence numbers
+	input_queues: HashMap<String, VecDeque<(u64, BoatInputs)>>,
+	/// Latest input sequence number applied for each user
+	last_processed_inputs: HashMap<String, u64>,
+	/// Sequence number of the latest snapshot
+	snapshot_seq: u64,
+	/// Snapshots sent to each user, for delta compression
+	snapshot_histories: HashMap<String, SnapshotHistory>,
+	/// Number of race results which have been added to the leaderboard
+	recorded_results: usize
+}
+
+impl WorldServer {
+	pub fn new(sim_name: String, sim: Simulation) -> Self {
+		let recorded_results = sim.save_state().race_results.len();
+		Self {
+			sim_name,
+			sim,
+			spectator_opt: None,
+			tick: 0,
+			input_queues: HashMap::new(),
+			last_processed_inputs: HashMap::new(),
+			snapshot_seq: 0,
+			snapshot_histories: HashMap::new(),
+			recorded_results
+		}
+	}
+	/// Checks the password and adds the user to the simulation if they are new
+	pub fn join(&mut self, username: &str, password: Option<String>, boat_type: &str) -> Result<(), String> {
+		if let Some(sim_password) = &self.sim.save_state().password {
+			if password.as_ref() != Some(sim_password) {
+				return Err("Incorrect password".to_owned());
+			}
+		}
+		if username.is_empty() {
+			return Err("Username cannot be empty".to_owned());
+		}
+		self.sim.add_client(username, boat_type)
+	}
+	/// Returns the response to send back, if there is one
+	pub fn handle_request(&mut self, username: &str, request: RenetRequest) -> Result<Option<RenetResponse>, String> {
+		match request {
+			RenetRequest::Join{..} => Err(format!("User \"{}\" has already joined", username)),
+			RenetRequest::Input{seq, input} => {
+				// Inputs can arrive out of order on unreliable channels, old ones are dropped
+				let queue = self.input_queues.entry(username.to_owned()).or_default();
+				let last_seq = queue.back().map(|(seq, _)| *seq).unwrap_or(0).max(self.last_processed_inputs.get(username).copied().unwrap_or(0));
+				if seq <= last_seq {
+					return Ok(None);
+				}
+				queue.push_back((seq, input.boat.unwrap_or_default()));
+				if queue.len() > MAX_QUEUED_INPUTS {
+					self.apply_next_input(username)?;
+				}
+				if input.autopilot.is_some() {
+					self.sim.apply_input(username, UserInput {
+						boat: None,
+						autopilot: input.autopilot
+					})?;
+				}
+				Ok(None)
+			},
+			RenetRequest::AckSnapshot(seq) => {
+				self.snapshot_histories.entry(username.to_owned()).or_default().ack(seq);
+				Ok(None)
+			},
+			RenetRequest::StartRace => {
+				self.sim.start_race()?;
+				Ok(None)
+			},
+			RenetRequest::EndRace => {
+				self.sim.end_race();
+				Ok(None)
+			},
+			RenetRequest::QueryLeaderboard(query) => {
+				let leaderboard = resource_interface::load_leaderboard(self.sim.save_state().map_name.name())?;
+				Ok(Some(RenetResponse::Leaderboard(leaderboard.top(&query))))
+			}
+		}
+	}
+	/// Step used for every tick
+	pub fn time_step(&self) -> Float {
+		self.sim.settings().fixed_time_step_opt.unwrap_or(DEFAULT_TIME_STEP)
+	}
+	/// Applies the next queued input of every user and simulates one tick
+	/// Users without a queued input keep their previous one
+	pub fn step(&mut self) -> Result<(), String> {
+		let usernames: Vec<String> = self.input_queues.keys().cloned().collect();
+		for username in usernames {
+			self.apply_next_input(&username)?;
+		}
+		self.sim.step(self.time_step());
+		self.tick += 1;
+		Ok(())
+	}
+	fn apply_next_input(&mut self, username: &str) -> Result<(), String> {
+		let (seq, inputs) = match self.input_queues.get_mut(username).and_then(|queue| queue.pop_front()) {
+			Some(next) => next,
+			None => return Ok(())
+		};
+		self.last_processed_inputs.insert(username.to_owned(), seq);
+		self.sim.apply_input(username, UserInput {
+			boat: Some(inputs),
+			autopilot: None
+		})
+	}
+	/// Adds every finisher of races which have ended since the last call to the map's leaderboard
+	pub fn record_new_results(&mut self) -> Result<(), String> {
+		let save = self.sim.save_state();
+		if save.race_results.len() <= self.recorded_results {
+			return Ok(());
+		}
+		let mut leaderboard = resource_interface::load_leaderboard(save.map_name.name())?;
+		for result in &save.race_results[self.recorded_results..] {
+			print!("{}", result);
+			for entry in &result.entries {
+				let (time, wind) = match (entry.elapsed_time_opt, &entry.mean_wind_opt) {
+					(Some(time), Some(wind)) => (time, wind),
+					_ => continue
+				};
+				leaderboard.record(LeaderboardEntry {
+					username: entry.username.clone(),
+					boat_type: entry.boat_type.clone(),
+					map_name: save.map_name.name().to_owned(),
+					course_name_opt: Some(result.course_name.clone()),
+					wind_speed_average: wind.speed,
+					wind_direction: wind.direction,
+					time,
+					corrected_time_opt: entry.corrected_time_opt,
+					date: result.date
+				});
+			}
+		}
+		resource_interface::save_leaderboard(save.map_name.name(), &leaderboard)?;
+		self.recorded_results = save.race_results.len();
+		Ok(())
+	}
+	/// Takes a new snapshot and returns the delta to send to each of `usernames`
+	pub fn make_snapshot_deltas(&mut self, usernames: &[String]) -> Vec<(String, SnapshotDelta)> {
+		self.snapshot_seq += 1;
+		let snapshot = Snapshot::from_sim(self.snapshot_seq, self.tick, &self.sim);
+		let mut out = Vec::new();
+		for username in usernames {
+			let last_processed_input = self.last_processed_inputs.get(username).copied().unwrap_or(0);
+			let history = self.snapshot_histories.entry(username.clone()).or_default();
+			out.push((username.clone(), history.make_delta(snapshot.clone(), &self.sim, last_processed_input)));
+		}
+		out
+	}
+	/// Forgets everything about a disconnected user's connection
+	pub fn remove_connection(&mut self, username: &str) {
+		self.input_queues.remove(username);
+		self.last_processed_inputs.remove(username);
+		self.snapshot_histories.remove(username);
+	}
+	/// Starts the HTTP spectator view on `port`, land is filled in unless the GUI settings turn it off
+	pub fn start_spectator(&mut self, port: u16) -> Result<(), String> {
+		let map = self.sim.map();
+		self.spectator_opt = Some(SpectatorServer::start(port, map, spectator::land_image(map)?)?);
+		Ok(())
+	}
+	/// Pushes the current simulation state to the spectator view, if it is running
+	pub fn update_spectator(&self) -> Result<(), String> {
+		match &self.spectator_opt {
+			Some(spectator) => spectator.update(&self.sim),
+			None => Ok(())
+		}
+	}
+	/// Main server loop, runs until there is an error
+	/// If the simulation settings say so, the simulation is saved when this returns
+	pub fn run(&mut self, port: u16) -> Result<(), String> {
+		if let Some(spectator_port) = self.sim.settings().spectator_port_opt {
+			self.start_spectator(spectator_port)?;
+			println!("Spectator view on port {}", spectator_port);
+		}
+		if self.sim.settings().record_replays {
+			self.sim.start_recording();
+		}
+		let result = self.run_network(port);
+		if let Some(replay) = self.sim.stop_recording() {
+			let replay_name = format!("{}_{}", self.sim_name, leaderboard::unix_time_now());
+			resource_interface::save_replay(&replay_name, &replay)?;
+			println!("Saved replay \"{}\"", replay_name);
+		}
+		if self.sim.settings().save_sims {
+			resource_interface::save_simulation(&self.sim_name, self.sim.save_state())?;
+			println!("Saved simulation \"{}\"", self.sim_name);
+		}
+		result
+	}
+	fn run_network(&mut self, port: u16) -> Result<(), String> {
+		let public_addr = SocketAddr::new(to_string_err(local_ip_address::local_ip())?, port);
+		let socket = to_string_err(UdpSocket::bind(SocketAddr::new([0, 0, 0, 0].into(), port)))?;
+		let server_config = ServerConfig {
+			current_time: to_string_err(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH))?,
+			max_clients: MAX_CLIENTS,
+			protocol_id: PROTOCOL_ID,
+			public_addresses: vec![public_addr],
+			authentication: ServerAuthentication::Unsecure
+		};
+		let mut transport = to_string_err(NetcodeServerTransport::new(server_config, socket))?;
+		let mut server = RenetServer::new(ConnectionConfig::default());
+		let mut connections: HashMap<ClientId, String> = HashMap::new();
+		let mut last_update = Instant::now();
+		// Real time which hasn't been simulated yet
+		let mut unsimulated_time: Float = 0.0;
+		println!("Serving simulation \"{}\" on {}", self.sim_name, public_addr);
+		loop {
+			let elapsed = last_update.elapsed();
+			last_update = Instant::now();
+			server.update(elapsed);
+			to_string_err(transport.update(elapsed, &mut server))?;
+			// Connections
+			while let Some(event) = server.get_event() {
+				match event {
+					ServerEvent::ClientConnected{client_id} => println!("Client {} connected", client_id),
+					ServerEvent::ClientDisconnected{client_id, reason} => {
+						println!("Client {} disconnected: {}", client_id, reason);
+						if let Some(username) = connections.remove(&client_id) {
+							self.remove_connection(&username);
+						}
+					}
+				}
+			}
+			// Requests
+			for client_id in server.clients_id() {
+				while let Some(message) = server.receive_message(client_id, DefaultChannel::ReliableOrdered) {
+					let request: RenetRequest = match decode_message(&message) {
+						Ok(request) => request,
+						Err(e) => {
+							println!("Could not decode request from client {}: {}", client_id, e);
+							continue;
+						}
+					};
+					let response_opt: Option<RenetResponse> = match (connections.get(&client_id).cloned(), request) {
+						(None, RenetRequest::Join{username, password, boat_type}) => Some(match self.join(&username, password, &boat_type) {
+							Ok(()) => {
+								connections.insert(client_id, username);
+								RenetResponse::JoinAccepted {
+									time_step: self.time_step()
+								}
+							},
+							Err(e) => RenetResponse::JoinRejected(e)
+						}),
+						(None, _) => Some(RenetResponse::JoinRejected("First request must be `Join`".to_owned())),
+						(Some(username), request) => match self.handle_request(&username, request) {
+							Ok(response_opt) => response_opt,
+							Err(e) => Some(RenetResponse::Error(e))
+						}
+					};
+					if let Some(response) = response_opt {
+						server.send_message(client_id, DefaultChannel::ReliableOrdered, encode_message(&response)?);
+					}
+				}
+			}
+			// Simulation
+			let time_step = self.time_step();
+			unsimulated_time += elapsed.as_secs_f32().min(self.sim.settings().max_time_step);
+			let mut steps = 0;
+			while unsimulated_time >= time_step && steps < MAX_STEPS_PER_TICK {
+				self.step()?;
+				unsimulated_time -= time_step;
+				steps += 1;
+			}
+			// Catching up on the rest would make the next tick even slower, so the simulation runs slower than real time instead
+			if steps == MAX_STEPS_PER_TICK {
+				unsimulated_time = unsimulated_time.min(time_step);
+			}
+			self.record_new_results()?;
+			self.update_spectator()?;
+			// Snapshots
+			let usernames: Vec<String> = connections.values().cloned().collect();
+			let deltas = self.make_snapshot_deltas(&usernames);
+			for (client_id, username) in &connections {
+				if let Some((_, delta)) = deltas.iter().find(|(name, _)| name == username) {
+					server.send_message(*client_id, DefaultChannel::Unreliable, encode_message(&RenetResponse::Snapshot(delta.clone()))?);
+				}
+			}
+			transport.send_packets(&mut server);
+			thread::sleep(TICK_INTERVAL);
+		}
+	}
+}
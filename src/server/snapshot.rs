@@ -0,0 +1,575 @@
+//! Delta-compressed state snapshots
+//! The server remembers recent snapshots sent to each client and only sends what has changed since the latest one the client acknowledged.
+//! Positions, angles and velocities are quantized to integers so that tiny changes don't count as changes and the encoding is smaller.
+//! Tracers are never sent in full, only the points added since the acknowledged snapshot.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use crate::{prelude::*, simulation::boat::SailSaveState, resource_interface::ResourceRef};
+use serde::{Deserialize, Serialize};
+
+use super::{StateSnapshot, SnapshotBoat};
+
+// CONSTS
+/// Distance units per quantization step
+const POSITION_RESOLUTION: Float = 0.01;
+/// Velocity units per quantization step
+const VELOCITY_RESOLUTION: Float = 0.01;
+/// Radians/second per quantization step
+const ANGULAR_VELOCITY_RESOLUTION: Float = 0.001;
+/// Rudder and sail angle units per quantization step
+const CONTROL_ANGLE_RESOLUTION: Float = 0.01;
+/// Hit-point units per quantization step
+const HP_RESOLUTION: Float = 0.1;
+/// Number of sent snapshots remembered for each client, if the client's last ack is older than this a full snapshot is sent
+const HISTORY_LEN: usize = 64;
+
+fn quantize(value: Float, resolution: Float) -> i32 {
+	(value / resolution).round() as i32
+}
+
+fn dequantize(value: i32, resolution: Float) -> Float {
+	value as Float * resolution
+}
+
+fn quantize_v2(v: &V2, resolution: Float) -> [i32; 2] {
+	[quantize(v.x, resolution), quantize(v.y, resolution)]
+}
+
+fn dequantize_v2(v: [i32; 2], resolution: Float) -> V2 {
+	V2::new(dequantize(v[0], resolution), dequantize(v[1], resolution))
+}
+
+/// Full turn is mapped onto the entire range of u16
+fn quantize_angle(angle: Float) -> u16 {
+	((angle / (2.0 * PI)).rem_euclid(1.0) * 65536.0).round() as u32 as u16
+}
+
+fn dequantize_angle(angle: u16) -> Float {
+	angle as Float / 65536.0 * 2.0 * PI
+}
+
+/// Returns Some(new) if it is different from `old`
+fn changed<T: PartialEq + Clone>(old: &T, new: &T) -> Option<T> {
+	match old == new {
+		true => None,
+		false => Some(new.clone())
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct QuantizedSail {
+	pub ref_: GenericRef<SailSaveState>,
+	pub angle: i32,
+	pub sheeting_angle: i32
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct QuantizedBoat {
+	pub pos: [i32; 2],
+	pub angle: u16,
+	pub vel: [i32; 2],
+	pub angular_vel: i32,
+	pub rudder_angle: i32,
+	pub rudder_hp: bool,
+	pub hull_hp: i32,
+	pub sails: Vec<QuantizedSail>
+}
+
+impl QuantizedBoat {
+	pub fn from_state(state: &BoatSaveState) -> Self {
+		Self {
+			pos: quantize_v2(&state.pos.translation.vector, POSITION_RESOLUTION),
+			angle: quantize_angle(state.pos.rotation.angle()),
+			vel: quantize_v2(&state.vel.translation.vector, VELOCITY_RESOLUTION),
+			angular_vel: quantize(state.vel.rotation.angle(), ANGULAR_VELOCITY_RESOLUTION),
+			rudder_angle: quantize(state.rudder_angle, CONTROL_ANGLE_RESOLUTION),
+			rudder_hp: state.rudder_hp,
+			hull_hp: quantize(state.hull_hp, HP_RESOLUTION),
+			sails: state.sails.iter().map(|(ref_, sail)| QuantizedSail {
+				ref_: ref_.clone(),
+				angle: quantize(sail.angle, CONTROL_ANGLE_RESOLUTION),
+				sheeting_angle: quantize(sail.sheeting_angle, CONTROL_ANGLE_RESOLUTION)
+			}).collect()
+		}
+	}
+	/// True if every field is within one quantization step of `other`
+	/// A state predicted from a dequantized snapshot can't be expected to get any closer than that to the server's
+	pub fn is_close_to(&self, other: &Self) -> bool {
+		let close = |a: i32, b: i32| a.abs_diff(b) <= 1;
+		let close_v2 = |a: [i32; 2], b: [i32; 2]| close(a[0], b[0]) && close(a[1], b[1]);
+		close_v2(self.pos, other.pos) &&
+		self.angle.wrapping_sub(other.angle).min(other.angle.wrapping_sub(self.angle)) <= 1 &&
+		close_v2(self.vel, other.vel) &&
+		close(self.angular_vel, other.angular_vel) &&
+		close(self.rudder_angle, other.rudder_angle) &&
+		self.rudder_hp == other.rudder_hp &&
+		close(self.hull_hp, other.hull_hp) &&
+		self.sails.len() == other.sails.len() &&
+		self.sails.iter().zip(&other.sails).all(|(a, b)| a.ref_ == b.ref_ && close(a.angle, b.angle) && close(a.sheeting_angle, b.sheeting_angle))
+	}
+	pub fn to_state(&self, type_name: &str) -> BoatSaveState {
+		let mut sails = GenericDataset::new();
+		for sail in &self.sails {
+			// A sail repeated in a malformed snapshot is ignored
+			let _ = sails.insert_with_ref(sail.ref_.clone(), SailSaveState {
+				angle: dequantize(sail.angle, CONTROL_ANGLE_RESOLUTION),
+				sheeting_angle: dequantize(sail.sheeting_angle, CONTROL_ANGLE_RESOLUTION)
+			});
+		}
+		BoatSaveState {
+			type_name: ResourceRef::new(type_name.to_owned()),
+			pos: Iso::new(dequantize_v2(self.pos, POSITION_RESOLUTION), dequantize_angle(self.angle)),
+			vel: Iso::new(dequantize_v2(self.vel, VELOCITY_RESOLUTION), dequantize(self.angular_vel, ANGULAR_VELOCITY_RESOLUTION)),
+			rudder_angle: dequantize(self.rudder_angle, CONTROL_ANGLE_RESOLUTION),
+			rudder_hp: self.rudder_hp,
+			hull_hp: dequantize(self.hull_hp, HP_RESOLUTION),
+			sails
+		}
+	}
+}
+
+/// Quantized state of one client, tracers are not included, only their length
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ClientSnapshot {
+	pub type_name: String,
+	pub boat: QuantizedBoat,
+	pub wind: [i32; 2],
+	pub has_finished: bool,
+	pub tracer_len: usize
+}
+
+impl ClientSnapshot {
+	pub fn from_client(client: &SimulationClientSave) -> Self {
+		Self {
+			type_name: client.boat.type_name.name().to_owned(),
+			boat: QuantizedBoat::from_state(&client.boat),
+			wind: quantize_v2(&client.wind.vector(), VELOCITY_RESOLUTION),
+			has_finished: client.has_finished,
+			tracer_len: client.tracer_list.len()
+		}
+	}
+}
+
+/// Quantized state of the whole simulation at one tick
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Snapshot {
+	pub seq: u64,
+	/// Number of fixed steps the server had simulated
+	pub tick: u64,
+	pub time: Float,
+	pub clients: BTreeMap<String, ClientSnapshot>
+}
+
+impl Snapshot {
+	pub fn empty() -> Self {
+		Self {
+			seq: 0,
+			tick: 0,
+			time: 0.0,
+			clients: BTreeMap::new()
+		}
+	}
+	pub fn from_sim(seq: u64, tick: u64, sim: &Simulation) -> Self {
+		Self {
+			seq,
+			tick,
+			time: sim.time(),
+			clients: sim.save_state().clients.iter().map(|(username, client)| (username.clone(), ClientSnapshot::from_client(client))).collect()
+		}
+	}
+	/// Changes needed to turn `base` into `self`, `new_tracer_points` is called with (username, first point index) to get tracer points
+	pub fn delta_from<F>(&self, base: &Self, base_seq_opt: Option<u64>, last_processed_input: u64, mut new_tracer_points: F) -> SnapshotDelta
+	where F: FnMut(&str, usize) -> Vec<V2> {
+		let mut changed_clients = Vec::new();
+		for (username, new) in &self.clients {
+			let delta = match base.clients.get(username) {
+				Some(old) => ClientDelta::between(username, old, new, &mut new_tracer_points),
+				None => ClientDelta::full(username, new, &mut new_tracer_points)
+			};
+			if !delta.is_empty() {
+				changed_clients.push(delta);
+			}
+		}
+		SnapshotDelta {
+			base_seq_opt,
+			seq: self.seq,
+			tick: self.tick,
+			time: self.time,
+			last_processed_input,
+			changed: changed_clients,
+			removed: base.clients.keys().filter(|username| !self.clients.contains_key(*username)).cloned().collect()
+		}
+	}
+	/// Applies `delta` to a copy of self, tracer points are passed to `tracer_update` as (username, first point index, points)
+	pub fn apply_delta<F>(&self, delta: &SnapshotDelta, mut tracer_update: F) -> Result<Self, String>
+	where F: FnMut(&str, usize, Vec<V2>) {
+		let mut out = self.clone();
+		out.seq = delta.seq;
+		out.tick = delta.tick;
+		out.time = delta.time;
+		for username in &delta.removed {
+			out.clients.remove(username);
+		}
+		for client_delta in &delta.changed {
+			let client = match out.clients.get_mut(&client_delta.username) {
+				Some(client) => client,
+				None => {
+					let full = client_delta.to_full()?;
+					out.clients.insert(client_delta.username.clone(), full);
+					out.clients.get_mut(&client_delta.username).unwrap()
+				}
+			};
+			client_delta.apply(client);
+			if let Some(tracer_start) = client_delta.tracer_start_opt {
+				let points: Vec<V2> = client_delta.new_tracer_points.iter().map(|p| dequantize_v2(*p, POSITION_RESOLUTION)).collect();
+				client.tracer_len = tracer_start + points.len();
+				tracer_update(&client_delta.username, tracer_start, points);
+			}
+		}
+		Ok(out)
+	}
+	/// Dequantized view used by the client's prediction
+	pub fn to_state_snapshot(&self, last_processed_input: u64) -> StateSnapshot {
+		StateSnapshot {
+			tick: self.tick,
+			time: self.time,
+			last_processed_input,
+			boats: self.clients.iter().map(|(username, client)| (username.clone(), SnapshotBoat {
+				boat: client.boat.to_state(&client.type_name),
+				wind: dequantize_v2(client.wind, VELOCITY_RESOLUTION)
+			})).collect()
+		}
+	}
+}
+
+/// Changed fields of one client, None means unchanged
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClientDelta {
+	pub username: String,
+	pub type_name: Option<String>,
+	pub pos: Option<[i32; 2]>,
+	pub angle: Option<u16>,
+	pub vel: Option<[i32; 2]>,
+	pub angular_vel: Option<i32>,
+	pub rudder_angle: Option<i32>,
+	pub rudder_hp: Option<bool>,
+	pub hull_hp: Option<i32>,
+	/// Only sails that have changed
+	pub sails: Vec<QuantizedSail>,
+	pub wind: Option<[i32; 2]>,
+	pub has_finished: Option<bool>,
+	/// Index in the tracer list of the first point in `new_tracer_points`, None if the tracer hasn't changed
+	pub tracer_start_opt: Option<usize>,
+	pub new_tracer_points: Vec<[i32; 2]>
+}
+
+impl ClientDelta {
+	fn between<F>(username: &str, old: &ClientSnapshot, new: &ClientSnapshot, new_tracer_points: &mut F) -> Self
+	where F: FnMut(&str, usize) -> Vec<V2> {
+		let (tracer_start_opt, points) = if new.tracer_len != old.tracer_len {
+			// If the tracer was reset it is shorter, so it is re-sent from the start
+			let start = if new.tracer_len > old.tracer_len {old.tracer_len} else {0};
+			(Some(start), new_tracer_points(username, start))
+		}
+		else {
+			(None, Vec::new())
+		};
+		Self {
+			username: username.to_owned(),
+			type_name: changed(&old.type_name, &new.type_name),
+			pos: changed(&old.boat.pos, &new.boat.pos),
+			angle: changed(&old.boat.angle, &new.boat.angle),
+			vel: changed(&old.boat.vel, &new.boat.vel),
+			angular_vel: changed(&old.boat.angular_vel, &new.boat.angular_vel),
+			rudder_angle: changed(&old.boat.rudder_angle, &new.boat.rudder_angle),
+			rudder_hp: changed(&old.boat.rudder_hp, &new.boat.rudder_hp),
+			hull_hp: changed(&old.boat.hull_hp, &new.boat.hull_hp),
+			sails: new.boat.sails.iter().filter(|sail| !old.boat.sails.contains(sail)).cloned().collect(),
+			wind: changed(&old.wind, &new.wind),
+			has_finished: changed(&old.has_finished, &new.has_finished),
+			tracer_start_opt,
+			new_tracer_points: points.iter().map(|p| quantize_v2(p, POSITION_RESOLUTION)).collect()
+		}
+	}
+	/// Every field is set, for clients which the recipient doesn't know about yet
+	fn full<F>(username: &str, new: &ClientSnapshot, new_tracer_points: &mut F) -> Self
+	where F: FnMut(&str, usize) -> Vec<V2> {
+		Self {
+			username: username.to_owned(),
+			type_name: Some(new.type_name.clone()),
+			pos: Some(new.boat.pos),
+			angle: Some(new.boat.angle),
+			vel: Some(new.boat.vel),
+			angular_vel: Some(new.boat.angular_vel),
+			rudder_angle: Some(new.boat.rudder_angle),
+			rudder_hp: Some(new.boat.rudder_hp),
+			hull_hp: Some(new.boat.hull_hp),
+			sails: new.boat.sails.clone(),
+			wind: Some(new.wind),
+			has_finished: Some(new.has_finished),
+			tracer_start_opt: Some(0),
+			new_tracer_points: new_tracer_points(username, 0).iter().map(|p| quantize_v2(p, POSITION_RESOLUTION)).collect()
+		}
+	}
+	fn is_empty(&self) -> bool {
+		self.type_name.is_none() &&
+		self.pos.is_none() &&
+		self.angle.is_none() &&
+		self.vel.is_none() &&
+		self.angular_vel.is_none() &&
+		self.rudder_angle.is_none() &&
+		self.rudder_hp.is_none() &&
+		self.hull_hp.is_none() &&
+		self.sails.is_empty() &&
+		self.wind.is_none() &&
+		self.has_finished.is_none() &&
+		self.tracer_start_opt.is_none()
+	}
+	/// Creates a client snapshot out of a delta made with `full()`
+	fn to_full(&self) -> Result<ClientSnapshot, String> {
+		let missing = || format!("Delta for new client \"{}\" is missing fields", self.username);
+		Ok(ClientSnapshot {
+			type_name: self.type_name.clone().ok_or_else(missing)?,
+			boat: QuantizedBoat {
+				pos: self.pos.ok_or_else(missing)?,
+				angle: self.angle.ok_or_else(missing)?,
+				vel: self.vel.ok_or_else(missing)?,
+				angular_vel: self.angular_vel.ok_or_else(missing)?,
+				rudder_angle: self.rudder_angle.ok_or_else(missing)?,
+				rudder_hp: self.rudder_hp.ok_or_else(missing)?,
+				hull_hp: self.hull_hp.ok_or_else(missing)?,
+				sails: self.sails.clone()
+			},
+			wind: self.wind.ok_or_else(missing)?,
+			has_finished: self.has_finished.ok_or_else(missing)?,
+			tracer_len: 0
+		})
+	}
+	fn apply(&self, client: &mut ClientSnapshot) {
+		if let Some(type_name) = &self.type_name {client.type_name = type_name.clone();}
+		if let Some(pos) = self.pos {client.boat.pos = pos;}
+		if let Some(angle) = self.angle {client.boat.angle = angle;}
+		if let Some(vel) = self.vel {client.boat.vel = vel;}
+		if let Some(angular_vel) = self.angular_vel {client.boat.angular_vel = angular_vel;}
+		if let Some(rudder_angle) = self.rudder_angle {client.boat.rudder_angle = rudder_angle;}
+		if let Some(rudder_hp) = self.rudder_hp {client.boat.rudder_hp = rudder_hp;}
+		if let Some(hull_hp) = self.hull_hp {client.boat.hull_hp = hull_hp;}
+		for new_sail in &self.sails {
+			match client.boat.sails.iter_mut().find(|sail| sail.ref_ == new_sail.ref_) {
+				Some(sail) => *sail = new_sail.clone(),
+				None => client.boat.sails.push(new_sail.clone())
+			}
+		}
+		if let Some(wind) = self.wind {client.wind = wind;}
+		if let Some(has_finished) = self.has_finished {client.has_finished = has_finished;}
+	}
+}
+
+/// Sent to clients every tick
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SnapshotDelta {
+	/// Snapshot this is relative to, None means relative to an empty snapshot
+	pub base_seq_opt: Option<u64>,
+	pub seq: u64,
+	pub tick: u64,
+	pub time: Float,
+	/// Latest input sequence number from the recipient which has been applied
+	pub last_processed_input: u64,
+	pub changed: Vec<ClientDelta>,
+	pub removed: Vec<String>
+}
+
+impl SnapshotDelta {
+	/// Size in bytes when sent over the network
+	pub fn encoded_len(&self) -> Result<usize, String> {
+		Ok(super::encode_message(self)?.len())
+	}
+}
+
+/// Server-side record of snapshots sent to one client
+pub struct SnapshotHistory {
+	sent: VecDeque<Snapshot>,
+	last_acked_seq_opt: Option<u64>
+}
+
+impl SnapshotHistory {
+	pub fn new() -> Self {
+		Self {
+			sent: VecDeque::new(),
+			last_acked_seq_opt: None
+		}
+	}
+	pub fn ack(&mut self, seq: u64) {
+		if self.last_acked_seq_opt.is_none_or(|last| seq > last) {
+			self.last_acked_seq_opt = Some(seq);
+		}
+		// Nothing older than the acked snapshot will be used as a base again
+		while let Some(oldest) = self.sent.front() {
+			if oldest.seq < seq {
+				self.sent.pop_front();
+			}
+			else {
+				break;
+			}
+		}
+	}
+	/// Creates the delta to send and remembers `snapshot`
+	pub fn make_delta(&mut self, snapshot: Snapshot, sim: &Simulation, last_processed_input: u64) -> SnapshotDelta {
+		let base_opt = self.last_acked_seq_opt.and_then(|seq| self.sent.iter().find(|sent| sent.seq == seq));
+		let tracers = |username: &str, start: usize| match sim.save_state().clients.get(username) {
+			Some(client) => client.tracer_list.get(start..).map(|points| points.to_vec()).unwrap_or_default(),
+			None => Vec::new()
+		};
+		let delta = match base_opt {
+			Some(base) => snapshot.delta_from(base, Some(base.seq), last_processed_input, tracers),
+			None => snapshot.delta_from(&Snapshot::empty(), None, last_processed_input, tracers)
+		};
+		self.sent.push_back(snapshot);
+		if self.sent.len() > HISTORY_LEN {
+			self.sent.pop_front();
+		}
+		delta
+	}
+}
+
+impl Default for SnapshotHistory {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Client-side reconstruction of snapshots from deltas
+pub struct SnapshotReceiver {
+	/// Recently received snapshots, any of which the server may use as a base
+	received: VecDeque<Snapshot>,
+	tracers: HashMap<String, Vec<V2>>
+}
+
+impl SnapshotReceiver {
+	pub fn new() -> Self {
+		Self {
+			received: VecDeque::new(),
+			tracers: HashMap::new()
+		}
+	}
+	/// Reconstructs the full snapshot, the returned seq should be acknowledged to the server
+	pub fn receive(&mut self, delta: &SnapshotDelta) -> Result<(u64, StateSnapshot), String> {
+		let empty = Snapshot::empty();
+		let base = match delta.base_seq_opt {
+			Some(base_seq) => match self.received.iter().find(|snapshot| snapshot.seq == base_seq) {
+				Some(base) => base,
+				None => return Err(format!("Received snapshot delta relative to unknown snapshot {}", base_seq))
+			},
+			None => &empty
+		};
+		let tracers = &mut self.tracers;
+		let snapshot = base.apply_delta(delta, |username, start, points| {
+			let tracer = tracers.entry(username.to_owned()).or_default();
+			tracer.truncate(start);
+			tracer.extend(points);
+		})?;
+		for username in &delta.removed {
+			self.tracers.remove(username);
+		}
+		// Bases older than the new one will never be used again
+		self.received.retain(|received| received.seq >= delta.base_seq_opt.unwrap_or(0));
+		self.received.push_back(snapshot.clone());
+		if self.received.len() > HISTORY_LEN {
+			self.received.pop_front();
+		}
+		Ok((snapshot.seq, snapshot.to_state_snapshot(delta.last_processed_input)))
+	}
+	pub fn tracer(&self, username: &str) -> Option<&Vec<V2>> {
+		self.tracers.get(username)
+	}
+}
+
+impl Default for SnapshotReceiver {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{simulation::user::UserInput, test_utils};
+
+	const BOATS: usize = 20;
+	const TICKS: u64 = 120;
+	const DT: Float = 1.0 / 60.0;
+	/// Average size of a delta once every boat is known to the client, every boat moves and turns on every tick
+	const BYTES_PER_TICK_BUDGET: usize = 4000;
+
+	/// 20 boats turning at different rates so that every boat changes on every tick
+	fn race_sim() -> Simulation {
+		let mut sim = Simulation::from_resources(test_utils::save(), test_utils::resources(test_utils::open_water_map(2000))).unwrap();
+		for i in 0..BOATS {
+			let username = format!("boat{:02}", i);
+			sim.add_client(&username, test_utils::BOAT_TYPE_NAME).unwrap();
+			sim.apply_input(&username, UserInput {
+				boat: Some(BoatInputs {
+					rudder_control: Some(0.01 * i as Float - 0.1),
+					sheeting_angles: Vec::new()
+				}),
+				autopilot: None
+			}).unwrap();
+		}
+		sim
+	}
+
+	/// Sends a snapshot every tick, acks from `dropped_acks` never reach the server
+	/// Returns the average bytes per tick after the first (full) snapshot
+	fn run(dropped_acks: &[u64]) -> usize {
+		let mut sim = race_sim();
+		let mut history = SnapshotHistory::new();
+		let mut receiver = SnapshotReceiver::new();
+		let mut total_bytes = 0;
+		for seq in 1..=TICKS {
+			sim.step(DT);
+			let snapshot = Snapshot::from_sim(seq, seq, &sim);
+			let delta = history.make_delta(snapshot.clone(), &sim, 0);
+			if seq > 1 {
+				total_bytes += delta.encoded_len().unwrap();
+			}
+			let encoded = crate::server::encode_message(&delta).unwrap();
+			let (received_seq, _) = receiver.receive(&crate::server::decode_message(&encoded).unwrap()).unwrap();
+			assert_eq!(received_seq, seq);
+			// The client's reconstruction has to match the server's quantized state exactly
+			assert_eq!(receiver.received.back(), Some(&snapshot), "Reconstruction differs at tick {}", seq);
+			for (username, client) in &sim.save_state().clients {
+				assert_eq!(receiver.tracer(username).map(|tracer| tracer.len()), Some(client.tracer_list.len()));
+			}
+			if !dropped_acks.contains(&seq) {
+				history.ack(received_seq);
+			}
+		}
+		total_bytes / (TICKS as usize - 1)
+	}
+
+	#[test]
+	fn twenty_boat_race_fits_bandwidth_budget() {
+		let bytes_per_tick = run(&[]);
+		assert!(bytes_per_tick < BYTES_PER_TICK_BUDGET, "{} bytes per tick ({} per boat) is over the budget of {}", bytes_per_tick, bytes_per_tick / BOATS, BYTES_PER_TICK_BUDGET);
+	}
+
+	#[test]
+	fn reconstruction_survives_dropped_acks() {
+		// A single lost ack, then a run of them
+		run(&[10, 40, 41, 42, 43, 44, 45]);
+	}
+
+	#[test]
+	fn full_snapshot_is_sent_when_ack_is_too_old() {
+		let sim = race_sim();
+		let mut history = SnapshotHistory::new();
+		let first = history.make_delta(Snapshot::from_sim(1, 1, &sim), &sim, 0);
+		history.ack(first.seq);
+		for seq in 2..(HISTORY_LEN as u64 + 3) {
+			history.make_delta(Snapshot::from_sim(seq, seq, &sim), &sim, 0);
+		}
+		let delta = history.make_delta(Snapshot::from_sim(HISTORY_LEN as u64 + 3, HISTORY_LEN as u64 + 3, &sim), &sim, 0);
+		assert_eq!(delta.base_seq_opt, None);
+		assert_eq!(delta.changed.len(), BOATS);
+	}
+}
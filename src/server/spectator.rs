@@ -0,0 +1,221 @@
+//! Browser-based spectator view, served over HTTP with rouille
+//! The page (spectator.html) fetches the map once and then polls `/state` to draw the boats, wind and tracers
+//! Land is drawn from the image at `/land` if the server has one, otherwise only coastlines are outlined
+
+use std::{sync::{Arc, Mutex}, thread};
+use crate::{prelude::*, resource_interface::MapSave, raster::{self, LandMask}};
+use serde::{Deserialize, Serialize};
+use rouille::{self, router, Request, Response};
+
+// CONSTS
+const SPECTATOR_PAGE: &str = include_str!("spectator.html");
+/// Larger side of the filled land image, pixels
+const LAND_IMAGE_SIZE: usize = 2048;
+/// Most tracer points sent for each boat, so that `/state` doesn't grow over a long session
+const MAX_TRACER_POINTS: usize = 256;
+
+/// Filled land image for `SpectatorServer::start()` in the GUI colors, None if the GUI settings turn off land filling
+/// Without global settings land is filled with the default colors
+pub fn land_image(map: &MapSave) -> Result<Option<Vec<u8>>, String> {
+	let (floodfill_land, water_color, land_color) = match resource_interface::load_settings() {
+		Ok(settings) => (settings.gui.floodfil_land, settings.gui.colors.ocean, settings.gui.colors.land),
+		Err(_) => (true, raster::DEFAULT_WATER_COLOR, raster::DEFAULT_LAND_COLOR)
+	};
+	if !floodfill_land {
+		return Ok(None);
+	}
+	let resolution = map.size.0.max(map.size.1).max(1) as Float / LAND_IMAGE_SIZE as Float;
+	Ok(Some(LandMask::new(map, resolution)?.to_png(map, water_color, land_color)?))
+}
+
+/// Everything the spectator page needs to draw one frame
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SpectatorFrame {
+	pub paused: bool,
+	pub boats: Vec<SpectatorBoat>
+}
+
+impl SpectatorFrame {
+	pub fn from_sim(sim: &Simulation) -> Self {
+		let save = sim.save_state();
+		Self {
+			paused: save.paused,
+			boats: save.clients.iter().map(|(username, client)| SpectatorBoat::from_client(username, client)).collect()
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SpectatorBoat {
+	pub username: String,
+	pub type_name: String,
+	pub pos: V2,
+	/// Radians CCW from +X
+	pub heading: Float,
+	pub speed: Float,
+	pub wind: Wind,
+	/// Whole tracer thinned out to at most `MAX_TRACER_POINTS`, always ending at the latest point
+	pub tracer: Vec<V2>,
+	pub has_finished: bool,
+	pub time: Float
+}
+
+impl SpectatorBoat {
+	pub fn from_client(username: &str, client: &SimulationClientSave) -> Self {
+		Self {
+			username: username.to_owned(),
+			type_name: client.boat.type_name.name().to_owned(),
+			pos: client.boat.pos.translation.vector,
+			heading: client.boat.pos.rotation.angle(),
+			speed: client.boat.vel.translation.vector.magnitude(),
+			wind: client.wind.vector(),
+			tracer: decimate(&client.tracer_list, MAX_TRACER_POINTS),
+			has_finished: client.has_finished,
+			time: client.time
+		}
+	}
+}
+
+/// Evenly spaced points from `points` including the first and last, at most `max_points`
+fn decimate(points: &[V2], max_points: usize) -> Vec<V2> {
+	if points.len() <= max_points {
+		return points.to_vec();
+	}
+	// One point is kept free for the last one
+	let step = points.len().div_ceil(max_points.max(2) - 1);
+	let mut out: Vec<V2> = points.iter().step_by(step).copied().collect();
+	if !(points.len() - 1).is_multiple_of(step) {
+		out.extend(points.last());
+	}
+	out
+}
+
+/// Responses to the spectator page's requests, shared with the server thread
+struct Routes {
+	map_json: String,
+	land_png_opt: Option<Vec<u8>>,
+	frame_json: Arc<Mutex<String>>
+}
+
+impl Routes {
+	fn handle(&self, request: &Request) -> Response {
+		router!(request,
+			(GET) (/) => {
+				Response::html(SPECTATOR_PAGE)
+			},
+			(GET) (/map) => {
+				Response::from_data("application/json", self.map_json.clone())
+			},
+			(GET) (/land) => {
+				match &self.land_png_opt {
+					Some(land_png) => Response::from_data("image/png", land_png.clone()),
+					None => Response::empty_404()
+				}
+			},
+			(GET) (/state) => {
+				Response::from_data("application/json", self.frame_json.lock().unwrap().clone())
+			},
+			_ => Response::empty_404()
+		)
+	}
+}
+
+/// HTTP server running on its own thread, the simulation thread pushes new frames to it with `update()`
+pub struct SpectatorServer {
+	frame_json: Arc<Mutex<String>>
+}
+
+impl SpectatorServer {
+	/// `land_png_opt` is the filled land image served at `/land`, see `LandMask::to_png()`
+	pub fn start(port: u16, map: &MapSave, land_png_opt: Option<Vec<u8>>) -> Result<Self, String> {
+		let (out, routes) = Self::new(map, land_png_opt)?;
+		let server = to_string_err(rouille::Server::new(("0.0.0.0", port), move |request| routes.handle(request)))?;
+		thread::spawn(move || server.run());
+		Ok(out)
+	}
+	/// Server state and the routes which read it, without listening
+	fn new(map: &MapSave, land_png_opt: Option<Vec<u8>>) -> Result<(Self, Routes), String> {
+		let frame_json = Arc::new(Mutex::new(to_string_err(serde_json::to_string(&SpectatorFrame{paused: true, boats: Vec::new()}))?));
+		let routes = Routes {
+			map_json: to_string_err(serde_json::to_string(map))?,
+			land_png_opt,
+			frame_json: frame_json.clone()
+		};
+		Ok((
+			Self {
+				frame_json
+			},
+			routes
+		))
+	}
+	/// Replaces the frame returned by `/state`
+	pub fn update(&self, sim: &Simulation) -> Result<(), String> {
+		let new_json = to_string_err(serde_json::to_string(&SpectatorFrame::from_sim(sim)))?;
+		*self.frame_json.lock().unwrap() = new_json;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Read;
+	use crate::test_utils;
+
+	fn get(routes: &Routes, url: &str) -> Response {
+		routes.handle(&Request::fake_http("GET", url, Vec::new(), Vec::new()))
+	}
+
+	fn body(response: Response) -> String {
+		let mut out = String::new();
+		response.data.into_reader_and_size().0.read_to_string(&mut out).unwrap();
+		out
+	}
+
+	#[test]
+	fn state_has_every_boat_and_wind() {
+		let mut sim = Simulation::from_resources(test_utils::save(), test_utils::resources(test_utils::open_water_map(1000))).unwrap();
+		for username in ["alice", "bob"] {
+			sim.add_client(username, test_utils::BOAT_TYPE_NAME).unwrap();
+		}
+		sim.step(0.1);
+		let (server, routes) = SpectatorServer::new(sim.map(), None).unwrap();
+		server.update(&sim).unwrap();
+		let response = get(&routes, "/state");
+		assert_eq!(response.status_code, 200);
+		let frame: SpectatorFrame = serde_json::from_str(&body(response)).unwrap();
+		assert!(!frame.paused);
+		assert_eq!(frame.boats.iter().map(|boat| boat.username.as_str()).collect::<Vec<_>>(), ["alice", "bob"]);
+		for boat in &frame.boats {
+			let client = &sim.save_state().clients[&boat.username];
+			assert_eq!(boat.wind, client.wind.vector());
+			assert_eq!(boat.pos, client.boat.pos.translation.vector);
+			assert_eq!(boat.type_name, test_utils::BOAT_TYPE_NAME);
+		}
+	}
+
+	#[test]
+	fn map_and_land_routes() {
+		let map = test_utils::open_water_map(1000);
+		let (_, routes) = SpectatorServer::new(&map, None).unwrap();
+		let served: MapSave = serde_json::from_str(&body(get(&routes, "/map"))).unwrap();
+		assert_eq!(served.end, map.end);
+		assert_eq!(get(&routes, "/land").status_code, 404);
+		assert_eq!(get(&routes, "/").status_code, 200);
+		let (_, routes) = SpectatorServer::new(&map, Some(vec![1, 2, 3])).unwrap();
+		assert_eq!(get(&routes, "/land").status_code, 200);
+	}
+
+	#[test]
+	fn tracer_is_decimated() {
+		let points: Vec<V2> = (0..1000).map(|i| V2::new(i as Float, 0.0)).collect();
+		let decimated = decimate(&points, MAX_TRACER_POINTS);
+		assert!(decimated.len() <= MAX_TRACER_POINTS && decimated.len() > MAX_TRACER_POINTS / 2);
+		assert_eq!((decimated[0], *decimated.last().unwrap()), (points[0], points[999]));
+		assert_eq!(decimate(&points[..10], MAX_TRACER_POINTS), points[..10]);
+		for len in [MAX_TRACER_POINTS + 1, 2 * MAX_TRACER_POINTS, 5000] {
+			let decimated = decimate(&points.iter().cycle().take(len).copied().collect::<Vec<_>>(), MAX_TRACER_POINTS);
+			assert!(decimated.len() <= MAX_TRACER_POINTS, "{} points from {}", decimated.len(), len);
+		}
+	}
+}
@@ -1,10 +1,11 @@
 //! Physics simulation and graphics for boats
 
-use crate::prelude::*;
+use crate::{prelude::*, geometry, generic_ref::SharesIds, resource_interface::ResourceRef};
 use serde::{Deserialize, Serialize};
 use std::rc::Rc;
+use nalgebra::UnitComplex;
 
-use super::physical_integrator::PhysicalIntegrator;
+use super::{physical_integrator::PhysicalIntegrator, flat_object_physics::{flat_plate_force, AIR_DENSITY, WATER_DENSITY}};
 
 // CONSTS
 const DEFAULT_SHEETING_ANGLE: Float = 90.0;
@@ -46,10 +47,68 @@ pub struct BoatType {
 	pub upwind_max_wind_angle: Float,
 	/// Angle between the boat's momentum and the wind (apparent) where the boat is fastest upwind, used by the autopilot
 	pub upwind_max_total_leeway: Float,
+	/// Angle between the boat and the wind (true) where the boat is fastest directly downwind, used by the autopilot
+	#[serde(default = "default_downwind_max_wind_angle")]
+	pub downwind_max_wind_angle: Float,
+	/// Rating used to compare this boat with other types in mixed-fleet races, results only show elapsed time if None
+	#[serde(default)]
+	pub handicap_opt: Option<Handicap>,
 	/// Configuration of all the sails
 	pub sails: GenericDataset<SailStatic>
 }
 
+fn default_downwind_max_wind_angle() -> Float {
+	150.0
+}
+
+impl BoatType {
+	/// Human-readable list of everything wrong with this boat type
+	pub fn problems(&self) -> Vec<String> {
+		let mut out = Vec::new();
+		if self.perimeter.len() < 3 {
+			out.push("Perimeter has less than 3 points".to_owned());
+		}
+		if self.mass <= 0.0 {
+			out.push(format!("Mass must be positive, not {}", self.mass));
+		}
+		if self.moment <= 0.0 {
+			out.push(format!("Moment must be positive, not {}", self.moment));
+		}
+		if self.sails.is_empty() {
+			out.push("Boat has no sails".to_owned());
+		}
+		for (ref_, sail) in &self.sails {
+			if sail.area <= 0.0 {
+				out.push(format!("Sail {} area must be positive, not {}", ref_, sail.area));
+			}
+		}
+		if let Some(Handicap::TimeOnTime(rating)) = self.handicap_opt {
+			if rating <= 0.0 {
+				out.push(format!("Time-on-time handicap rating must be positive, not {}", rating));
+			}
+		}
+		out
+	}
+}
+
+/// Handicap rating, converts elapsed time to corrected time so that different boat types can be compared
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Handicap {
+	/// Portsmouth-style number, corrected time = elapsed time * 1000 / rating, so faster boats have lower numbers
+	TimeOnTime(Float),
+	/// Seconds per unit of course length given to this boat (or owed if negative), corrected time = elapsed time - allowance * course length
+	TimeOnDistance(Float)
+}
+
+impl Handicap {
+	pub fn corrected_time(&self, elapsed_time: Float, course_length: Float) -> Float {
+		match self {
+			Self::TimeOnTime(rating) => elapsed_time * 1000.0 / rating,
+			Self::TimeOnDistance(allowance) => elapsed_time - allowance * course_length
+		}
+	}
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct SailStatic {
 	/// Area
@@ -62,7 +121,24 @@ pub struct SailStatic {
 	pub foot_len: Float
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl SailStatic {
+	/// Angle (degrees) the sail settles at, it weathervanes with the wind until stopped by the sheet
+	/// `apparent_wind` is in the boat's frame
+	pub fn angle_for_sheeting(&self, sheeting_angle: Float, apparent_wind: &Wind) -> Float {
+		// Angle from straight aft to the direction the wind is blowing towards
+		let free_angle = wrap_angle(apparent_wind.y.atan2(apparent_wind.x) - PI).to_degrees();
+		free_angle.clamp(-sheeting_angle.abs(), sheeting_angle.abs())
+	}
+	/// Aerodynamic force on the sail in the boat's frame, `angle` is in degrees and `apparent_wind` is in the boat's frame
+	pub fn force(&self, angle: Float, apparent_wind: &Wind) -> V2 {
+		let angle_rad = angle.to_radians();
+		// Sail points aft from the mast, its normal is 90 degrees CCW from that
+		let normal = V2::new(angle_rad.sin(), -angle_rad.cos());
+		flat_plate_force(*apparent_wind, normal, self.area, AIR_DENSITY)
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct SailSaveState {
 	/// Current angle relative to a line from the mast pointing straight aft, + = CCW, - = CW
 	pub angle: Float,
@@ -74,8 +150,17 @@ impl SailSaveState {
 	pub fn average_with_other_state(&mut self, other: &Self) {
 		self.angle = (self.angle + other.angle) / 2.0;
 	}
+	/// Linear interpolation towards `other`, `t` = 0 -> self, `t` = 1 -> other
+	pub fn interpolate(&self, other: &Self, t: Float) -> Self {
+		Self {
+			angle: self.angle + (other.angle - self.angle) * t,
+			sheeting_angle: other.sheeting_angle
+		}
+	}
 }
 
+impl SharesIds<SailStatic> for SailSaveState {}
+
 impl Default for SailSaveState {
 	fn default() -> Self {
 		Self {
@@ -87,13 +172,13 @@ impl Default for SailSaveState {
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct BoatSaveState {
-	/// Name of static boat type file (without .json)
-	pub type_name: String,
+	/// Static boat type file
+	pub type_name: ResourceRef<BoatType>,
 	/// Displacement, angle
 	pub pos: Iso,
 	/// Velocity, angular velocity
 	pub vel: Iso,
-	/// Rudder angle
+	/// Rudder angle relative to the hull, radians, + = CCW which turns the boat CW
 	pub rudder_angle: Float,
 	/// Rudder hit-points
 	pub rudder_hp: bool,
@@ -104,14 +189,61 @@ pub struct BoatSaveState {
 }
 
 impl BoatSaveState {
+	/// Boat at rest at `pos` with every sail at its default state
+	pub fn new(type_name: String, boat_type: &BoatType, pos: Iso) -> Self {
+		let mut sails = GenericDataset::new();
+		for (ref_, _) in &boat_type.sails {
+			sails.insert_with_ref(ref_.to_shared(), SailSaveState::default()).expect("Sail references are unique in the boat type");
+		}
+		Self {
+			type_name: ResourceRef::new(type_name),
+			pos,
+			vel: Iso::identity(),
+			rudder_angle: 0.0,
+			rudder_hp: true,
+			hull_hp: boat_type.max_hull_hp,
+			sails
+		}
+	}
+	/// Heading in radians, CCW from +X
+	pub fn heading(&self) -> Float {
+		self.pos.rotation.angle()
+	}
+	/// Wind felt by the boat (true wind - boat velocity), direction it is blowing towards
+	pub fn apparent_wind(&self, true_wind: &Wind) -> Wind {
+		true_wind - self.vel.translation.vector
+	}
+	/// Apparent wind rotated into the boat's frame (+X = forward, +Y = port)
+	pub fn apparent_wind_local(&self, true_wind: &Wind) -> Wind {
+		self.pos.rotation.inverse() * self.apparent_wind(true_wind)
+	}
+	/// Signed angle from the bow to where the apparent wind is coming from, radians, + = CCW (port side)
+	pub fn apparent_wind_angle(&self, true_wind: &Wind) -> Float {
+		let from = -self.apparent_wind(true_wind);
+		wrap_angle(from.y.atan2(from.x) - self.heading())
+	}
 	pub fn average_with_other_state(&mut self, other: Self) {
 		self.pos = average_iso(&self.pos, &other.pos);
 		self.vel = average_iso(&self.vel, &other.vel);
 		self.rudder_angle = (self.rudder_angle + other.rudder_angle) / 2.0;// TODO: check: This will only be reliable if the angle doesn't go across 0
 		// iterate over sails
-		for (ref_, sail) in self.sails.items.iter_mut() {
-			sail.average_with_other_state(other.sails.get_item_tuple(&ref_.to_query()).expect(&format!("Could not corresponding sail to average with during physics integrator update, reference={:?}", ref_)).1);
+		for (ref_, sail) in self.sails.iter_mut() {
+			sail.average_with_other_state(other.sails.get_item_tuple(&ref_.to_query()).unwrap_or_else(|| panic!("Could not corresponding sail to average with during physics integrator update, reference={:?}", ref_)).1);
+		}
+	}
+	/// Interpolates between two snapshots of the same boat, `t` = 0 -> self, `t` = 1 -> other
+	/// Used by the client to smoothly display other boats between server snapshots
+	pub fn interpolate(&self, other: &Self, t: Float) -> Self {
+		let mut out = other.clone();
+		out.pos = interpolate_iso(&self.pos, &other.pos, t);
+		out.vel = interpolate_iso(&self.vel, &other.vel, t);
+		out.rudder_angle = self.rudder_angle + (other.rudder_angle - self.rudder_angle) * t;
+		for (ref_, sail) in out.sails.iter_mut() {
+			if let Some((_, self_sail)) = self.sails.get_item_tuple(&ref_.to_query()) {
+				*sail = self_sail.interpolate(sail, t);
+			}
 		}
+		out
 	}
 }
 
@@ -120,24 +252,56 @@ struct BoatPhysicalIntegrator {
 	state: BoatSaveState
 }
 
-impl PhysicalIntegrator<BoatStatic, Wind> for BoatPhysicalIntegrator {
-	fn partial_step(&mut self, dt: Float, static_: &BoatStatic, dynamic: &Wind) {
+impl PhysicalIntegrator<BoatType, Wind> for BoatPhysicalIntegrator {
+	fn partial_step(&mut self, dt: Float, static_: &BoatType, dynamic: &Wind) {
+		// Everything is calculated in the boat's frame (+X = forward, +Y = port) and then rotated back
+		let state = &mut self.state;
+		let rotation = state.pos.rotation;
+		let local_vel: V2 = rotation.inverse() * state.vel.translation.vector;
+		let angular_vel: Float = state.vel.rotation.angle();
+		let apparent_wind = state.apparent_wind_local(dynamic);
+		let mut force = V2::zeros();
+		let mut torque: Float = 0.0;
+		let mut apply = |f: V2, at: V2| {
+			force += f;
+			torque += geometry::cross(&at, &f);
+		};
 		// Update sails
-		// TODO
-		// Get all translational forces
-		// TODO
-		// Get all torques
-		// TODO
+		for (ref_, sail_state) in state.sails.iter_mut() {
+			let sail = match static_.sails.get_item_tuple(&GenericQuery::id(ref_.id)) {
+				Some((_, sail)) => sail,
+				None => continue
+			};
+			sail_state.angle = sail.angle_for_sheeting(sail_state.sheeting_angle, &apparent_wind);
+			let angle_rad = sail_state.angle.to_radians();
+			let center_of_effort = V2::new(sail.tack - angle_rad.cos() * sail.center_of_effort, -angle_rad.sin() * sail.center_of_effort);
+			apply(sail.force(sail_state.angle, &apparent_wind), center_of_effort);
+		}
+		// Air drag on the hull and rigging
+		apply(apparent_wind * (0.5 * AIR_DENSITY * static_.air_drag * apparent_wind.magnitude()), V2::zeros());
+		// Hull drag, sideways resistance acts at the center of lateral resistance
+		apply(V2::new(-0.5 * WATER_DENSITY * static_.forward_drag * local_vel.x * local_vel.x.abs(), 0.0), V2::zeros());
+		apply(V2::new(0.0, -0.5 * WATER_DENSITY * static_.sideways_drag * local_vel.y * local_vel.y.abs()), V2::new(static_.center_of_lateral_resistance, 0.0));
+		// Rudder, points aft from its pivot
+		if state.rudder_hp {
+			let (sin, cos) = state.rudder_angle.sin_cos();
+			let center_of_effort = V2::new(static_.rudder_pivot - cos * static_.rudder_center_of_effort, -sin * static_.rudder_center_of_effort);
+			let point_vel = local_vel + V2::new(-center_of_effort.y, center_of_effort.x) * angular_vel;
+			apply(flat_plate_force(-point_vel, V2::new(sin, -cos), static_.rudder_area, WATER_DENSITY), center_of_effort);
+		}
+		// Angular drag
+		torque -= static_.angular_drag * angular_vel * angular_vel.abs();
 		// Update translational velocity
-		// TODO
+		let new_vel = state.vel.translation.vector + rotation * (force / static_.mass) * dt;
 		// Update angular velocity
-		// TODO
+		let new_angular_vel = angular_vel + torque / static_.moment * dt;
+		state.vel = Iso::new(new_vel, new_angular_vel);
 		// Update translation
-		// TODO
-		// Update angule
-		// TODO
+		state.pos.translation.vector += new_vel * dt;
+		// Update angle
+		state.pos.rotation = UnitComplex::new(rotation.angle() + new_angular_vel * dt);
 	}
-	fn average_with_other_state(&mut self, other: Self, static_: &BoatStatic, dynamic: &Wind) {
+	fn average_with_other_state(&mut self, other: Self, _static: &BoatType, _dynamic: &Wind) {
 		self.state.average_with_other_state(other.state);
 	}
 }
@@ -145,35 +309,68 @@ impl PhysicalIntegrator<BoatStatic, Wind> for BoatPhysicalIntegrator {
 pub struct Boat {
 	/// Boat type
 	/// Incase there are many boats of the same type, it is more efficient to store one reference to the boat type, so reference counter is used
-	static_: Rc<BoatStatic>,
+	static_: Rc<BoatType>,
 	physics: BoatPhysicalIntegrator,
 	rudder_input_opt: Option<Float>
 }
 
 impl Boat {
-	pub fn update(&mut self, wind: &Wind, inputs: BoatInputs) {
-		// TODO
+	pub fn new(static_: Rc<BoatType>, state: BoatSaveState) -> Self {
+		Self {
+			static_,
+			physics: BoatPhysicalIntegrator {
+				state
+			},
+			rudder_input_opt: None
+		}
+	}
+	/// Applies `inputs` and then steps the physics by `dt`
+	pub fn update(&mut self, dt: Float, wind: &Wind, inputs: &BoatInputs) {
+		if let Some(rudder) = inputs.rudder_control {
+			self.rudder_input_opt = Some(rudder);
+		}
+		for (query, sheeting_angle) in &inputs.sheeting_angles {
+			if let Some(sail) = self.physics.state.sails.get_mut(query) {
+				sail.sheeting_angle = sheeting_angle.abs();
+			}
+		}
+		if let Some(rudder) = self.rudder_input_opt {
+			self.physics.state.rudder_angle = rudder;
+		}
+		self.physics.full_step(dt, &self.static_, wind);
+	}
+	pub fn state(&self) -> &BoatSaveState {
+		&self.physics.state
+	}
+	/// Overwrites the physical state, used when the server corrects the client
+	pub fn set_state(&mut self, state: BoatSaveState) {
+		self.physics.state = state;
+	}
+	pub fn static_(&self) -> &BoatType {
+		&self.static_
 	}
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct BoatInputs {
 	pub rudder_control: Option<Float>,
 	pub sheeting_angles: Vec<(GenericQuery<SailSaveState>, Float)>
 }
 
-impl Default for BoatInputs {
-	fn default() -> Self {
-		Self {
-			rudder_control: None,
-			sheeting_angles: Vec::new()
-		}
-	}
-}
-
 impl BoatInputs {
 	/// Update current self with new input
 	pub fn merge(&mut self, other: &Self, sails: &GenericDataset<SailSaveState>) {
-		// TODO
+		if other.rudder_control.is_some() {
+			self.rudder_control = other.rudder_control;
+		}
+		for (query, angle) in &other.sheeting_angles {
+			// Ignore sails which don't exist on this boat
+			let id = match sails.get_item_id(query) {
+				Some(id) => id,
+				None => continue
+			};
+			self.sheeting_angles.retain(|(existing_query, _)| sails.get_item_id(existing_query) != Some(id));
+			self.sheeting_angles.push((query.clone(), *angle));
+		}
 	}
 }
\ No newline at end of file
@@ -4,6 +4,21 @@ use crate::prelude::*;
 
 use super::physical_integrator::PhysicalIntegrator;
 
+// CONSTS
+pub const AIR_DENSITY: Float = 1.225;
+pub const WATER_DENSITY: Float = 1025.0;
+/// Drag coefficient along the surface of a flat object
+const SKIN_FRICTION: Float = 0.02;
+
+/// Force on a thin flat object from fluid flowing past it
+/// `flow` is the velocity of the fluid relative to the object, `normal` is a unit vector perpendicular to the object
+/// Uses the flat plate approximation: a normal force coefficient of 2*sin(angle of attack) plus skin friction along the flow
+pub fn flat_plate_force(flow: V2, normal: V2, area: Float, fluid_density: Float) -> V2 {
+	let speed = flow.magnitude();
+	let normal_speed = flow.dot(&normal);
+	normal * (fluid_density * area * normal_speed * speed) + flow * (0.5 * fluid_density * area * SKIN_FRICTION * speed)
+}
+
 pub struct FlatObjectStaticData {
     /// Area
     pub area: Float,
@@ -30,10 +45,10 @@ pub struct FlatObjectPhysicalIntegrator {
 }
 
 impl PhysicalIntegrator<FlatObjectStaticData, FlatObjectDynamicData> for FlatObjectPhysicalIntegrator {
-    fn partial_step(&mut self, dt: Float, static_: &FlatObjectStaticData, dynamic: &FlatObjectDynamicData) {
+    fn partial_step(&mut self, _dt: Float, _static: &FlatObjectStaticData, _dynamic: &FlatObjectDynamicData) {
         // TODO
     }
-    fn average_with_other_state(&mut self, other: Self, static_: &FlatObjectStaticData, dynamic: &FlatObjectDynamicData) {
+    fn average_with_other_state(&mut self, _other: Self, _static: &FlatObjectStaticData, _dynamic: &FlatObjectDynamicData) {
         // TODO
     }
 }
\ No newline at end of file
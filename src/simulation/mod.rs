@@ -1,7 +1,11 @@
 //! Simulation module
 
-use std::collections::HashMap;
-use crate::prelude::*;
+use std::{collections::{BTreeMap, HashMap, HashSet}, rc::Rc, time::SystemTime};
+use crate::{prelude::*, resource_interface::{MapSave, ResourceRef}, rng::Rng, replay::{Replay, ReplayAction}, spatial_index::LandIndex};
+use crate::course::CourseSave;
+use user::UserInput;
+use race::{RaceSave, RaceResult};
+use rules::{RulesChecker, RuleIncident};
 use serde::{Deserialize, Serialize};
 
 pub mod physical_integrator;
@@ -9,8 +13,10 @@ pub mod boat;
 pub mod wind;
 pub mod user;
 pub mod flat_object_physics;
+pub mod race;
+pub mod rules;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SimulationSettings {
 	/// Whether to save the simulation when it is quit or when there is an error.
 	pub save_sims: bool,
@@ -25,10 +31,46 @@ pub struct SimulationSettings {
 	/// Time for a client to not be responding for them to be considered disconnected
 	pub client_timeout: Float,
 	/// Upper limits to prevent the simulation from getting out of control
-	pub sanity_limits: SimulatorSanityLimits
+	pub sanity_limits: SimulatorSanityLimits,
+	/// Port to serve the browser-based spectator view on, disabled if None
+	#[serde(default)]
+	pub spectator_port_opt: Option<u16>,
+	/// Length of the countdown before the start signal, seconds
+	#[serde(default = "default_start_sequence_time")]
+	pub start_sequence_time: Float,
+	/// Boats closer than this are checked for racing-rule infringements, the checker is disabled if None
+	#[serde(default)]
+	pub rules_check_distance_opt: Option<Float>,
+	/// Whether the server records a replay of every session
+	#[serde(default)]
+	pub record_replays: bool,
+	/// Deterministic mode: if set the server steps by exactly this much (seconds) instead of its default step and replays record a state hash after every step
+	/// Results are reproducible on machines with the same float behavior, trigonometry still comes from the platform's math library
+	#[serde(default)]
+	pub fixed_time_step_opt: Option<Float>
 }
 
-#[derive(Serialize, Deserialize)]
+fn default_start_sequence_time() -> Float {
+	180.0
+}
+
+impl SimulationSettings {
+	/// Human-readable list of settings which would break the simulation
+	pub fn problems(&self) -> Vec<String> {
+		let mut out = Vec::new();
+		if !(self.max_time_step > 0.0 && self.max_time_step.is_finite()) {
+			out.push(format!("Max time step must be positive, not {}", self.max_time_step));
+		}
+		if let Some(fixed_dt) = self.fixed_time_step_opt {
+			if !(fixed_dt > 0.0 && fixed_dt.is_finite()) {
+				out.push(format!("Fixed time step must be positive, not {}", fixed_dt));
+			}
+		}
+		out
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SimulatorSanityLimits {
 	/// Max speed
 	pub speed: Float,
@@ -37,16 +79,129 @@ pub struct SimulatorSanityLimits {
 }
 
 /// Simulation "save-file"
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SimulationSave {
-	pub map_name: String,
+	pub map_name: ResourceRef<MapSave>,
 	pub local_settings_opt: Option<SimulationSettings>,
 	pub paused: bool,
 	pub password: Option<String>,
-	pub clients: HashMap<String, SimulationClientSave>
+	/// Wind that new clients start with
+	#[serde(default)]
+	pub default_wind: WindGeneratorSaveState,
+	/// Drives every client's wind, saved so that the wind continues the same sequence when the simulation is loaded again
+	#[serde(default)]
+	pub wind_rng: Rng,
+	/// Boat types users may join with, any boat type is allowed if this is empty
+	#[serde(default)]
+	pub boat_types: Vec<ResourceRef<BoatType>>,
+	/// Course to race on, must be laid out on the same map
+	#[serde(default)]
+	pub course_name_opt: Option<ResourceRef<CourseSave>>,
+	/// Race currently running on the course
+	#[serde(default)]
+	pub race: RaceSave,
+	/// Results of every finished race, oldest first
+	#[serde(default)]
+	pub race_results: Vec<RaceResult>,
+	/// Racing-rule infringements, oldest first
+	#[serde(default)]
+	pub rule_incidents: Vec<RuleIncident>,
+	/// Ordered by username so that every step processes clients in the same order
+	pub clients: BTreeMap<String, SimulationClientSave>
+}
+
+impl SimulationSave {
+	/// New simulation with no clients
+	pub fn new(map_name: String, default_wind: WindGeneratorSaveState, password: Option<String>) -> Self {
+		Self {
+			map_name: ResourceRef::new(map_name),
+			local_settings_opt: None,
+			paused: false,
+			password,
+			default_wind,
+			wind_rng: Rng::new(crate::leaderboard::unix_time_now()),
+			boat_types: Vec::new(),
+			course_name_opt: None,
+			race: RaceSave::default(),
+			race_results: Vec::new(),
+			rule_incidents: Vec::new(),
+			clients: BTreeMap::new()
+		}
+	}
+	/// Loads every resource this save refers to, or lists every reference which can't be loaded
+	/// Each boat type is only loaded (and reported) once, even if several clients use it
+	pub fn resolve(&self) -> Result<ResolvedResources, Vec<String>> {
+		let mut dangling = Vec::new();
+		let map_opt = match self.map_name.load() {
+			Ok(map) => Some(map),
+			Err(e) => {
+				dangling.push(format!("Map: {}", e));
+				None
+			}
+		};
+		let course_opt = match &self.course_name_opt {
+			Some(course_name) => match course_name.load() {
+				Ok(course) => Some(course),
+				Err(e) => {
+					dangling.push(format!("Course: {}", e));
+					None
+				}
+			},
+			None => None
+		};
+		let mut boat_types: HashMap<String, Rc<BoatType>> = HashMap::new();
+		let mut missing_boat_types: HashSet<&str> = HashSet::new();
+		let allowed = self.boat_types.iter().map(|type_name| (type_name, "Allowed boat type".to_owned()));
+		let used = self.clients.iter().map(|(username, client)| (&client.boat.type_name, format!("Boat type of client \"{}\"", username)));
+		for (type_name, description) in allowed.chain(used) {
+			if boat_types.contains_key(type_name.name()) || missing_boat_types.contains(type_name.name()) {
+				continue;
+			}
+			match type_name.load() {
+				Ok(boat_type) => {
+					boat_types.insert(type_name.name().to_owned(), Rc::new(boat_type));
+				},
+				Err(e) => {
+					dangling.push(format!("{}: {}", description, e));
+					missing_boat_types.insert(type_name.name());
+				}
+			}
+		}
+		match (map_opt, dangling.is_empty()) {
+			(Some(map), true) => Ok(ResolvedResources {
+				map,
+				course_opt,
+				boat_types
+			}),
+			_ => Err(dangling)
+		}
+	}
+	/// Human-readable list of everything wrong with this save, including resources it refers to that can't be loaded
+	pub fn problems(&self) -> Vec<String> {
+		let mut out = match self.resolve() {
+			Ok(resolved) => match (&resolved.course_opt, &self.course_name_opt) {
+				(Some(course), Some(course_name)) if course.map_name != self.map_name => vec![format!("Course \"{}\" is for map \"{}\", not \"{}\"", course_name, course.map_name, self.map_name)],
+				_ => Vec::new()
+			},
+			Err(dangling) => dangling
+		};
+		if let Some(settings) = &self.local_settings_opt {
+			out.extend(settings.problems().into_iter().map(|problem| format!("Local settings: {}", problem)));
+		}
+		out
+	}
 }
 
-#[derive(Serialize, Deserialize)]
+/// Resources a `SimulationSave` refers to, loaded by `SimulationSave::resolve()`
+#[derive(Clone)]
+pub struct ResolvedResources {
+	pub map: MapSave,
+	pub course_opt: Option<CourseSave>,
+	/// By name
+	pub boat_types: HashMap<String, Rc<BoatType>>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SimulationClientSave {
 	pub has_finished: bool,
 	pub paused: bool,
@@ -60,16 +215,392 @@ pub struct SimulationClientSave {
 	/// Time since latest global reset
 	pub time: Float,
 	/// Best time on the course
-	pub best_time: Float
+	pub best_time: Float,
+	/// Timestamped version of the tracer, for exporting to chart software
+	#[serde(default)]
+	pub track: Vec<TrackPoint>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TrackPoint {
+	/// Unix timestamp, seconds
+	pub date: f64,
+	pub pos: V2,
+	/// Speed over ground
+	pub speed: Float,
+	/// Radians CCW from +X
+	pub heading: Float
+}
+
+impl SimulationClientSave {
+	pub fn new(boat_start: BoatSaveState, wind: WindGeneratorSaveState) -> Self {
+		Self {
+			has_finished: false,
+			paused: false,
+			tracer_list: vec![boat_start.pos.translation.vector],
+			time_since_reset: 0.0,
+			autopilot_enabled: false,
+			autopilot_state: crate::autopilot::AutopilotSave::default(),
+			boat: boat_start.clone(),
+			boat_start,
+			wind,
+			time: 0.0,
+			best_time: 0.0,
+			track: Vec::new()
+		}
+	}
+	/// Adds the boat's current position to the tracer and track if it is far enough from the previous point
+	fn update_tracer(&mut self, resolution: Float, date: f64) {
+		let pos = self.boat.pos.translation.vector;
+		let far_enough = match self.tracer_list.last() {
+			Some(last) => (pos - last).magnitude() >= resolution,
+			None => true
+		};
+		if far_enough {
+			self.tracer_list.push(pos);
+			self.track.push(TrackPoint {
+				date,
+				pos,
+				speed: self.boat.vel.translation.vector.magnitude(),
+				heading: self.boat.heading()
+			});
+		}
+	}
 }
 
 /// Main simulation class
 pub struct Simulation {
-    save_state: SimulationSave
+	save_state: SimulationSave,
+	/// Local settings if the save has them, otherwise global settings
+	settings: SimulationSettings,
+	map: MapSave,
+	/// Built from `map` on load, for fast land queries
+	land_index: LandIndex,
+	course_opt: Option<CourseSave>,
+	/// Boat types loaded so far, keyed by name
+	boat_types: HashMap<String, Rc<BoatType>>,
+	/// Live boats, keyed by username, their states are copied into `save_state` after every step
+	boats: BTreeMap<String, Boat>,
+	/// Latest merged inputs for each user
+	inputs: BTreeMap<String, BoatInputs>,
+	/// Autopilots, keyed by username, their states are copied into `save_state` after every step
+	autopilots: BTreeMap<String, Autopilot>,
+	rules_checker: RulesChecker,
+	/// Everything that happens to the simulation is added to this while recording
+	replay_opt: Option<Replay>,
+	/// Unix timestamp (seconds) when `time` was 0, for track timestamps
+	start_date: f64,
+	/// Time since the simulation was loaded
+	time: Float
 }
 
 impl Simulation {
-    pub fn load(save: SimulationSave) -> Result<Self, String> {
+	/// Fails listing every dangling reference in `save` if any resource it refers to can't be loaded
+	pub fn load(save: SimulationSave) -> Result<Self, String> {
+		let resources = save.resolve().map_err(|dangling| format!("{} dangling reference(s) in simulation:\n{}", dangling.len(), dangling.join("\n")))?;
+		Self::from_resources(save, resources)
+	}
+	/// Same as `load()` with resources which have already been loaded, boat types missing from `resources` are loaded when needed
+	pub fn from_resources(save: SimulationSave, resources: ResolvedResources) -> Result<Self, String> {
+		let ResolvedResources{map, course_opt, boat_types} = resources;
+		let settings = match &save.local_settings_opt {
+			Some(settings) => settings.clone(),
+			None => resource_interface::load_settings()?.simulator
+		};
+		let problems = settings.problems();
+		if !problems.is_empty() {
+			return Err(format!("Invalid simulation settings:\n{}", problems.join("\n")));
+		}
+		let mut out = Self {
+			save_state: save,
+			settings,
+			land_index: LandIndex::new(&map),
+			map,
+			course_opt,
+			boat_types,
+			boats: BTreeMap::new(),
+			inputs: BTreeMap::new(),
+			autopilots: BTreeMap::new(),
+			rules_checker: RulesChecker::default(),
+			replay_opt: None,
+			start_date: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0),
+			time: 0.0
+		};
+		let usernames: Vec<String> = out.save_state.clients.keys().cloned().collect();
+		for username in usernames {
+			out.load_boat(&username)?;
+		}
+		Ok(out)
+	}
+	/// Creates the live boat for `username` from their save state, loading the boat type if needed
+	fn load_boat(&mut self, username: &str) -> Result<(), String> {
+		let (state, autopilot_state) = match self.save_state.clients.get(username) {
+			Some(client) => (client.boat.clone(), client.autopilot_state.clone()),
+			None => return Err(format!("No client named \"{}\"", username))
+		};
+		let boat_type = self.get_boat_type(state.type_name.name())?;
+		self.boats.insert(username.to_owned(), Boat::new(boat_type, state));
+		self.autopilots.insert(username.to_owned(), Autopilot::from_save(autopilot_state));
+		Ok(())
+	}
+	/// Adds a new client starting at the map's default start, does nothing if they already exist
+	pub fn add_client(&mut self, username: &str, boat_type_name: &str) -> Result<(), String> {
+		if self.save_state.clients.contains_key(username) {
+			return Ok(());
+		}
+		if !self.save_state.boat_types.is_empty() && !self.save_state.boat_types.iter().any(|type_name| type_name.name() == boat_type_name) {
+			return Err(format!("Boat type \"{}\" is not allowed in this simulation", boat_type_name));
+		}
+		let boat_type = self.get_boat_type(boat_type_name)?;
+		let boat_start = BoatSaveState::new(boat_type_name.to_owned(), &boat_type, Iso::translation(self.map.global_default_start.x, self.map.global_default_start.y));
+		self.save_state.clients.insert(username.to_owned(), SimulationClientSave::new(boat_start, self.save_state.default_wind.clone()));
+		self.record(ReplayAction::Join{username: username.to_owned(), boat_type: boat_type_name.to_owned()});
+		self.load_boat(username)
+	}
+	pub fn get_boat_type(&mut self, name: &str) -> Result<Rc<BoatType>, String> {
+		if let Some(boat_type) = self.boat_types.get(name) {
+			return Ok(boat_type.clone());
+		}
+		let boat_type = Rc::new(resource_interface::load_boat_type(name)?);
+		self.boat_types.insert(name.to_owned(), boat_type.clone());
+		Ok(boat_type)
+	}
+	/// Merges new input from a user, it will be used for all following steps until changed
+	pub fn apply_input(&mut self, username: &str, input: UserInput) -> Result<(), String> {
+		self.record(ReplayAction::Input{username: username.to_owned(), input: input.clone()});
+		let boat = match self.boats.get(username) {
+			Some(boat) => boat,
+			None => return Err(format!("No boat for user \"{}\"", username))
+		};
+		if let Some(boat_inputs) = input.boat {
+			self.inputs.entry(username.to_owned()).or_default().merge(&boat_inputs, &boat.state().sails);
+		}
+		if let Some(autopilot_inputs) = input.autopilot {
+			if let (Some(autopilot), Some(client)) = (self.autopilots.get_mut(username), self.save_state.clients.get_mut(username)) {
+				autopilot.handle_input(&autopilot_inputs);
+				if let Some(waypoints) = &autopilot_inputs.waypoints_opt {
+					let path = crate::autopilot::route::plan_route(&self.map, &self.land_index, boat.static_(), &client.wind.vector(), boat.state().pos.translation.vector, waypoints)?;
+					autopilot.set_mode(AutopilotMode::Route{path, next: 0});
+				}
+				if let Some(enabled) = autopilot_inputs.enabled_opt {
+					if enabled && !client.autopilot_enabled {
+						autopilot.reset();
+					}
+					client.autopilot_enabled = enabled;
+				}
+				client.autopilot_state = autopilot.save();
+			}
+		}
+		Ok(())
+	}
+	/// Steps every un-paused boat by `dt`
+	pub fn step(&mut self, dt: Float) {
+		if self.save_state.paused {
+			return;
+		}
+		self.time += dt;
+		for client in self.save_state.clients.values_mut() {
+			if !client.paused {
+				client.wind.update(dt, &mut self.save_state.wind_rng);
+			}
+		}
+		let prev_positions: BTreeMap<String, V2> = self.save_state.clients.iter().map(|(username, client)| (username.clone(), client.boat.pos.translation.vector)).collect();
+		for (username, client) in self.save_state.clients.iter_mut() {
+			if client.paused {
+				continue;
+			}
+			let boat = match self.boats.get_mut(username) {
+				Some(boat) => boat,
+				None => continue
+			};
+			let wind = client.wind.vector();
+			let mut inputs = self.inputs.get(username).cloned().unwrap_or_default();
+			// Autopilot overrides the user's rudder and sheeting inputs
+			if let Some(autopilot) = self.autopilots.get_mut(username) {
+				if client.autopilot_enabled {
+					inputs.merge(&autopilot.update(dt, boat.state(), boat.static_(), &wind, self.settings.max_rudder_movement), &boat.state().sails);
+					client.autopilot_state = autopilot.save();
+				}
+				if autopilot.auto_trim() {
+					inputs.merge(&crate::autopilot::optimal_sheeting_angles(boat.state(), boat.static_(), &wind), &boat.state().sails);
+				}
+			}
+			boat.update(dt, &wind, &inputs);
+			client.boat = boat.state().clone();
+			client.time += dt;
+			client.time_since_reset += dt;
+			if self.settings.tracer_enabled {
+				client.update_tracer(self.settings.tracer_resulution, self.start_date + self.time as f64);
+			}
+		}
+		if let (Some(course), Some(course_name)) = (&self.course_opt, &self.save_state.course_name_opt) {
+			if let Some(mut result) = self.save_state.race.update(dt, course, course_name.name(), &prev_positions, &mut self.save_state.clients) {
+				result.apply_handicaps(&self.boat_types, course.length());
+				self.save_state.race_results.push(result);
+			}
+		}
+		if let Some(check_distance) = self.settings.rules_check_distance_opt {
+			let incidents = self.rules_checker.check(check_distance, &self.save_state.clients, &self.boat_types, &self.save_state.race, self.course_opt.as_ref());
+			self.save_state.rule_incidents.extend(incidents);
+		}
+		if self.replay_opt.is_some() {
+			let state_hash_opt = self.settings.fixed_time_step_opt.map(|_| self.state_hash());
+			self.record(ReplayAction::Step{dt, state_hash_opt});
+		}
+	}
+	/// Hash of everything which affects future steps, two simulations with the same hash stay in sync when given the same inputs
+	/// Floats are hashed by their exact bits and clients in username order, so this is only equal across runs in deterministic mode
+	pub fn state_hash(&self) -> u64 {
+		let mut hasher = StateHasher::new();
+		hasher.write_float(self.time);
+		hasher.write(&self.save_state.wind_rng.state().to_le_bytes());
+		hasher.write_float(self.save_state.race.time);
+		for (username, client) in &self.save_state.clients {
+			hasher.write(username.as_bytes());
+			for iso in [&client.boat.pos, &client.boat.vel] {
+				hasher.write_float(iso.translation.vector.x);
+				hasher.write_float(iso.translation.vector.y);
+				hasher.write_float(iso.rotation.re);
+				hasher.write_float(iso.rotation.im);
+			}
+			hasher.write_float(client.boat.rudder_angle);
+			hasher.write_float(client.boat.hull_hp);
+			for (_, sail) in &client.boat.sails {
+				hasher.write_float(sail.angle);
+				hasher.write_float(sail.sheeting_angle);
+			}
+			hasher.write_float(client.wind.speed);
+			hasher.write_float(client.wind.direction);
+			hasher.write_float(client.autopilot_state.integral);
+			hasher.write_float(client.autopilot_state.prev_error_opt.unwrap_or(0.0));
+		}
+		hasher.finish()
+	}
+	/// Starts the countdown for a new race, any race in progress is ended first
+	pub fn start_race(&mut self) -> Result<(), String> {
+		if self.course_opt.is_none() {
+			return Err("This simulation has no course".to_owned());
+		}
+		self.end_race();
+		self.record(ReplayAction::StartRace);
+		self.save_state.race.start(self.settings.start_sequence_time, &mut self.save_state.clients);
+		Ok(())
+	}
+	/// Ends the current race if there is one, boats which haven't finished are recorded as DNF
+	pub fn end_race(&mut self) {
+		if self.save_state.race.phase == race::RacePhase::Idle {
+			return;
+		}
+		self.record(ReplayAction::EndRace);
+		if let (Some(course), Some(course_name)) = (&self.course_opt, &self.save_state.course_name_opt) {
+			let mut result = self.save_state.race.end(course_name.name(), &self.save_state.clients);
+			result.apply_handicaps(&self.boat_types, course.length());
+			self.save_state.race_results.push(result);
+		}
+	}
+	/// Starts recording a replay from the current state, any replay already being recorded is discarded
+	pub fn start_recording(&mut self) {
+		self.replay_opt = Some(Replay::new(self.save_state.clone(), self.settings.clone(), self.time, self.start_date));
+	}
+	/// Stops recording and returns the replay, if one was being recorded
+	pub fn stop_recording(&mut self) -> Option<Replay> {
+		self.replay_opt.take()
+	}
+	fn record(&mut self, action: ReplayAction) {
+		if let Some(replay) = &mut self.replay_opt {
+			replay.record(self.time, action);
+		}
+	}
+	/// Only used to line up replays with the time they were recorded at
+	pub fn set_time(&mut self, time: Float, start_date: f64) {
+		self.time = time;
+		self.start_date = start_date;
+	}
+	pub fn course(&self) -> Option<&CourseSave> {
+		self.course_opt.as_ref()
+	}
+	pub fn save_state(&self) -> &SimulationSave {
+		&self.save_state
+	}
+	pub fn settings(&self) -> &SimulationSettings {
+		&self.settings
+	}
+	pub fn map(&self) -> &MapSave {
+		&self.map
+	}
+	pub fn land_index(&self) -> &LandIndex {
+		&self.land_index
+	}
+	pub fn boat(&self, username: &str) -> Option<&Boat> {
+		self.boats.get(username)
+	}
+	pub fn time(&self) -> Float {
+		self.time
+	}
+}
+
+/// FNV-1a, used instead of `DefaultHasher` because its output is specified and the same on every machine and Rust version
+struct StateHasher(u64);
+
+impl StateHasher {
+	fn new() -> Self {
+		Self(0xCBF2_9CE4_8422_2325)
+	}
+	fn write(&mut self, bytes: &[u8]) {
+		for byte in bytes {
+			self.0 ^= *byte as u64;
+			self.0 = self.0.wrapping_mul(0x0000_0100_0000_01B3);
+		}
+	}
+	fn write_float(&mut self, x: Float) {
+		self.write(&x.to_bits().to_le_bytes());
+	}
+	fn finish(&self) -> u64 {
+		self.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils;
+
+	#[test]
+	fn non_positive_fixed_time_steps_are_rejected() {
+		for fixed_dt in [0.0, -0.02, Float::NAN, Float::INFINITY] {
+			let mut save = test_utils::save();
+			save.local_settings_opt.as_mut().unwrap().fixed_time_step_opt = Some(fixed_dt);
+			assert!(Simulation::from_resources(save, test_utils::resources(test_utils::open_water_map(1000))).is_err(), "Accepted fixed time step {}", fixed_dt);
+		}
+		assert!(Simulation::from_resources(test_utils::save(), test_utils::resources(test_utils::open_water_map(1000))).is_ok());
+	}
 
-    }
-}
\ No newline at end of file
+	#[test]
+	fn every_dangling_reference_is_listed() {
+		let mut save = SimulationSave::new("missing_test_map".to_owned(), WindGeneratorSaveState::default(), None);
+		save.course_name_opt = Some(ResourceRef::new("missing_test_course".to_owned()));
+		save.boat_types = vec![ResourceRef::new("missing_test_boat_a".to_owned()), ResourceRef::new("missing_test_boat_b".to_owned())];
+		let dangling = match save.resolve() {
+			Ok(_) => panic!("Resolved a save with missing resources"),
+			Err(dangling) => dangling
+		};
+		assert_eq!(dangling.len(), 4);
+		let error = match Simulation::load(save) {
+			Ok(_) => panic!("Loaded a save with missing resources"),
+			Err(e) => e
+		};
+		for name in ["missing_test_map", "missing_test_course", "missing_test_boat_a", "missing_test_boat_b"] {
+			assert!(error.contains(name), "\"{}\" is not listed in: {}", name, error);
+		}
+	}
+
+	#[test]
+	fn saves_without_new_fields_load() {
+		let mut save = serde_json::to_value(test_utils::save()).unwrap();
+		// Only the fields saves have always had
+		save.as_object_mut().unwrap().retain(|key, _| ["map_name", "local_settings_opt", "paused", "password", "clients"].contains(&key.as_str()));
+		let save: SimulationSave = serde_json::from_value(save).unwrap();
+		assert_eq!(save.default_wind.speed_average, WindGeneratorSaveState::default().speed_average);
+		assert!(save.boat_types.is_empty() && save.course_name_opt.is_none() && save.race_results.is_empty());
+	}
+}
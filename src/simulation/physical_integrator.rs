@@ -10,9 +10,9 @@ pub trait PhysicalIntegrator<STATIC, DYNAMIC>: Clone {
 	/// Special method I made up, should prevent "bouncing"
 	/// This is the only method that should be called outside of this trait
 	fn full_step(&mut self, dt: Float, static_: &STATIC, dynamic: &DYNAMIC) {
-		self.partial_step(dt * 0.666666666, static_, dynamic);
+		self.partial_step(dt * 0.666_666_7, static_, dynamic);
 		let self_clone = self.clone();
-		self.partial_step(dt * 0.666666666, static_, dynamic);
+		self.partial_step(dt * 0.666_666_7, static_, dynamic);
 		self.average_with_other_state(self_clone, static_, dynamic);
 	}
 }
\ No newline at end of file
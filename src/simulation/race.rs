@@ -0,0 +1,561 @@
+//! Race controller: countdown start sequence, OCS detection, mark rounding, finish detection and results
+
+use std::{collections::{BTreeMap, HashMap}, fmt, rc::Rc};
+use crate::{prelude::*, course::{CourseSave, CourseElement, RoundingSide}, leaderboard::unix_time_now};
+use serde::{Deserialize, Serialize};
+
+use super::{SimulationClientSave, boat::BoatType};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub enum RacePhase {
+	/// No race running
+	#[default]
+	Idle,
+	/// Counting down to the start signal
+	Countdown,
+	Racing
+}
+
+/// Progress of one boat around the course
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RaceClientState {
+	/// Crossed the start line after the start signal (and after clearing an OCS)
+	pub started: bool,
+	/// On the course side of the start line at the start signal, must go back to the pre-start side before starting
+	pub ocs: bool,
+	/// Index in the course's marks of the one currently being sailed to, == number of marks when heading to the finish
+	pub next_element: usize,
+	/// Angle swept around the current mark while inside its zone, radians, + = CCW
+	pub swept_angle: Float,
+	/// Race time when the finish line was crossed
+	pub finish_time_opt: Option<Float>,
+	/// Wind the boat has had since the start signal
+	#[serde(default)]
+	pub wind: WindAccumulator
+}
+
+/// Time-weighted sums of the wind, for its mean over a run
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WindAccumulator {
+	/// Integral of the speed over time
+	speed_sum: Float,
+	/// Integral of the unit vector in the wind's direction over time, so that directions either side of 0/360 don't average to 180
+	direction_sum: [Float; 2],
+	time: Float
+}
+
+impl WindAccumulator {
+	pub fn add(&mut self, wind: &WindGeneratorSaveState, dt: Float) {
+		let direction = wind.direction.to_radians();
+		self.speed_sum += wind.speed * dt;
+		self.direction_sum[0] += direction.cos() * dt;
+		self.direction_sum[1] += direction.sin() * dt;
+		self.time += dt;
+	}
+	/// None if nothing has been added
+	pub fn mean(&self) -> Option<MeanWind> {
+		if self.time <= 0.0 {
+			return None;
+		}
+		Some(MeanWind {
+			speed: self.speed_sum / self.time,
+			direction: self.direction_sum[1].atan2(self.direction_sum[0]).to_degrees().rem_euclid(360.0)
+		})
+	}
+}
+
+/// Wind averaged over a run
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct MeanWind {
+	/// m/s
+	pub speed: Float,
+	/// Degrees CCW from +X, the direction the wind is blowing towards
+	pub direction: Float
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum RaceResultStatus {
+	Finished,
+	/// On course side at the start and never restarted
+	Ocs,
+	/// Did not finish
+	Dnf
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RaceResultEntry {
+	pub username: String,
+	pub boat_type: String,
+	pub status: RaceResultStatus,
+	/// Time from the start signal to crossing the finish line
+	pub elapsed_time_opt: Option<Float>,
+	/// Elapsed time adjusted by the boat type's handicap, None if the boat didn't finish or any boat type in the race has no handicap
+	#[serde(default)]
+	pub corrected_time_opt: Option<Float>,
+	/// Mean wind from the start signal until the boat finished or the race ended
+	#[serde(default)]
+	pub mean_wind_opt: Option<MeanWind>
+}
+
+impl RaceResultEntry {
+	/// Corrected time if there is one, otherwise elapsed time, either every finisher has a corrected time or none do
+	fn ranking_time_opt(&self) -> Option<Float> {
+		self.corrected_time_opt.or(self.elapsed_time_opt)
+	}
+}
+
+/// Results table for one race, entries are sorted with finishers first by corrected time (elapsed time if the race isn't handicapped)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RaceResult {
+	pub course_name: String,
+	/// Unix timestamp (seconds) of the start signal
+	pub date: u64,
+	pub entries: Vec<RaceResultEntry>,
+	/// Boat types without a handicap in a race where other boat types have one
+	/// Elapsed and corrected times can't be compared, so if there are any the race is ranked by elapsed time only
+	#[serde(default)]
+	pub unrated_boat_types: Vec<String>
+}
+
+/// State of the current race, saved with the simulation
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RaceSave {
+	pub phase: RacePhase,
+	/// Time since the start signal, negative during the countdown
+	pub time: Float,
+	pub clients: BTreeMap<String, RaceClientState>,
+	/// Unix timestamp (seconds) of the start signal
+	pub date: u64
+}
+
+impl RaceSave {
+	/// Starts the countdown for every client in `clients`, their race timers are reset
+	pub fn start(&mut self, countdown: Float, clients: &mut BTreeMap<String, SimulationClientSave>) {
+		self.phase = RacePhase::Countdown;
+		self.time = -countdown.abs();
+		self.date = unix_time_now() + countdown.abs() as u64;
+		self.clients = clients.keys().map(|username| (username.clone(), RaceClientState::default())).collect();
+		for client in clients.values_mut() {
+			client.has_finished = false;
+			client.time = self.time;
+		}
+	}
+	/// Advances the race, `prev_positions` are the boat positions before this step and `clients` have the positions after it
+	/// Returns the results once every boat has finished
+	pub fn update(&mut self, dt: Float, course: &CourseSave, course_name: &str, prev_positions: &BTreeMap<String, V2>, clients: &mut BTreeMap<String, SimulationClientSave>) -> Option<RaceResult> {
+		if self.phase == RacePhase::Idle {
+			return None;
+		}
+		let prev_time = self.time;
+		self.time += dt;
+		let start_signal = prev_time < 0.0 && self.time >= 0.0;
+		if start_signal {
+			self.phase = RacePhase::Racing;
+		}
+		let pre_start_side = course.pre_start_side();
+		for (username, client) in clients.iter_mut() {
+			let state = match self.clients.get_mut(username) {
+				Some(state) => state,
+				// Joined after the countdown started
+				None => continue
+			};
+			client.time = self.time;
+			let now = client.boat.pos.translation.vector;
+			let prev = match prev_positions.get(username) {
+				Some(prev) => *prev,
+				None => now
+			};
+			// Where the boat was at the start signal, a boat crossing the line later in the same step is not OCS
+			let at_signal = prev + (now - prev) * (-prev_time / dt).clamp(0.0, 1.0);
+			if start_signal && course.start_line.side(&at_signal) * pre_start_side < 0.0 {
+				state.ocs = true;
+			}
+			if self.phase != RacePhase::Racing || state.finish_time_opt.is_some() {
+				continue;
+			}
+			state.wind.add(&client.wind, dt);
+			// Start
+			if !state.started {
+				if course.start_line.is_crossed(&prev, &now) {
+					let crossed_to_course_side = course.start_line.side(&now) * pre_start_side < 0.0;
+					match (state.ocs, crossed_to_course_side) {
+						// Returned to the pre-start side, OCS is cleared but the boat still has to start
+						(true, false) => state.ocs = false,
+						(false, true) => {
+							state.started = true;
+							client.time_since_reset = 0.0;
+						},
+						_ => {}
+					}
+				}
+				continue;
+			}
+			// Marks
+			match course.marks.get(state.next_element) {
+				Some(element) => if element_passed(element, course, state, &prev, &now) {
+					state.next_element += 1;
+					state.swept_angle = 0.0;
+				},
+				// Finish
+				None => if course.finish_line.is_crossed(&prev, &now) {
+					state.finish_time_opt = Some(self.time);
+					client.has_finished = true;
+					if client.best_time <= 0.0 || self.time < client.best_time {
+						client.best_time = self.time;
+					}
+				}
+			}
+		}
+		// Race is over once every boat has finished
+		if self.phase == RacePhase::Racing && !self.clients.is_empty() && self.clients.values().all(|state| state.finish_time_opt.is_some()) {
+			return Some(self.end(course_name, clients));
+		}
+		None
+	}
+	/// Ends the race, boats which haven't finished are DNF (or OCS)
+	pub fn end(&mut self, course_name: &str, clients: &BTreeMap<String, SimulationClientSave>) -> RaceResult {
+		let mut entries: Vec<RaceResultEntry> = self.clients.iter().map(|(username, state)| RaceResultEntry {
+			username: username.clone(),
+			boat_type: clients.get(username).map(|client| client.boat.type_name.name().to_owned()).unwrap_or_default(),
+			status: match (state.finish_time_opt, state.ocs) {
+				(Some(_), _) => RaceResultStatus::Finished,
+				(None, true) => RaceResultStatus::Ocs,
+				(None, false) => RaceResultStatus::Dnf
+			},
+			elapsed_time_opt: state.finish_time_opt,
+			corrected_time_opt: None,
+			mean_wind_opt: state.wind.mean()
+		}).collect();
+		entries.sort_by(|a, b| a.username.cmp(&b.username));
+		let mut result = RaceResult {
+			course_name: course_name.to_owned(),
+			date: self.date,
+			entries,
+			unrated_boat_types: Vec::new()
+		};
+		result.sort();
+		*self = Self::default();
+		result
+	}
+}
+
+impl RaceResult {
+	/// Fills in corrected times for finishers and re-sorts the entries, only if every boat type in the race has a handicap
+	pub fn apply_handicaps(&mut self, boat_types: &HashMap<String, Rc<BoatType>>, course_length: Float) {
+		let handicap_of = |boat_type: &str| boat_types.get(boat_type).and_then(|boat_type| boat_type.handicap_opt.clone());
+		let mut unrated_boat_types: Vec<String> = self.entries.iter().filter(|entry| handicap_of(&entry.boat_type).is_none()).map(|entry| entry.boat_type.clone()).collect();
+		unrated_boat_types.sort();
+		unrated_boat_types.dedup();
+		let any_rated = self.entries.iter().any(|entry| handicap_of(&entry.boat_type).is_some());
+		self.unrated_boat_types = match any_rated {
+			true => unrated_boat_types,
+			false => Vec::new()
+		};
+		let handicapped = any_rated && self.unrated_boat_types.is_empty();
+		for entry in self.entries.iter_mut() {
+			entry.corrected_time_opt = match (entry.elapsed_time_opt, handicap_of(&entry.boat_type)) {
+				(Some(elapsed_time), Some(handicap)) if handicapped => Some(handicap.corrected_time(elapsed_time, course_length)),
+				_ => None
+			};
+		}
+		self.sort();
+	}
+	/// Finishers first by corrected time, the sort is stable so non-finishers stay in name order
+	fn sort(&mut self) {
+		self.entries.sort_by(|a, b| match (a.ranking_time_opt(), b.ranking_time_opt()) {
+			(Some(a_time), Some(b_time)) => a_time.total_cmp(&b_time),
+			(Some(_), None) => std::cmp::Ordering::Less,
+			(None, Some(_)) => std::cmp::Ordering::Greater,
+			(None, None) => std::cmp::Ordering::Equal
+		});
+	}
+}
+
+impl fmt::Display for RaceResult {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "Results for course \"{}\"", self.course_name)?;
+		writeln!(f, "{:>3}  {:<20} {:<16} {:>10} {:>10}", "#", "User", "Boat type", "Elapsed", "Corrected")?;
+		let format_time = |time_opt: Option<Float>| time_opt.map(|time| format!("{:.2}", time)).unwrap_or_else(|| "-".to_owned());
+		for (i, entry) in self.entries.iter().enumerate() {
+			let place = match entry.status {
+				RaceResultStatus::Finished => (i + 1).to_string(),
+				RaceResultStatus::Ocs => "OCS".to_owned(),
+				RaceResultStatus::Dnf => "DNF".to_owned()
+			};
+			writeln!(f, "{:>3}  {:<20} {:<16} {:>10} {:>10}", place, entry.username, entry.boat_type, format_time(entry.elapsed_time_opt), format_time(entry.corrected_time_opt))?;
+		}
+		if !self.unrated_boat_types.is_empty() {
+			writeln!(f, "Ranked by elapsed time, no handicap for: {}", self.unrated_boat_types.join(", "))?;
+		}
+		Ok(())
+	}
+}
+
+/// How far around mark number `index` (radians) a boat has to sail, while inside the zone, for the rounding to count
+/// This is from the bearing it approaches from to past the bearing pointing out of the turn, about PI for a mark where the course turns back on itself, so sailing straight past the mark doesn't count
+fn required_sweep(course: &CourseSave, index: usize) -> Float {
+	let (pos, rounding) = match course.marks.get(index) {
+		Some(CourseElement::Mark{pos, rounding}) => (*pos, rounding),
+		_ => return 0.0
+	};
+	let prev_target = match index {
+		0 => course.start_line.center(),
+		_ => course.marks[index - 1].target()
+	};
+	let next_target = course.marks.get(index + 1).map(CourseElement::target).unwrap_or_else(|| course.finish_line.center());
+	let (leg_in, leg_out) = (pos - prev_target, next_target - pos);
+	let turn = wrap_angle(leg_out.y.atan2(leg_out.x) - leg_in.y.atan2(leg_in.x));
+	// Keeping the mark to port means turning CCW
+	let turn = match rounding {
+		RoundingSide::Port => turn,
+		RoundingSide::Starboard => -turn
+	};
+	(PI + turn.rem_euclid(2.0 * PI)) / 2.0
+}
+
+/// Checks whether the boat has completed the current mark or gate, updating its swept angle for marks
+fn element_passed(element: &CourseElement, course: &CourseSave, state: &mut RaceClientState, prev: &V2, now: &V2) -> bool {
+	match element {
+		CourseElement::Gate(line) => line.is_crossed(prev, now),
+		CourseElement::Mark{pos, rounding} => {
+			let (prev_offset, now_offset) = (prev - pos, now - pos);
+			if now_offset.magnitude() > course.mark_zone_radius {
+				state.swept_angle = 0.0;
+				return false;
+			}
+			state.swept_angle += wrap_angle(now_offset.y.atan2(now_offset.x) - prev_offset.y.atan2(prev_offset.x));
+			// Keeping the mark to port means circling it CCW
+			let required_sweep = required_sweep(course, state.next_element);
+			match rounding {
+				RoundingSide::Port => state.swept_angle >= required_sweep,
+				RoundingSide::Starboard => state.swept_angle <= -required_sweep
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{course::Line, resource_interface::ResourceRef, simulation::boat::Handicap, test_utils};
+
+	/// Start line along the X axis, a windward mark 200m north to be left to port, finishing just south of the start line
+	fn course() -> CourseSave {
+		CourseSave {
+			map_name: ResourceRef::new("test_map".to_owned()),
+			start_line: Line::new(V2::new(-20.0, 0.0), V2::new(20.0, 0.0)),
+			marks: vec![CourseElement::Mark{pos: V2::new(0.0, 200.0), rounding: RoundingSide::Port}],
+			finish_line: Line::new(V2::new(-20.0, -10.0), V2::new(20.0, -10.0)),
+			mark_zone_radius: 20.0
+		}
+	}
+
+	struct TestRace {
+		course: CourseSave,
+		race: RaceSave,
+		clients: BTreeMap<String, SimulationClientSave>
+	}
+
+	impl TestRace {
+		/// One boat, "alice", at `pos` with `countdown` seconds to the start signal
+		fn new(pos: V2, countdown: Float) -> Self {
+			let boat = BoatSaveState::new(test_utils::BOAT_TYPE_NAME.to_owned(), &test_utils::boat_type(), Iso::translation(pos.x, pos.y));
+			let mut clients = BTreeMap::from([("alice".to_owned(), SimulationClientSave::new(boat, WindGeneratorSaveState::default()))]);
+			let mut race = RaceSave::default();
+			race.start(countdown, &mut clients);
+			Self {
+				course: course(),
+				race,
+				clients
+			}
+		}
+		/// Moves the boat to `to` in a single step of `dt`
+		fn step(&mut self, to: V2, dt: Float) -> Option<RaceResult> {
+			let client = self.clients.get_mut("alice").unwrap();
+			let prev_positions = BTreeMap::from([("alice".to_owned(), client.boat.pos.translation.vector)]);
+			client.boat.pos.translation.vector = to;
+			self.race.update(dt, &self.course, "test", &prev_positions, &mut self.clients)
+		}
+		/// Sails in a straight line to each of `waypoints` in 1 second, 1 meter steps, returns the results if the race ended
+		fn sail(&mut self, waypoints: &[V2]) -> Option<RaceResult> {
+			let mut result_opt = None;
+			for waypoint in waypoints {
+				let from = self.clients["alice"].boat.pos.translation.vector;
+				let steps = (waypoint - from).magnitude().ceil().max(1.0) as usize;
+				for i in 1..=steps {
+					if let Some(result) = self.step(from + (waypoint - from) * (i as Float / steps as Float), 1.0) {
+						result_opt = Some(result);
+					}
+				}
+			}
+			result_opt
+		}
+		fn state(&self) -> &RaceClientState {
+			&self.race.clients["alice"]
+		}
+	}
+
+	#[test]
+	fn clean_start() {
+		// Reaches 2m behind the line right at the start signal
+		let mut race = TestRace::new(V2::new(0.0, -5.0), 3.0);
+		race.sail(&[V2::new(0.0, -2.0)]);
+		assert_eq!(race.race.phase, RacePhase::Racing);
+		assert!(!race.state().started && !race.state().ocs);
+		race.sail(&[V2::new(0.0, 5.0)]);
+		assert!(race.state().started && !race.state().ocs);
+	}
+
+	#[test]
+	fn crossing_after_the_signal_in_the_same_step_is_not_ocs() {
+		let mut race = TestRace::new(V2::new(0.0, -1.0), 0.5);
+		// Signal is half way through the step, when the boat is still 0.25m behind the line
+		race.step(V2::new(0.0, 0.5), 1.0);
+		assert!(race.state().started && !race.state().ocs);
+	}
+
+	#[test]
+	fn ocs_has_to_return_before_starting() {
+		let mut race = TestRace::new(V2::new(0.0, -1.0), 0.5);
+		// Crossed just before the signal
+		race.step(V2::new(0.0, 5.0), 1.0);
+		assert!(race.state().ocs && !race.state().started);
+		race.sail(&[V2::new(0.0, 20.0)]);
+		assert!(!race.state().started);
+		race.sail(&[V2::new(0.0, -5.0)]);
+		assert!(!race.state().ocs && !race.state().started);
+		race.sail(&[V2::new(0.0, 5.0)]);
+		assert!(race.state().started);
+		let result = race.race.end("test", &race.clients);
+		assert_eq!(result.entries[0].status, RaceResultStatus::Dnf);
+	}
+
+	#[test]
+	fn ocs_boat_which_never_returns_is_recorded_as_ocs() {
+		let mut race = TestRace::new(V2::new(0.0, 5.0), 1.0);
+		race.sail(&[V2::new(0.0, 100.0)]);
+		let result = race.race.end("test", &race.clients);
+		assert_eq!(result.entries[0].status, RaceResultStatus::Ocs);
+	}
+
+	#[test]
+	fn rounding_the_mark_and_finishing() {
+		let mut race = TestRace::new(V2::new(0.0, -5.0), 5.0);
+		race.sail(&[V2::new(0.0, -2.0), V2::new(0.0, 5.0), V2::new(8.0, 190.0), V2::new(8.0, 200.0)]);
+		assert_eq!(race.state().next_element, 0, "Only just reached the mark");
+		race.sail(&[V2::new(0.0, 208.0), V2::new(-8.0, 200.0)]);
+		assert_eq!(race.state().next_element, 1);
+		let result = race.sail(&[V2::new(-8.0, 185.0), V2::new(0.0, -20.0)]).expect("Race ends when the only boat finishes");
+		assert_eq!(result.entries[0].status, RaceResultStatus::Finished);
+		assert!(result.entries[0].elapsed_time_opt.unwrap() > 400.0);
+		assert!(race.clients["alice"].has_finished);
+	}
+
+	#[test]
+	fn sailing_past_the_mark_is_not_a_rounding() {
+		let mut race = TestRace::new(V2::new(0.0, -5.0), 5.0);
+		// Leaves the mark to port, but carries straight on instead of turning back
+		race.sail(&[V2::new(0.0, -2.0), V2::new(0.0, 5.0), V2::new(5.0, 150.0), V2::new(5.0, 260.0)]);
+		assert_eq!(race.state().next_element, 0);
+		// And doesn't finish without going back to round it
+		assert!(race.sail(&[V2::new(0.0, -20.0)]).is_none());
+		assert!(!race.clients["alice"].has_finished);
+	}
+
+	#[test]
+	fn mean_wind_is_recorded_over_the_run() {
+		let mut race = TestRace::new(V2::new(0.0, -5.0), 3.0);
+		let set_wind = |race: &mut TestRace, speed: Float, direction: Float| {
+			let wind = &mut race.clients.get_mut("alice").unwrap().wind;
+			wind.speed = speed;
+			wind.direction = direction;
+		};
+		// 10 seconds after the start signal in each wind, the countdown isn't included
+		set_wind(&mut race, 4.0, 350.0);
+		race.sail(&[V2::new(0.0, -2.0), V2::new(0.0, 7.0)]);
+		set_wind(&mut race, 6.0, 10.0);
+		race.sail(&[V2::new(0.0, 17.0)]);
+		let result = race.race.end("test", &race.clients);
+		let wind = result.entries[0].mean_wind_opt.clone().unwrap();
+		assert!((wind.speed - 5.0).abs() < 0.001, "Mean speed {}", wind.speed);
+		assert!(wrap_angle(wind.direction.to_radians()).abs() < 0.001, "Mean direction {}", wind.direction);
+	}
+
+	#[test]
+	fn turning_mark_needs_half_a_turn() {
+		assert!((required_sweep(&course(), 0) - PI).abs() < 0.01);
+	}
+
+	#[test]
+	fn handicap_corrected_times() {
+		assert!((Handicap::TimeOnTime(1000.0).corrected_time(100.0, 500.0) - 100.0).abs() < 0.001);
+		assert!((Handicap::TimeOnTime(800.0).corrected_time(100.0, 500.0) - 125.0).abs() < 0.001);
+		assert!((Handicap::TimeOnDistance(0.1).corrected_time(100.0, 200.0) - 80.0).abs() < 0.001);
+		assert!((Handicap::TimeOnDistance(-0.1).corrected_time(100.0, 200.0) - 120.0).abs() < 0.001);
+	}
+
+	/// Boat types "fast" and "slow" with the given handicaps
+	fn boat_types(fast_opt: Option<Handicap>, slow_opt: Option<Handicap>) -> HashMap<String, Rc<BoatType>> {
+		[("fast", fast_opt), ("slow", slow_opt)].into_iter().map(|(name, handicap_opt)| {
+			let mut boat_type = test_utils::boat_type();
+			boat_type.handicap_opt = handicap_opt;
+			(name.to_owned(), Rc::new(boat_type))
+		}).collect()
+	}
+
+	/// Alice in the fast boat finishes 10 seconds ahead of Bob in the slow boat, Carol doesn't finish
+	fn fleet_result() -> RaceResult {
+		let entry = |username: &str, boat_type: &str, elapsed_time_opt: Option<Float>| RaceResultEntry {
+			username: username.to_owned(),
+			boat_type: boat_type.to_owned(),
+			status: match elapsed_time_opt {
+				Some(_) => RaceResultStatus::Finished,
+				None => RaceResultStatus::Dnf
+			},
+			elapsed_time_opt,
+			corrected_time_opt: None,
+			mean_wind_opt: None
+		};
+		RaceResult {
+			course_name: "test".to_owned(),
+			date: 0,
+			entries: vec![entry("alice", "fast", Some(100.0)), entry("bob", "slow", Some(110.0)), entry("carol", "slow", None)],
+			unrated_boat_types: Vec::new()
+		}
+	}
+
+	fn usernames(result: &RaceResult) -> Vec<&str> {
+		result.entries.iter().map(|entry| entry.username.as_str()).collect()
+	}
+
+	#[test]
+	fn slower_boat_can_win_on_corrected_time() {
+		for (fast, slow) in [
+			(Handicap::TimeOnTime(800.0), Handicap::TimeOnTime(1000.0)),
+			(Handicap::TimeOnDistance(-0.05), Handicap::TimeOnDistance(0.05))
+		] {
+			let mut result = fleet_result();
+			result.apply_handicaps(&boat_types(Some(fast), Some(slow)), 200.0);
+			assert_eq!(usernames(&result), ["bob", "alice", "carol"]);
+			assert!(result.unrated_boat_types.is_empty());
+			assert!(result.entries[0].corrected_time_opt.unwrap() < result.entries[1].corrected_time_opt.unwrap());
+			assert_eq!(result.entries[2].corrected_time_opt, None);
+		}
+	}
+
+	#[test]
+	fn partly_handicapped_race_is_ranked_by_elapsed_time() {
+		let mut result = fleet_result();
+		// Bob's corrected time would be 55, which can't be compared with Alice's elapsed time
+		result.apply_handicaps(&boat_types(None, Some(Handicap::TimeOnTime(2000.0))), 200.0);
+		assert_eq!(usernames(&result), ["alice", "bob", "carol"]);
+		assert!(result.entries.iter().all(|entry| entry.corrected_time_opt.is_none()));
+		assert_eq!(result.unrated_boat_types, ["fast"]);
+		assert!(result.to_string().contains("Ranked by elapsed time, no handicap for: fast"));
+		// No handicaps at all is a normal elapsed time race
+		let mut result = fleet_result();
+		result.apply_handicaps(&boat_types(None, None), 200.0);
+		assert_eq!(usernames(&result), ["alice", "bob", "carol"]);
+		assert!(result.unrated_boat_types.is_empty());
+	}
+}
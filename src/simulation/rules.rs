@@ -0,0 +1,296 @@
+//! Racing-rules right-of-way checker, detects basic situations between boats which are close to each other
+//! Only the boats' positions, headings and the wind are used, so this is a training aid and not a protest committee
+
+use std::{collections::{BTreeMap, HashMap, HashSet}, fmt, rc::Rc};
+use crate::{prelude::*, course::CourseSave, leaderboard::unix_time_now};
+use serde::{Deserialize, Serialize};
+
+use super::{SimulationClientSave, boat::BoatType, race::{RaceSave, RacePhase}};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Rule {
+	/// Rule 10: on opposite tacks, the port-tack boat keeps clear
+	PortStarboard,
+	/// Rule 11: on the same tack and overlapped, the windward boat keeps clear
+	WindwardLeeward,
+	/// Rule 12: on the same tack and not overlapped, the boat clear astern keeps clear
+	Overtaking,
+	/// Rule 18: overlapped in the zone of the mark both boats are sailing to, the outside boat gives the inside boat room
+	MarkRoom
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Tack {
+	/// Wind coming over the port side
+	Port,
+	Starboard
+}
+
+/// One boat failing to keep clear of another, logged for post-race review
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RuleIncident {
+	pub rule: Rule,
+	/// Boat which had to keep clear (or give room)
+	pub keep_clear: String,
+	/// Boat which had right of way
+	pub right_of_way: String,
+	/// Distance between the boats when the incident was flagged
+	pub distance: Float,
+	pub keep_clear_pos: V2,
+	pub right_of_way_pos: V2,
+	/// Time since the start signal (negative during the countdown), None if there was no race running
+	pub race_time_opt: Option<Float>,
+	/// Unix timestamp, seconds
+	pub date: u64
+}
+
+impl fmt::Display for RuleIncident {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if let Some(race_time) = self.race_time_opt {
+			write!(f, "[{:.1} s] ", race_time)?;
+		}
+		write!(f, "{:?}: \"{}\" did not keep clear of \"{}\" ({:.1} apart)", self.rule, self.keep_clear, self.right_of_way, self.distance)
+	}
+}
+
+/// Remembers which situations are ongoing so that each one is only logged once, until the boats separate
+#[derive(Default)]
+pub struct RulesChecker {
+	/// (rule, keep clear, right of way)
+	active: HashSet<(Rule, String, String)>
+}
+
+impl RulesChecker {
+	/// Checks every pair of boats within `check_distance` of each other, returns incidents which weren't already ongoing
+	pub fn check(&mut self, check_distance: Float, clients: &BTreeMap<String, SimulationClientSave>, boat_types: &HashMap<String, Rc<BoatType>>, race: &RaceSave, course_opt: Option<&CourseSave>) -> Vec<RuleIncident> {
+		let usernames: Vec<&String> = clients.keys().collect();
+		let race_time_opt = match race.phase {
+			RacePhase::Idle => None,
+			_ => Some(race.time)
+		};
+		let mut current = HashSet::new();
+		let mut out = Vec::new();
+		for (i, a_name) in usernames.iter().enumerate() {
+			for b_name in &usernames[i + 1..] {
+				let (a, b) = match (BoatInfo::new(a_name, clients, boat_types), BoatInfo::new(b_name, clients, boat_types)) {
+					(Some(a), Some(b)) => (a, b),
+					_ => continue
+				};
+				let distance = (b.pos - a.pos).magnitude();
+				if distance > check_distance {
+					continue;
+				}
+				let (rule, keep_clear, right_of_way) = match mark_room(&a, &b, race, course_opt) {
+					Some((inside, outside)) => (Rule::MarkRoom, outside, inside),
+					None => right_of_way(&a, &b)
+				};
+				let key = (rule, keep_clear.name.to_owned(), right_of_way.name.to_owned());
+				if !self.active.contains(&key) {
+					out.push(RuleIncident {
+						rule,
+						keep_clear: keep_clear.name.to_owned(),
+						right_of_way: right_of_way.name.to_owned(),
+						distance,
+						keep_clear_pos: keep_clear.pos,
+						right_of_way_pos: right_of_way.pos,
+						race_time_opt,
+						date: unix_time_now()
+					});
+				}
+				current.insert(key);
+			}
+		}
+		self.active = current;
+		out
+	}
+}
+
+/// Everything about one boat needed to work out right of way
+struct BoatInfo<'a> {
+	name: &'a str,
+	pos: V2,
+	/// Unit vector pointing forward
+	forward: V2,
+	/// Direction the true wind is coming from, unit vector
+	wind_from: V2,
+	tack: Tack,
+	/// Aftmost and foremost points of the hull along the boat's X axis
+	stern: Float,
+	bow: Float
+}
+
+impl<'a> BoatInfo<'a> {
+	fn new(name: &'a str, clients: &BTreeMap<String, SimulationClientSave>, boat_types: &HashMap<String, Rc<BoatType>>) -> Option<Self> {
+		let client = clients.get(name)?;
+		let boat_type = boat_types.get(client.boat.type_name.name())?;
+		let wind_from = -client.wind.vector();
+		if wind_from.magnitude() < EPSILON {
+			return None;
+		}
+		let wind_from_local = client.boat.pos.rotation.inverse() * wind_from;
+		let stern = boat_type.perimeter.iter().map(|point| point.x).fold(Float::INFINITY, Float::min);
+		let bow = boat_type.perimeter.iter().map(|point| point.x).fold(Float::NEG_INFINITY, Float::max);
+		Some(Self {
+			name,
+			pos: client.boat.pos.translation.vector,
+			forward: client.boat.pos.rotation * V2::new(1.0, 0.0),
+			wind_from: wind_from.normalize(),
+			tack: match wind_from_local.y >= 0.0 {
+				true => Tack::Port,
+				false => Tack::Starboard
+			},
+			stern,
+			bow
+		})
+	}
+	/// Whether this boat's bow is behind a line abeam from `other`'s stern
+	fn clear_astern_of(&self, other: &Self) -> bool {
+		let bow = self.pos + self.forward * self.bow;
+		let other_stern = other.pos + other.forward * other.stern;
+		(bow - other_stern).dot(&other.forward) < 0.0
+	}
+	fn overlapped(&self, other: &Self) -> bool {
+		!self.clear_astern_of(other) && !other.clear_astern_of(self)
+	}
+}
+
+/// Rules 10, 11 and 12, returns (rule, keep clear, right of way)
+fn right_of_way<'a, 'b>(a: &'b BoatInfo<'a>, b: &'b BoatInfo<'a>) -> (Rule, &'b BoatInfo<'a>, &'b BoatInfo<'a>) {
+	if a.tack != b.tack {
+		return match a.tack {
+			Tack::Port => (Rule::PortStarboard, a, b),
+			Tack::Starboard => (Rule::PortStarboard, b, a)
+		};
+	}
+	if a.overlapped(b) {
+		let wind_from = (a.wind_from + b.wind_from) / 2.0;
+		return match a.pos.dot(&wind_from) > b.pos.dot(&wind_from) {
+			true => (Rule::WindwardLeeward, a, b),
+			false => (Rule::WindwardLeeward, b, a)
+		};
+	}
+	match a.clear_astern_of(b) {
+		true => (Rule::Overtaking, a, b),
+		false => (Rule::Overtaking, b, a)
+	}
+}
+
+/// Rule 18, returns (inside, outside) if both boats are overlapped in the zone of the mark they are both sailing to
+fn mark_room<'a, 'b>(a: &'b BoatInfo<'a>, b: &'b BoatInfo<'a>, race: &RaceSave, course_opt: Option<&CourseSave>) -> Option<(&'b BoatInfo<'a>, &'b BoatInfo<'a>)> {
+	let course = course_opt?;
+	if race.phase != RacePhase::Racing {
+		return None;
+	}
+	let (a_state, b_state) = (race.clients.get(a.name)?, race.clients.get(b.name)?);
+	if !a_state.started || !b_state.started || a_state.next_element != b_state.next_element {
+		return None;
+	}
+	let mark = course.marks.get(a_state.next_element)?.target();
+	let (a_distance, b_distance) = ((a.pos - mark).magnitude(), (b.pos - mark).magnitude());
+	if a_distance > course.mark_zone_radius || b_distance > course.mark_zone_radius || !a.overlapped(b) {
+		return None;
+	}
+	match a_distance < b_distance {
+		true => Some((a, b)),
+		false => Some((b, a))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{course::{CourseElement, Line, RoundingSide}, resource_interface::ResourceRef, simulation::race::RaceClientState, test_utils};
+
+	const CHECK_DISTANCE: Float = 20.0;
+
+	/// Boats at (position, heading in degrees) by username, with the wind from the north
+	fn clients(boats: &[(&str, V2, Float)]) -> BTreeMap<String, SimulationClientSave> {
+		let wind = WindGeneratorSaveState {
+			direction: -90.0,
+			..Default::default()
+		};
+		boats.iter().map(|(username, pos, heading)| {
+			let boat = BoatSaveState::new(test_utils::BOAT_TYPE_NAME.to_owned(), &test_utils::boat_type(), Iso::new(*pos, heading.to_radians()));
+			(username.to_string(), SimulationClientSave::new(boat, wind.clone()))
+		}).collect()
+	}
+
+	fn boat_types() -> HashMap<String, Rc<BoatType>> {
+		test_utils::resources(test_utils::open_water_map(1000)).boat_types
+	}
+
+	/// (rule, keep clear, right of way) of the only incident in `incidents`
+	fn only_incident(incidents: &[RuleIncident]) -> (Rule, String, String) {
+		assert_eq!(incidents.len(), 1);
+		(incidents[0].rule, incidents[0].keep_clear.clone(), incidents[0].right_of_way.clone())
+	}
+
+	/// Single incident between "alice" and "bob" outside of a race
+	fn incident(boats: &[(&str, V2, Float)]) -> (Rule, String, String) {
+		only_incident(&RulesChecker::default().check(CHECK_DISTANCE, &clients(boats), &boat_types(), &RaceSave::default(), None))
+	}
+
+	fn expected(rule: Rule, keep_clear: &str, right_of_way: &str) -> (Rule, String, String) {
+		(rule, keep_clear.to_owned(), right_of_way.to_owned())
+	}
+
+	#[test]
+	fn port_tack_keeps_clear_of_starboard() {
+		// Alice is heading north-east with the wind over her port side, Bob is heading north-west
+		assert_eq!(incident(&[("alice", V2::new(0.0, 0.0), 45.0), ("bob", V2::new(10.0, 0.0), 135.0)]), expected(Rule::PortStarboard, "alice", "bob"));
+		assert_eq!(incident(&[("alice", V2::new(0.0, 0.0), 135.0), ("bob", V2::new(10.0, 0.0), 45.0)]), expected(Rule::PortStarboard, "bob", "alice"));
+	}
+
+	#[test]
+	fn windward_boat_keeps_clear_when_overlapped() {
+		// Both heading east on port tack side by side, the northern boat is to windward
+		assert_eq!(incident(&[("alice", V2::new(0.0, 5.0), 0.0), ("bob", V2::new(1.0, 0.0), 0.0)]), expected(Rule::WindwardLeeward, "alice", "bob"));
+		assert_eq!(incident(&[("alice", V2::new(1.0, 0.0), 0.0), ("bob", V2::new(0.0, 5.0), 0.0)]), expected(Rule::WindwardLeeward, "bob", "alice"));
+	}
+
+	#[test]
+	fn boat_clear_astern_keeps_clear() {
+		// Same tack, Bob's bow is 6m behind Alice's stern, even though he is slightly to leeward
+		assert_eq!(incident(&[("alice", V2::new(10.0, 0.0), 0.0), ("bob", V2::new(0.0, -1.0), 0.0)]), expected(Rule::Overtaking, "bob", "alice"));
+		assert_eq!(incident(&[("alice", V2::new(0.0, -1.0), 0.0), ("bob", V2::new(10.0, 0.0), 0.0)]), expected(Rule::Overtaking, "alice", "bob"));
+	}
+
+	#[test]
+	fn inside_boat_gets_mark_room() {
+		let course = CourseSave {
+			map_name: ResourceRef::new("test_map".to_owned()),
+			start_line: Line::new(V2::new(-20.0, 0.0), V2::new(20.0, 0.0)),
+			marks: vec![CourseElement::Mark{pos: V2::new(0.0, 200.0), rounding: RoundingSide::Port}],
+			finish_line: Line::new(V2::new(-20.0, -10.0), V2::new(20.0, -10.0)),
+			mark_zone_radius: 20.0
+		};
+		let mut race = RaceSave {
+			phase: RacePhase::Racing,
+			..Default::default()
+		};
+		for username in ["alice", "bob"] {
+			race.clients.insert(username.to_owned(), RaceClientState {
+				started: true,
+				..Default::default()
+			});
+		}
+		// Overlapped heading east, Bob is to windward but inside, closer to the mark
+		let boats = clients(&[("alice", V2::new(0.0, 185.0), 0.0), ("bob", V2::new(1.0, 190.0), 0.0)]);
+		assert_eq!(only_incident(&RulesChecker::default().check(CHECK_DISTANCE, &boats, &boat_types(), &race, Some(&course))), expected(Rule::MarkRoom, "alice", "bob"));
+		// Outside the zone the normal windward rule applies
+		let boats = clients(&[("alice", V2::new(0.0, 165.0), 0.0), ("bob", V2::new(1.0, 170.0), 0.0)]);
+		assert_eq!(only_incident(&RulesChecker::default().check(CHECK_DISTANCE, &boats, &boat_types(), &race, Some(&course))), expected(Rule::WindwardLeeward, "bob", "alice"));
+	}
+
+	#[test]
+	fn ongoing_situation_is_logged_once() {
+		let mut checker = RulesChecker::default();
+		let mut check = |alice_x: Float| checker.check(CHECK_DISTANCE, &clients(&[("alice", V2::new(alice_x, 0.0), 45.0), ("bob", V2::new(10.0, 0.0), 135.0)]), &boat_types(), &RaceSave::default(), None).len();
+		assert_eq!(check(0.0), 1);
+		assert_eq!(check(1.0), 0);
+		assert_eq!(check(2.0), 0);
+		// Separated past the check distance, then close again
+		assert_eq!(check(-50.0), 0);
+		assert_eq!(check(0.0), 1);
+	}
+}
@@ -3,8 +3,8 @@
 use crate::prelude::*;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct UserInput {
-    boat: Option<BoatInputs>,
-    autopilot: Option<AutopilotInputs>
+    pub boat: Option<BoatInputs>,
+    pub autopilot: Option<AutopilotInputs>
 }
\ No newline at end of file
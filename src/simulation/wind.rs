@@ -1,9 +1,13 @@
 //! Pseudo-random wind generator
 
-use crate::prelude::*;
+use crate::{prelude::*, rng::Rng};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+// CONSTS
+/// Fraction per second of the difference between the current and average speed which is removed, so gusts die down
+const SPEED_RETURN_RATE: Float = 0.1;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct WindGeneratorSaveState {
 	/// Speed average, m/s
 	pub speed_average: Float,
@@ -15,8 +19,35 @@ pub struct WindGeneratorSaveState {
 	pub max_direction_variation: Float,
 	/// Current speed
 	pub speed: Float,
-	/// Current direction
+	/// Current direction, degrees CCW from +X, the direction the wind is blowing towards
 	pub direction: Float
 }
 
+impl Default for WindGeneratorSaveState {
+	fn default() -> Self {
+		Self {
+			speed_average: 5.0,
+			max_gust: 8.0,
+			max_speed_variation: 0.5,
+			max_direction_variation: 2.0,
+			speed: 5.0,
+			direction: 90.0
+		}
+	}
+}
+
+impl WindGeneratorSaveState {
+	/// Current true wind vector
+	pub fn vector(&self) -> Wind {
+		let angle = self.direction.to_radians();
+		V2::new(angle.cos(), angle.sin()) * self.speed
+	}
+	/// Random walk of the speed and direction, the speed drifts back towards the average and never goes above the maximum gust
+	pub fn update(&mut self, dt: Float, rng: &mut Rng) {
+		let speed_change = rng.range(-1.0, 1.0) * self.max_speed_variation * dt + (self.speed_average - self.speed) * SPEED_RETURN_RATE * dt;
+		self.speed = (self.speed + speed_change).clamp(0.0, self.max_gust);
+		self.direction = (self.direction + rng.range(-1.0, 1.0) * self.max_direction_variation * dt).rem_euclid(360.0);
+	}
+}
+
 pub type Wind = V2;
\ No newline at end of file
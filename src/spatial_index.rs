@@ -0,0 +1,369 @@
+//! Uniform grid over every coastline and hole edge, for fast land queries on detailed maps
+//! Each cell lists the edges passing through it and whether its center is on land, so a point query only has to look at one cell
+
+use std::time::Instant;
+use crate::{prelude::*, geometry, rng::Rng, resource_interface::{MapSave, LandmassSave}};
+
+// CONSTS
+/// Upper limit on columns and rows, so that huge sparse maps don't use too much memory
+const MAX_GRID_SIZE: usize = 2048;
+
+pub struct LandIndex {
+	/// Corner of cell (0, 0)
+	origin: V2,
+	cell_size: Float,
+	columns: usize,
+	rows: usize,
+	/// Every coastline and hole edge
+	edges: Vec<(V2, V2)>,
+	/// Indices into `edges` for each cell, row-major
+	cells: Vec<Vec<u32>>,
+	/// Whether each cell's center is on land, row-major
+	center_is_land: Vec<bool>
+}
+
+impl LandIndex {
+	pub fn new(map: &MapSave) -> Self {
+		let mut edges = Vec::new();
+		for (_, landmass) in &map.landmasses {
+			for ring in std::iter::once(&landmass.coastline).chain(landmass.holes.iter()) {
+				edges.extend(geometry::polygon_edges(ring).map(|(a, b)| (*a, *b)));
+			}
+		}
+		let mut min = V2::new(Float::INFINITY, Float::INFINITY);
+		let mut max = V2::new(Float::NEG_INFINITY, Float::NEG_INFINITY);
+		for (a, b) in &edges {
+			min = min.inf(a).inf(b);
+			max = max.sup(a).sup(b);
+		}
+		if edges.is_empty() {
+			(min, max) = (V2::zeros(), V2::zeros());
+		}
+		let extent = (max - min).add_scalar(EPSILON);
+		// About one cell per edge, most cells are empty water or land but the ones along the coast hold a few edges each
+		let cell_size = ((extent.x * extent.y) / edges.len().max(1) as Float).sqrt().max(extent.x.max(extent.y) / MAX_GRID_SIZE as Float).max(EPSILON);
+		let columns = (extent.x / cell_size).ceil() as usize + 1;
+		let rows = (extent.y / cell_size).ceil() as usize + 1;
+		let mut out = Self {
+			origin: min,
+			cell_size,
+			columns,
+			rows,
+			edges,
+			cells: vec![Vec::new(); columns * rows],
+			center_is_land: vec![false; columns * rows]
+		};
+		for i in 0..out.edges.len() {
+			let (a, b) = out.edges[i];
+			let mut cells_hit = Vec::new();
+			out.visit_cells(&a, &b, |cell| {
+				cells_hit.push(cell);
+				false
+			});
+			for cell in cells_hit {
+				out.cells[cell].push(i as u32);
+			}
+		}
+		out.classify_cell_centers();
+		out
+	}
+	/// Same result as `MapSave::is_land()`, assuming landmasses don't overlap
+	pub fn point_in_land(&self, point: &V2) -> bool {
+		let cell = match self.cell_containing(point) {
+			Some(cell) => cell,
+			None => return false
+		};
+		// Even-odd rule, starting from the cell center and counting edges crossed on the way to the point
+		let center = self.cell_center(cell);
+		let crossings = self.cells[cell].iter().filter(|i| {
+			let (a, b) = &self.edges[**i as usize];
+			geometry::segments_intersect(&center, point, a, b)
+		}).count();
+		self.center_is_land[cell] ^ (crossings % 2 == 1)
+	}
+	/// Same result as `MapSave::segment_hits_land()`
+	pub fn segment_hits_land(&self, a: &V2, b: &V2) -> bool {
+		if self.point_in_land(a) || self.point_in_land(b) {
+			return true;
+		}
+		self.visit_cells(a, b, |cell| self.cells[cell].iter().any(|i| {
+			let (p1, p2) = &self.edges[*i as usize];
+			geometry::segments_intersect(a, b, p1, p2)
+		}))
+	}
+	/// Closest point on any coastline or hole edge and the distance to it, None if the map has no land
+	pub fn nearest_coast(&self, point: &V2) -> Option<(V2, Float)> {
+		if self.edges.is_empty() {
+			return None;
+		}
+		let (center_x, center_y) = self.clamped_cell_coords(point);
+		let mut best_opt: Option<(V2, Float)> = None;
+		for ring in 0..=self.columns.max(self.rows) {
+			for (x, y) in ring_cells(center_x, center_y, ring) {
+				if x >= self.columns || y >= self.rows {
+					continue;
+				}
+				for i in &self.cells[y * self.columns + x] {
+					let (a, b) = &self.edges[*i as usize];
+					let closest = geometry::closest_point_on_segment(point, a, b);
+					let distance = (closest - point).magnitude();
+					if best_opt.is_none_or(|(_, best_distance)| distance < best_distance) {
+						best_opt = Some((closest, distance));
+					}
+				}
+			}
+			// Every cell further out is at least this far away
+			if let Some((_, best_distance)) = best_opt {
+				if best_distance <= ring as Float * self.cell_size {
+					break;
+				}
+			}
+		}
+		best_opt
+	}
+	pub fn edge_count(&self) -> usize {
+		self.edges.len()
+	}
+	/// Calls `visit` with every cell the segment passes through until it returns true, returns whether it did
+	fn visit_cells(&self, a: &V2, b: &V2, mut visit: impl FnMut(usize) -> bool) -> bool {
+		// Clip the segment to the grid
+		let grid_max = self.origin + V2::new(self.columns as Float, self.rows as Float) * self.cell_size;
+		let direction = b - a;
+		let (mut t_start, mut t_end): (Float, Float) = (0.0, 1.0);
+		for axis in 0..2 {
+			if direction[axis].abs() < EPSILON {
+				if a[axis] < self.origin[axis] || a[axis] > grid_max[axis] {
+					return false;
+				}
+			}
+			else {
+				let t_a = (self.origin[axis] - a[axis]) / direction[axis];
+				let t_b = (grid_max[axis] - a[axis]) / direction[axis];
+				t_start = t_start.max(t_a.min(t_b));
+				t_end = t_end.min(t_a.max(t_b));
+			}
+		}
+		if t_start > t_end {
+			return false;
+		}
+		let (start, end) = (a + direction * t_start, a + direction * t_end);
+		// Grid traversal (Amanatides & Woo)
+		let (mut x, mut y) = self.clamped_cell_coords(&start);
+		let end_cell = self.clamped_cell_coords(&end);
+		let step = end - start;
+		let axis_setup = |position: Float, cell: usize, origin: Float, delta: Float| -> (Float, Float) {
+			if delta.abs() < EPSILON {
+				return (Float::INFINITY, Float::INFINITY);
+			}
+			let boundary = origin + (cell as Float + if delta > 0.0 {1.0} else {0.0}) * self.cell_size;
+			((boundary - position) / delta, self.cell_size / delta.abs())
+		};
+		let (mut t_max_x, t_delta_x) = axis_setup(start.x, x, self.origin.x, step.x);
+		let (mut t_max_y, t_delta_y) = axis_setup(start.y, y, self.origin.y, step.y);
+		for _ in 0..(self.columns + self.rows + 2) {
+			if visit(y * self.columns + x) {
+				return true;
+			}
+			if (x, y) == end_cell {
+				break;
+			}
+			if t_max_x < t_max_y {
+				match (step.x > 0.0, x) {
+					(true, x_) if x_ + 1 < self.columns => x += 1,
+					(false, x_) if x_ > 0 => x -= 1,
+					_ => break
+				}
+				t_max_x += t_delta_x;
+			}
+			else {
+				match (step.y > 0.0, y) {
+					(true, y_) if y_ + 1 < self.rows => y += 1,
+					(false, y_) if y_ > 0 => y -= 1,
+					_ => break
+				}
+				t_max_y += t_delta_y;
+			}
+		}
+		false
+	}
+	/// Sweeps a horizontal line through each row of cell centers and counts coastline crossings to the left of each center
+	fn classify_cell_centers(&mut self) {
+		let mut seen = vec![false; self.edges.len()];
+		for row in 0..self.rows {
+			let y = self.origin.y + (row as Float + 0.5) * self.cell_size;
+			let mut row_edges: Vec<u32> = Vec::new();
+			for column in 0..self.columns {
+				for i in &self.cells[row * self.columns + column] {
+					if !seen[*i as usize] {
+						seen[*i as usize] = true;
+						row_edges.push(*i);
+					}
+				}
+			}
+			let mut crossings: Vec<Float> = Vec::new();
+			for i in &row_edges {
+				let (a, b) = &self.edges[*i as usize];
+				seen[*i as usize] = false;
+				if (a.y > y) != (b.y > y) {
+					crossings.push(a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x));
+				}
+			}
+			crossings.sort_by(|a, b| a.total_cmp(b));
+			let mut crossings_left = 0;
+			for column in 0..self.columns {
+				let x = self.origin.x + (column as Float + 0.5) * self.cell_size;
+				while crossings_left < crossings.len() && crossings[crossings_left] < x {
+					crossings_left += 1;
+				}
+				self.center_is_land[row * self.columns + column] = crossings_left % 2 == 1;
+			}
+		}
+	}
+	fn cell_containing(&self, point: &V2) -> Option<usize> {
+		let local = (point - self.origin) / self.cell_size;
+		if local.x < 0.0 || local.y < 0.0 || local.x >= self.columns as Float || local.y >= self.rows as Float {
+			return None;
+		}
+		Some(local.y as usize * self.columns + local.x as usize)
+	}
+	fn clamped_cell_coords(&self, point: &V2) -> (usize, usize) {
+		let local = (point - self.origin) / self.cell_size;
+		(
+			local.x.clamp(0.0, (self.columns - 1) as Float) as usize,
+			local.y.clamp(0.0, (self.rows - 1) as Float) as usize
+		)
+	}
+	fn cell_center(&self, cell: usize) -> V2 {
+		self.origin + V2::new((cell % self.columns) as Float + 0.5, (cell / self.columns) as Float + 0.5) * self.cell_size
+	}
+}
+
+/// Cells at exactly `ring` cells (Chebyshev distance) from (x, y), some may be off the grid
+fn ring_cells(x: usize, y: usize, ring: usize) -> Vec<(usize, usize)> {
+	let (x, y, ring) = (x as i64, y as i64, ring as i64);
+	let mut out = Vec::new();
+	for dy in -ring..=ring {
+		for dx in -ring..=ring {
+			if dx.abs() != ring && dy.abs() != ring {
+				continue;
+			}
+			if x + dx >= 0 && y + dy >= 0 {
+				out.push(((x + dx) as usize, (y + dy) as usize));
+			}
+		}
+	}
+	out
+}
+
+/// Times `queries` random point, segment and nearest-coast queries with and without the index, returns a human-readable report
+/// If `map_opt` is None, a map with one island of `synthetic_points` coastline points is used
+pub fn benchmark(map_opt: Option<&MapSave>, synthetic_points: usize, queries: usize) -> String {
+	let synthetic;
+	let map = match map_opt {
+		Some(map) => map,
+		None => {
+			synthetic = synthetic_map(synthetic_points);
+			&synthetic
+		}
+	};
+	let build_start = Instant::now();
+	let index = LandIndex::new(map);
+	let build_time = build_start.elapsed();
+	let mut rng = Rng::new(0);
+	let size = map.size.to_v2();
+	let mut random_point = || V2::new(rng.range(0.0, size.x), rng.range(0.0, size.y));
+	let points: Vec<V2> = (0..queries).map(|_| random_point()).collect();
+	let segments: Vec<(V2, V2)> = (0..queries).map(|_| (random_point(), random_point())).collect();
+	let time = |f: &mut dyn FnMut() -> usize| -> (Float, usize) {
+		let start = Instant::now();
+		let count = f();
+		(start.elapsed().as_secs_f32() * 1000.0, count)
+	};
+	let (point_index, point_hits) = time(&mut || points.iter().filter(|point| index.point_in_land(point)).count());
+	let (point_flat, point_hits_flat) = time(&mut || points.iter().filter(|point| map.is_land(point)).count());
+	let (segment_index, segment_hits) = time(&mut || segments.iter().filter(|(a, b)| index.segment_hits_land(a, b)).count());
+	let (segment_flat, segment_hits_flat) = time(&mut || segments.iter().filter(|(a, b)| map.segment_hits_land(a, b)).count());
+	let (nearest_index, _) = time(&mut || points.iter().filter_map(|point| index.nearest_coast(point)).count());
+	let mut out = format!("{} coastline edges, index built in {:.1} ms ({} x {} cells)\n", index.edge_count(), build_time.as_secs_f32() * 1000.0, index.columns, index.rows);
+	out += &format!("{} point-in-land queries: {:.1} ms indexed, {:.1} ms flat ({} vs {} on land)\n", queries, point_index, point_flat, point_hits, point_hits_flat);
+	out += &format!("{} segment queries: {:.1} ms indexed, {:.1} ms flat ({} vs {} hit land)\n", queries, segment_index, segment_flat, segment_hits, segment_hits_flat);
+	out += &format!("{} nearest-coast queries: {:.1} ms indexed", queries, nearest_index);
+	out
+}
+
+/// One big island with a very jagged coastline of `points` points
+fn synthetic_map(points: usize) -> MapSave {
+	let size = 10_000.0;
+	let center = V2::new(size / 2.0, size / 2.0);
+	let mut rng = Rng::new(1);
+	let coastline: Vec<V2> = (0..points.max(3)).map(|i| {
+		let angle = 2.0 * PI * i as Float / points.max(3) as Float;
+		let radius = size * 0.3 * (1.0 + 0.2 * (7.0 * angle).sin() + 0.1 * (31.0 * angle).sin() + 0.05 * rng.range(-1.0, 1.0));
+		center + V2::new(angle.cos(), angle.sin()) * radius
+	}).collect();
+	let mut landmasses = GenericDataset::new();
+	landmasses.insert_with_ref(GenericRef::id(0), LandmassSave {
+		coastline,
+		name_and_representative_point_opt: None,
+		color: [0, 0, 0, 255],
+		holes: Vec::new()
+	}).expect("Dataset is empty");
+	MapSave {
+		size: IntV2(size as Int, size as Int),
+		global_default_start: V2::new(100.0, 100.0),
+		end: V2::new(size - 100.0, size - 100.0),
+		landmasses,
+		geo_reference_opt: None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils;
+
+	/// Checks every kind of query against the unindexed `MapSave` version
+	fn assert_matches_map(map: &MapSave, seed: u64, queries: usize) {
+		let index = LandIndex::new(map);
+		let mut rng = Rng::new(seed);
+		let size = map.size.to_v2();
+		let mut random_point = || V2::new(rng.range(0.0, size.x), rng.range(0.0, size.y));
+		let edges: Vec<(V2, V2)> = map.landmasses.iter().flat_map(|(_, landmass)| std::iter::once(&landmass.coastline).chain(landmass.holes.iter())).flat_map(|ring| geometry::polygon_edges(ring).map(|(a, b)| (*a, *b)).collect::<Vec<_>>()).collect();
+		let (mut points_on_land, mut segments_hitting_land) = (0, 0);
+		for i in 0..queries {
+			let point = random_point();
+			let is_land = map.is_land(&point);
+			assert_eq!(index.point_in_land(&point), is_land, "Point {:?}", point);
+			points_on_land += is_land as usize;
+			// Alternate long segments with short ones, which are more likely to end near the coast
+			let end = match i % 2 {
+				0 => random_point(),
+				_ => point + (random_point() - point) * 0.02
+			};
+			let hits_land = map.segment_hits_land(&point, &end);
+			assert_eq!(index.segment_hits_land(&point, &end), hits_land, "Segment {:?} to {:?}", point, end);
+			segments_hitting_land += hits_land as usize;
+			let (_, distance) = index.nearest_coast(&point).unwrap();
+			let brute_force = edges.iter().map(|(a, b)| (geometry::closest_point_on_segment(&point, a, b) - point).magnitude()).fold(Float::INFINITY, Float::min);
+			assert!((distance - brute_force).abs() < 0.01, "Nearest coast to {:?} is {} away, not {}", point, brute_force, distance);
+		}
+		// Otherwise the comparison doesn't mean much
+		assert!(points_on_land > 0 && points_on_land < queries);
+		assert!(segments_hitting_land > 0 && segments_hitting_land < queries);
+	}
+
+	#[test]
+	fn index_matches_map_on_jagged_island() {
+		assert_matches_map(&synthetic_map(500), 2, 1000);
+	}
+
+	#[test]
+	fn index_matches_map_with_holes() {
+		let atoll = test_utils::landmass(test_utils::rectangle(V2::new(200.0, 200.0), V2::new(800.0, 800.0)), vec![
+			test_utils::rectangle(V2::new(300.0, 300.0), V2::new(450.0, 700.0)),
+			test_utils::rectangle(V2::new(550.0, 300.0), V2::new(700.0, 700.0))
+		]);
+		let islet = test_utils::landmass(vec![V2::new(50.0, 900.0), V2::new(150.0, 880.0), V2::new(120.0, 980.0)], Vec::new());
+		assert_matches_map(&test_utils::map_with_land(1000, vec![atoll, islet]), 3, 1000);
+	}
+}
@@ -0,0 +1,109 @@
+//! Resources built in code for tests, so that tests don't depend on the files in `resources/`
+
+use std::{collections::HashMap, rc::Rc};
+use crate::{prelude::*, resource_interface::{LandmassSave, MapSave}, rng::Rng, simulation::{ResolvedResources, SimulatorSanityLimits}};
+
+/// Small dinghy with a main and a jib, in the list format boat type files have always used
+pub const BOAT_TYPE_JSON: &str = r#"{
+	"perimeter": [[2.0, 0.0], [1.0, 0.7], [-2.0, 0.7], [-2.0, -0.7], [1.0, -0.7]],
+	"center_of_lateral_resistance": 0.3,
+	"forward_drag": 0.02,
+	"sideways_drag": 0.8,
+	"air_drag": 0.3,
+	"max_draft": 1.0,
+	"rudder_pivot": -2.0,
+	"rudder_area": 0.15,
+	"rudder_center_of_effort": 0.2,
+	"rudder_len": 0.5,
+	"mass": 200.0,
+	"moment": 300.0,
+	"angular_drag": 400.0,
+	"max_hull_hp": 100.0,
+	"max_rudder_hp": 100.0,
+	"upwind_max_wind_angle": 45.0,
+	"upwind_max_total_leeway": 50.0,
+	"sails": {
+		"items": [
+			[{"id": 0, "unique_name_opt": "main"}, {"area": 8.0, "center_of_effort": 1.2, "tack": 0.3, "foot_len": 2.5}],
+			[{"id": 1, "unique_name_opt": "jib"}, {"area": 4.0, "center_of_effort": 0.8, "tack": 1.8, "foot_len": 1.5}]
+		]
+	}
+}"#;
+
+pub fn boat_type() -> BoatType {
+	serde_json::from_str(BOAT_TYPE_JSON).expect("Test boat type is valid")
+}
+
+pub const BOAT_TYPE_NAME: &str = "dinghy";
+
+/// Square map with no land, the start is near the bottom-left corner and the end near the top-right
+pub fn open_water_map(size: Int) -> MapSave {
+	MapSave {
+		size: IntV2(size, size),
+		global_default_start: V2::new(50.0, 50.0),
+		end: V2::new(size as Float - 50.0, size as Float - 50.0),
+		landmasses: GenericDataset::new(),
+		geo_reference_opt: None
+	}
+}
+
+/// Counter-clockwise rectangle
+pub fn rectangle(min: V2, max: V2) -> Vec<V2> {
+	vec![min, V2::new(max.x, min.y), max, V2::new(min.x, max.y)]
+}
+
+/// Unnamed landmass with the default color
+pub fn landmass(coastline: Vec<V2>, holes: Vec<Vec<V2>>) -> LandmassSave {
+	LandmassSave {
+		coastline,
+		name_and_representative_point_opt: None,
+		color: [0, 0, 0, 0],
+		holes
+	}
+}
+
+/// `open_water_map()` with land added
+pub fn map_with_land(size: Int, landmasses: Vec<LandmassSave>) -> MapSave {
+	let mut map = open_water_map(size);
+	for landmass in landmasses {
+		map.landmasses.insert(None, landmass).expect("Unnamed landmasses can always be added");
+	}
+	map
+}
+
+/// Deterministic mode with the rules checker and tracer enabled, so tests cover as much of `Simulation::step()` as possible
+pub fn settings() -> SimulationSettings {
+	SimulationSettings {
+		save_sims: false,
+		max_time_step: 0.1,
+		max_rudder_movement: 90.0,
+		tracer_resulution: 1.0,
+		tracer_enabled: true,
+		client_timeout: 5.0,
+		sanity_limits: SimulatorSanityLimits {
+			speed: 100.0,
+			angular_speed: 360.0
+		},
+		spectator_port_opt: None,
+		start_sequence_time: 10.0,
+		rules_check_distance_opt: Some(20.0),
+		record_replays: false,
+		fixed_time_step_opt: Some(0.02)
+	}
+}
+
+pub fn resources(map: MapSave) -> ResolvedResources {
+	ResolvedResources {
+		map,
+		course_opt: None,
+		boat_types: HashMap::from([(BOAT_TYPE_NAME.to_owned(), Rc::new(boat_type()))])
+	}
+}
+
+/// Save with no clients using `settings()` and a fixed wind seed
+pub fn save() -> SimulationSave {
+	let mut save = SimulationSave::new("test_map".to_owned(), WindGeneratorSaveState::default(), None);
+	save.local_settings_opt = Some(settings());
+	save.wind_rng = Rng::new(1);
+	save
+}
@@ -0,0 +1,79 @@
+//! Interactive terminal setup wizard for creating new simulations
+
+use dialoguer::{Confirm, Input, MultiSelect, Password, Select};
+use crate::{prelude::*, resource_interface::{ResourceType, ResourceRef}};
+
+/// Walks the user through creating a new simulation and saves it
+pub fn new_sim_wizard() -> Result<(), String> {
+	// Map
+	let map_names = ResourceType::Map.list()?;
+	if map_names.is_empty() {
+		return Err("There are no maps in the resources folder".to_owned());
+	}
+	let map_index = to_string_err(Select::new()
+		.with_prompt("Map")
+		.items(&map_names)
+		.default(0)
+		.interact())?;
+	let map_name = map_names[map_index].clone();
+	// Boat types
+	let boat_type_names = ResourceType::BoatType.list()?;
+	let boat_type_indices = to_string_err(MultiSelect::new()
+		.with_prompt("Allowed boat types (space to select, none selected allows all)")
+		.items(&boat_type_names)
+		.interact())?;
+	let boat_types: Vec<String> = boat_type_indices.into_iter().map(|i| boat_type_names[i].clone()).collect();
+	// Wind
+	let wind = match to_string_err(Confirm::new()
+		.with_prompt("Use default wind settings?")
+		.default(true)
+		.interact())? {
+		true => WindGeneratorSaveState::default(),
+		false => wind_prompt()?
+	};
+	// Password
+	let password_raw = to_string_err(Password::new()
+		.with_prompt("Password (leave empty for none)")
+		.allow_empty_password(true)
+		.interact())?;
+	let password = match password_raw.is_empty() {
+		true => None,
+		false => Some(password_raw)
+	};
+	// Name
+	let existing_sims = ResourceType::Simulation.list()?;
+	let sim_name: String = to_string_err(Input::new()
+		.with_prompt("Simulation name")
+		.validate_with(|name: &String| -> Result<(), &str> {
+			match (name.is_empty(), existing_sims.contains(name)) {
+				(true, _) => Err("Name cannot be empty"),
+				(false, true) => Err("A simulation with this name already exists"),
+				(false, false) => Ok(())
+			}
+		})
+		.interact_text())?;
+	let mut save = SimulationSave::new(map_name, wind, password);
+	save.boat_types = boat_types.into_iter().map(ResourceRef::new).collect();
+	resource_interface::save_simulation(&sim_name, &save)?;
+	println!("Created simulation \"{}\"", sim_name);
+	Ok(())
+}
+
+fn wind_prompt() -> Result<WindGeneratorSaveState, String> {
+	let default = WindGeneratorSaveState::default();
+	let float_prompt = |prompt: &str, default: Float| -> Result<Float, String> {
+		to_string_err(Input::<Float>::new()
+			.with_prompt(prompt)
+			.default(default)
+			.interact_text())
+	};
+	let speed_average = float_prompt("Average wind speed (m/s)", default.speed_average)?;
+	Ok(WindGeneratorSaveState {
+		speed_average,
+		max_gust: float_prompt("Maximum gust (m/s)", default.max_gust)?,
+		max_speed_variation: float_prompt("Maximum speed variation (m/s^2)", default.max_speed_variation)?,
+		max_direction_variation: float_prompt("Maximum direction variation (degrees/s^2)", default.max_direction_variation)?,
+		speed: speed_average,
+		direction: float_prompt("Initial direction (degrees CCW from east, direction the wind blows towards)", default.direction)?
+	})
+}